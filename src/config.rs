@@ -0,0 +1,187 @@
+//! Reading git's configuration files: the user's global ones and the
+//! repository's own `config`, later files overriding earlier ones.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RitError};
+
+/// Parsed configuration: every `section[.subsection].name = value` line
+/// of the files read, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    /// Read the files in order, skipping any that do not exist.
+    pub fn load(paths: &[PathBuf]) -> Result<Config> {
+        let mut config = Config::default();
+        for path in paths {
+            match fs::read(path) {
+                Ok(data) => config.entries.extend(parse(&String::from_utf8_lossy(&data), path)?.entries),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The user's configuration files, lowest priority first.
+    pub fn global_paths() -> Vec<PathBuf> {
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let xdg = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+        let mut paths = Vec::new();
+        paths.extend(xdg.map(|dir| dir.join("git/config")));
+        paths.extend(home.map(|home| home.join(".gitconfig")));
+        paths
+    }
+
+    /// The configuration in effect for the repository at `git_dir`: the
+    /// user's global files overlaid with its own `config`.
+    pub fn for_git_dir(git_dir: &Path) -> Result<Config> {
+        let mut paths = Config::global_paths();
+        paths.push(git_dir.join("config"));
+        Config::load(&paths)
+    }
+
+    /// The last value set for `key` (e.g. `core.quotePath`). A key given
+    /// without `=` reads as "true".
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries.iter().rev().find(|(k, _)| *k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// `key` as a boolean: true/yes/on/1 or false/no/off/0/empty.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let Some(value) = self.get(key) else { return Ok(None) };
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(Some(true)),
+            "false" | "no" | "off" | "0" | "" => Ok(Some(false)),
+            _ => Err(RitError::InvalidArgument(format!("bad boolean config value '{value}' for '{key}'"))),
+        }
+    }
+}
+
+fn parse(text: &str, path: &Path) -> Result<Config> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let bad = || RitError::InvalidArgument(format!("bad config line {} in file {}", number + 1, path.display()));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix('[') {
+            // [section] or [section "subsection"]
+            let end = rest.find(']').ok_or_else(bad)?;
+            let header = &rest[..end];
+            section = match header.split_once(' ') {
+                Some((name, sub)) => {
+                    let sub = sub.trim().strip_prefix('"').and_then(|s| s.strip_suffix('"')).ok_or_else(bad)?;
+                    format!("{}.{}", name.to_ascii_lowercase(), sub.replace("\\\"", "\"").replace("\\\\", "\\"))
+                }
+                None => header.to_ascii_lowercase(),
+            };
+            continue;
+        }
+
+        if section.is_empty() {
+            return Err(bad());
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), parse_value(value).ok_or_else(bad)?),
+            None => (line, "true".to_string()),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(bad());
+        }
+        entries.push((format!("{section}.{}", name.to_ascii_lowercase()), value));
+    }
+
+    Ok(Config { entries })
+}
+
+// Strip comments and quotes and resolve escapes in a value
+fn parse_value(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut quoted = false;
+    // Unquoted trailing whitespace is dropped, but not whitespace inside quotes
+    let mut pending_space = String::new();
+    let mut chars = raw.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'b' => '\u{8}',
+                    c @ ('"' | '\\') => c,
+                    _ => return None,
+                });
+                continue;
+            }
+            c if c.is_whitespace() && !quoted => {
+                pending_space.push(c);
+                continue;
+            }
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+
+    (!quoted).then_some(value)
+}
+
+// Section and variable names are case-insensitive; subsections are not
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => {
+            format!("{}{}{}", key[..first].to_ascii_lowercase(), &key[first..last], key[last..].to_ascii_lowercase())
+        }
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_git_config_syntax() {
+        let text = "# comment\n\
+                    [core]\n\
+                    \tquotePath = false ; trailing\n\
+                    \tbare\n\
+                    [remote \"Origin\"]\n\
+                    \turl = \"a b\"  \\t# x\n\
+                    [Core]\n\
+                    \tQuotePath = yes\n";
+        let config = parse(text, Path::new("config")).unwrap();
+        assert_eq!(config.get("core.quotepath"), Some("yes"));
+        assert_eq!(config.get_bool("core.quotePath").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get("remote.Origin.URL"), Some("a b  \t"));
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get("core.missing"), None);
+
+        assert!(parse("name = value\n", Path::new("config")).is_err());
+        assert!(parse("[core]\n\tx = \"open\n", Path::new("config")).is_err());
+        assert!(parse("[core]\n\tx = 2\n", Path::new("config")).unwrap().get_bool("core.x").is_err());
+    }
+}
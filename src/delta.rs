@@ -0,0 +1,252 @@
+//! Git's delta format: an object expressed as copies from a base object
+//! plus inserted literal bytes, used inside packs.
+
+use std::collections::HashMap;
+
+// Base bytes are indexed in blocks of this size; shorter matches are not
+// worth a copy instruction
+const BLOCK: usize = 16;
+// Largest copy a single instruction can express
+const MAX_COPY: usize = 0x10000;
+// Largest literal a single insert instruction can carry
+const MAX_INSERT: usize = 0x7f;
+/// Most memory reserved up front for an object of a size read from a pack
+/// or delta, which may be corrupt; larger objects grow as they are read.
+pub(crate) const MAX_RESERVE: usize = 16 << 20;
+
+/// Rebuild an object from `base` and a delta: the two sizes, then
+/// instructions to copy a range of the base or insert literal bytes.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    let mut pos = 0;
+    let base_size = read_varint(delta, &mut pos)?;
+    let result_size = read_varint(delta, &mut pos)?;
+    if base_size != base.len() as u64 {
+        return Err(format!("delta base is {} bytes, expected {base_size}", base.len()));
+    }
+
+    let mut result = Vec::with_capacity(result_size.min(MAX_RESERVE as u64) as usize);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // Copy: bits 0-3 select offset bytes, bits 4-6 size bytes
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..7 {
+                if op & (1 << i) != 0 {
+                    let byte = *delta.get(pos).ok_or("truncated delta")? as usize;
+                    pos += 1;
+                    if i < 4 {
+                        offset |= byte << (8 * i);
+                    } else {
+                        size |= byte << (8 * (i - 4));
+                    }
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = base.get(offset..offset + size).ok_or("delta copies past the end of its base")?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            let chunk = delta.get(pos..pos + op as usize).ok_or("truncated delta")?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            return Err("reserved delta opcode 0".to_string());
+        }
+        if result.len() as u64 > result_size {
+            return Err(format!("delta produces more than the {result_size} bytes it declares"));
+        }
+    }
+
+    if result.len() as u64 != result_size {
+        return Err(format!("delta produced {} bytes, expected {result_size}", result.len()));
+    }
+    Ok(result)
+}
+
+// Little-endian base-128 size at the start of a delta
+fn read_varint(data: &[u8], pos: &mut usize) -> std::result::Result<u64, String> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        if shift >= 64 {
+            return Err("delta size does not fit in 64 bits".to_string());
+        }
+        let byte = *data.get(*pos).ok_or("truncated delta")?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Express `target` as a delta against `base`. Returns `None` as soon as the
+/// delta would be larger than `max_size`.
+pub fn create_delta(base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, base.len() as u64);
+    write_varint(&mut delta, target.len() as u64);
+
+    // First occurrence of each aligned block of the base
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks.entry(&base[start..start + BLOCK]).or_insert(start);
+    }
+
+    let mut pos = 0;
+    let mut literal_start = 0;
+    while pos + BLOCK <= target.len() {
+        let Some(&base_start) = blocks.get(&target[pos..pos + BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+
+        // Extend the match forwards, then backwards over pending literals
+        let mut len = BLOCK;
+        while base_start + len < base.len() && pos + len < target.len() && base[base_start + len] == target[pos + len] {
+            len += 1;
+        }
+        let mut back = 0;
+        while pos - back > literal_start && base_start > back && base[base_start - back - 1] == target[pos - back - 1] {
+            back += 1;
+        }
+
+        push_insert(&mut delta, &target[literal_start..pos - back]);
+        push_copy(&mut delta, base_start - back, len + back);
+        pos += len;
+        literal_start = pos;
+
+        if delta.len() > max_size {
+            return None;
+        }
+    }
+    push_insert(&mut delta, &target[literal_start..]);
+
+    (delta.len() <= max_size).then_some(delta)
+}
+
+fn push_insert(delta: &mut Vec<u8>, mut literal: &[u8]) {
+    while !literal.is_empty() {
+        let n = literal.len().min(MAX_INSERT);
+        delta.push(n as u8);
+        delta.extend_from_slice(&literal[..n]);
+        literal = &literal[n..];
+    }
+}
+
+// Copy instruction: a flag byte saying which offset and size bytes follow,
+// then those bytes, little-endian with zero bytes left out
+fn push_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let n = len.min(MAX_COPY);
+        let size = if n == MAX_COPY { 0 } else { n };
+
+        let op_pos = delta.len();
+        let mut op = 0x80u8;
+        delta.push(op);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                delta.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = (size >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << (4 + i);
+                delta.push(byte);
+            }
+        }
+        delta[op_pos] = op;
+
+        offset += n;
+        len -= n;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bytes(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas_roundtrip() {
+        let base = random_bytes(3 * MAX_COPY, 1);
+        let mut edited = base.clone();
+        edited.splice(1000..1000, random_bytes(3 * MAX_INSERT, 2));
+        edited.drain(50_000..50_100);
+        edited.extend_from_slice(&base[..BLOCK * 3]);
+
+        let cases: [(&[u8], &[u8]); 6] = [
+            (b"", b""),
+            (b"", b"only literal bytes"),
+            (b"only a base", b""),
+            (&base, &base),
+            (&base, &edited),
+            (&edited, &base),
+        ];
+        for (base, target) in cases {
+            let delta = create_delta(base, target, usize::MAX).unwrap();
+            assert_eq!(apply_delta(base, &delta).unwrap(), target);
+        }
+
+        // Long matches become a few copies rather than literals
+        assert!(create_delta(&base, &edited, usize::MAX).unwrap().len() < 1000);
+        assert_eq!(create_delta(&base, &random_bytes(1000, 3), 100), None);
+    }
+
+    #[test]
+    fn applies_git_deltas() {
+        // Sizes 11 and 9, copy 5 bytes from offset 6, insert "!!!!"
+        let delta = [11, 9, 0x80 | 0x01 | 0x10, 6, 5, 4, b'!', b'!', b'!', b'!'];
+        assert_eq!(apply_delta(b"hello world", &delta).unwrap(), b"world!!!!");
+        // A copy with no size bytes copies 0x10000 bytes
+        let base = vec![7; 0x10000];
+        assert_eq!(apply_delta(&base, &[0x80, 0x80, 0x04, 0x80, 0x80, 0x04, 0x80]).unwrap(), base);
+
+        assert!(apply_delta(b"hello", &delta).is_err());
+        assert!(apply_delta(b"hello world", &[11, 5, 0x80 | 0x01 | 0x10, 8, 5]).is_err());
+        assert!(apply_delta(b"hello world", &[11, 1, 0]).is_err());
+        assert!(apply_delta(b"hello world", &[11, 9, 4, b'!']).is_err());
+        assert!(apply_delta(b"hello world", &[11]).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_sizes() {
+        // Continuation bytes past 64 bits
+        let mut delta = vec![0xff; 10];
+        delta.push(0);
+        assert!(apply_delta(b"", &delta).unwrap_err().contains("64 bits"));
+
+        // A result of 2^63 - 1 bytes is not reserved before the delta is read
+        let mut delta = vec![0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        delta.extend_from_slice(&[1, b'x']);
+        assert!(apply_delta(b"", &delta).is_err());
+        // Nor does a delta write past the size it declares
+        assert!(apply_delta(b"hello world", &[11, 2, 0x80 | 0x01 | 0x10, 0, 5]).is_err());
+    }
+}
@@ -0,0 +1,1168 @@
+//! Line diffs and the file level changes between trees, the index and the
+//! work tree, written out as unified diffs, diffstats or name lists.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::error::{Result, RitError};
+use crate::index::Index;
+use crate::object::{tree_order, ObjectKind, TreeEntry, MODE_GITLINK};
+use crate::odb::{ObjectDatabase, DEFAULT_ABBREV};
+use crate::oid::Oid;
+use crate::quote;
+use crate::rename;
+use crate::repository::Repository;
+use crate::status::{self, Change};
+use crate::worktree;
+
+/// How far into a file to look for a NUL byte when deciding it is binary,
+/// as git does.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Longest function name shown after a hunk header.
+const FUNCNAME_LEN: usize = 80;
+
+/// Histogram diff falls back to Myers for lines repeated more often than this.
+const MAX_CHAIN: usize = 64;
+
+/// Edits Myers searches for at least before settling for a diff that may not
+/// be the shortest.
+const MIN_MAX_COST: usize = 256;
+
+/// How to find the lines two versions of a file have in common.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// The shortest edit script.
+    #[default]
+    Myers,
+    /// Anchor on lines that occur exactly once on both sides first.
+    Patience,
+    /// Anchor on the least repeated lines first.
+    Histogram,
+}
+
+impl FromStr for Algorithm {
+    type Err = RitError;
+
+    fn from_str(s: &str) -> Result<Algorithm> {
+        match s {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => Err(RitError::InvalidArgument(format!("unknown diff algorithm '{s}'"))),
+        }
+    }
+}
+
+/// A run of lines replaced between the two versions: `old` lines were
+/// removed and `new` lines put in their place. Either may be empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Split `data` into lines, each keeping its newline; the last one may lack it.
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Whether `data` looks binary: a NUL byte near the start.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// The chunks that turn `old` into `new`, in order. Runs of changed lines
+/// that could be placed in several equivalent spots are slid as far down as
+/// possible, unless they can line up with a change on the other side.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Chunk> {
+    // Compare small IDs rather than line contents
+    let mut ids = HashMap::new();
+    let a = intern(&mut ids, old);
+    let b = intern(&mut ids, new);
+
+    let mut changes = Changes { old: vec![false; a.len()], new: vec![false; b.len()] };
+    match algorithm {
+        Algorithm::Myers => myers_discarding(&a, &b, 0..a.len(), 0..b.len(), &mut changes),
+        Algorithm::Patience => patience(&a, &b, 0..a.len(), 0..b.len(), &mut changes),
+        Algorithm::Histogram => histogram(&a, &b, 0..a.len(), 0..b.len(), &mut changes),
+    }
+    compact(&mut changes.old, &mut changes.new, &a);
+    compact(&mut changes.new, &mut changes.old, &b);
+
+    // Unchanged lines pair up one to one between the changes
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if changes.old.get(i) == Some(&true) || changes.new.get(j) == Some(&true) {
+            let (start_old, start_new) = (i, j);
+            while changes.old.get(i) == Some(&true) {
+                i += 1;
+            }
+            while changes.new.get(j) == Some(&true) {
+                j += 1;
+            }
+            chunks.push(Chunk { old: start_old..i, new: start_new..j });
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    chunks
+}
+
+fn intern<'a>(ids: &mut HashMap<&'a [u8], u32>, lines: &[&'a [u8]]) -> Vec<u32> {
+    lines
+        .iter()
+        .map(|line| {
+            let next = ids.len() as u32;
+            *ids.entry(*line).or_insert(next)
+        })
+        .collect()
+}
+
+// Which lines of each side are not part of the common subsequence
+struct Changes {
+    old: Vec<bool>,
+    new: Vec<bool>,
+}
+
+impl Changes {
+    fn mark(&mut self, old: Range<usize>, new: Range<usize>) {
+        self.old[old].fill(true);
+        self.new[new].fill(true);
+    }
+}
+
+// Shrink both ranges by the lines they start and end with in common
+fn trim(a: &[u32], b: &[u32], old: &mut Range<usize>, new: &mut Range<usize>) {
+    while old.start < old.end && new.start < new.end && a[old.start] == b[new.start] {
+        old.start += 1;
+        new.start += 1;
+    }
+    while old.start < old.end && new.start < new.end && a[old.end - 1] == b[new.end - 1] {
+        old.end -= 1;
+        new.end -= 1;
+    }
+}
+
+// Lines with no equal on the other side are changed whatever the diff, so
+// leave them out before running Myers; this keeps it fast on files that have
+// little in common
+fn myers_discarding(a: &[u32], b: &[u32], old: Range<usize>, new: Range<usize>, changes: &mut Changes) {
+    let in_old: HashSet<u32> = a[old.clone()].iter().copied().collect();
+    let in_new: HashSet<u32> = b[new.clone()].iter().copied().collect();
+    let (kept_old, dropped_old): (Vec<usize>, Vec<usize>) = old.partition(|&i| in_new.contains(&a[i]));
+    let (kept_new, dropped_new): (Vec<usize>, Vec<usize>) = new.partition(|&j| in_old.contains(&b[j]));
+
+    let reduced_a: Vec<u32> = kept_old.iter().map(|&i| a[i]).collect();
+    let reduced_b: Vec<u32> = kept_new.iter().map(|&j| b[j]).collect();
+    let max_cost = ((reduced_a.len() + reduced_b.len() + 3) as f64).sqrt() as usize;
+    let mut reduced = Changes { old: vec![false; reduced_a.len()], new: vec![false; reduced_b.len()] };
+    myers(&reduced_a, &reduced_b, 0..reduced_a.len(), 0..reduced_b.len(), max_cost.max(MIN_MAX_COST), &mut reduced);
+
+    for i in dropped_old.into_iter().chain(kept_old.iter().zip(&reduced.old).filter(|(_, &c)| c).map(|(&i, _)| i)) {
+        changes.old[i] = true;
+    }
+    for j in dropped_new.into_iter().chain(kept_new.iter().zip(&reduced.new).filter(|(_, &c)| c).map(|(&j, _)| j)) {
+        changes.new[j] = true;
+    }
+}
+
+// Divide and conquer on the middle snake, in linear space
+fn myers(a: &[u32], b: &[u32], mut old: Range<usize>, mut new: Range<usize>, max_cost: usize, changes: &mut Changes) {
+    trim(a, b, &mut old, &mut new);
+    if old.is_empty() || new.is_empty() {
+        changes.mark(old, new);
+        return;
+    }
+
+    match middle_snake(&a[old.clone()], &b[new.clone()], max_cost) {
+        Some((x, y)) => {
+            myers(a, b, old.start..old.start + x, new.start..new.start + y, max_cost, changes);
+            myers(a, b, old.start + x..old.end, new.start + y..new.end, max_cost, changes);
+        }
+        None => changes.mark(old, new),
+    }
+}
+
+// A point on a shortest edit path from the start of both sides to their
+// end, found by searching forward from the start and backward from the end
+// at once; `None` if the sides have nothing in common. After `max_cost`
+// edits the furthest point the forward search reached is taken instead.
+fn middle_snake(a: &[u32], b: &[u32], max_cost: usize) -> Option<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let len = 2 * max_d + 2;
+    // Furthest x reached on each diagonal k = x - y, forward and backward
+    let mut forward = vec![-1isize; len as usize];
+    let mut backward = vec![-1isize; len as usize];
+    forward[offset as usize + 1] = 0;
+    backward[offset as usize + 1] = 0;
+
+    let delta = n - m;
+    // With an odd delta the paths meet while extending forward
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let i = (offset + k1) as usize;
+            let mut x = if k1 == -d || (k1 != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k1;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            if x > n {
+                k1_end += 2;
+            } else if y > m {
+                k1_start += 2;
+            } else if front {
+                let j = offset + delta - k1;
+                if j >= 0 && j < len && backward[j as usize] != -1 && x >= n - backward[j as usize] {
+                    return Some((x as usize, y as usize));
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let i = (offset + k2) as usize;
+            let mut x = if k2 == -d || (k2 != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k2;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            if x > n {
+                k2_end += 2;
+            } else if y > m {
+                k2_start += 2;
+            } else if !front {
+                let j = offset + delta - k2;
+                if j >= 0 && j < len && forward[j as usize] != -1 {
+                    let x1 = forward[j as usize];
+                    let y1 = offset + x1 - j;
+                    if x1 >= n - x {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+
+        if d as usize >= max_cost {
+            return (-d + k1_start..=d - k1_end)
+                .step_by(2)
+                .map(|k| (forward[(offset + k) as usize], k))
+                .filter(|&(x, k)| x >= 0 && x <= n && x - k >= 0 && x - k <= m && x + x - k > 0 && x + x - k < n + m)
+                .max_by_key(|&(x, k)| x + x - k)
+                .map(|(x, k)| (x as usize, (x - k) as usize));
+        }
+    }
+    None
+}
+
+// Match up the lines that occur exactly once on each side, keep the longest
+// run of those matches that is in order on both, and diff between them
+fn patience(a: &[u32], b: &[u32], mut old: Range<usize>, mut new: Range<usize>, changes: &mut Changes) {
+    trim(a, b, &mut old, &mut new);
+    if old.is_empty() || new.is_empty() {
+        changes.mark(old, new);
+        return;
+    }
+
+    // Per line: occurrences and last position on each side
+    let mut counts: HashMap<u32, (usize, usize, usize, usize)> = HashMap::new();
+    for i in old.clone() {
+        let count = counts.entry(a[i]).or_default();
+        count.0 += 1;
+        count.1 = i;
+    }
+    for j in new.clone() {
+        if let Some(count) = counts.get_mut(&b[j]) {
+            count.2 += 1;
+            count.3 = j;
+        }
+    }
+    let mut unique: Vec<(usize, usize)> = counts
+        .values()
+        .filter(|&&(in_old, _, in_new, _)| in_old == 1 && in_new == 1)
+        .map(|&(_, i, _, j)| (i, j))
+        .collect();
+    if unique.is_empty() {
+        myers_discarding(a, b, old, new, changes);
+        return;
+    }
+    unique.sort_unstable();
+
+    let (mut i, mut j) = (old.start, new.start);
+    for (x, y) in longest_increasing(&unique) {
+        patience(a, b, i..x, j..y, changes);
+        (i, j) = (x + 1, y + 1);
+    }
+    patience(a, b, i..old.end, j..new.end, changes);
+}
+
+// The longest subsequence of `pairs` (sorted by their first element) that
+// also increases in the second, by patience sorting
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // Top of each pile, and for every pair the pair below it on the left pile
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (n, &(_, y)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&top| pairs[top].1 < y);
+        previous[n] = pile.checked_sub(1).map(|left| piles[left]);
+        if pile == piles.len() {
+            piles.push(n);
+        } else {
+            piles[pile] = n;
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut next = piles.last().copied();
+    while let Some(n) = next {
+        result.push(pairs[n]);
+        next = previous[n];
+    }
+    result.reverse();
+    result
+}
+
+// Find the longest common run around the least repeated line of `old`,
+// and diff on either side of it
+fn histogram(a: &[u32], b: &[u32], mut old: Range<usize>, mut new: Range<usize>, changes: &mut Changes) {
+    trim(a, b, &mut old, &mut new);
+    if old.is_empty() || new.is_empty() {
+        changes.mark(old, new);
+        return;
+    }
+
+    let mut positions: HashMap<u32, Vec<usize>> = HashMap::new();
+    for i in old.clone() {
+        positions.entry(a[i]).or_default().push(i);
+    }
+
+    // Best run so far: fewest occurrences of its rarest line, then longest
+    let mut best: Option<(usize, Range<usize>, Range<usize>)> = None;
+    let mut j = new.start;
+    while j < new.end {
+        let mut next = j + 1;
+        if let Some(found) = positions.get(&b[j]) {
+            let rarity = found.len();
+            if rarity <= MAX_CHAIN && best.as_ref().map_or(true, |(best_rarity, _, _)| rarity <= *best_rarity) {
+                for &i in found {
+                    let (mut start_old, mut start_new) = (i, j);
+                    while start_old > old.start && start_new > new.start && a[start_old - 1] == b[start_new - 1] {
+                        start_old -= 1;
+                        start_new -= 1;
+                    }
+                    let (mut end_old, mut end_new) = (i + 1, j + 1);
+                    while end_old < old.end && end_new < new.end && a[end_old] == b[end_new] {
+                        end_old += 1;
+                        end_new += 1;
+                    }
+                    let rarity = (start_old..end_old).map(|x| positions[&a[x]].len()).min().unwrap_or(rarity);
+                    let better = match &best {
+                        None => true,
+                        Some((best_rarity, best_old, _)) => {
+                            rarity < *best_rarity || (rarity == *best_rarity && end_old - start_old > best_old.len())
+                        }
+                    };
+                    if better {
+                        best = Some((rarity, start_old..end_old, start_new..end_new));
+                    }
+                    next = next.max(end_new);
+                }
+            }
+        }
+        j = next;
+    }
+
+    match best {
+        Some((_, run_old, run_new)) => {
+            histogram(a, b, old.start..run_old.start, new.start..run_new.start, changes);
+            histogram(a, b, run_old.end..old.end, run_new.end..new.end, changes);
+        }
+        None if positions.values().any(|found| found.len() > MAX_CHAIN) => myers_discarding(a, b, old, new, changes),
+        None => changes.mark(old, new),
+    }
+}
+
+// Slide each run of changed lines in `changed` up as far as it goes, then
+// down as far as it goes, and settle where it lines up with a change in
+// `other` if it passed one. `lines` are the line IDs of the changed side.
+fn compact(changed: &mut [bool], other: &mut [bool], lines: &[u32]) {
+    let n = changed.len();
+    let is_changed = |changed: &[bool], i: isize| i >= 0 && (i as usize) < changed.len() && changed[i as usize];
+
+    // Runs on both sides are visited in step: the unchanged lines between
+    // them pair up one to one
+    let mut group = Group::first(changed);
+    let mut other_group = Group::first(other);
+    loop {
+        if group.end != group.start {
+            let mut end_matching_other;
+            let mut earliest_end;
+            loop {
+                let size = group.end - group.start;
+                end_matching_other = None;
+
+                while group.start > 0 && lines[group.start - 1] == lines[group.end - 1] {
+                    group.start -= 1;
+                    group.end -= 1;
+                    changed[group.start] = true;
+                    changed[group.end] = false;
+                    while is_changed(changed, group.start as isize - 1) {
+                        group.start -= 1;
+                    }
+                    other_group.previous(other);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while group.end < n && lines[group.start] == lines[group.end] {
+                    changed[group.start] = false;
+                    changed[group.end] = true;
+                    group.start += 1;
+                    group.end += 1;
+                    while is_changed(changed, group.end as isize) {
+                        group.end += 1;
+                    }
+                    other_group.next(other);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+
+                // Sliding may have merged runs; go again until it settles
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end != earliest_end && end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    group.start -= 1;
+                    group.end -= 1;
+                    changed[group.start] = true;
+                    changed[group.end] = false;
+                    while is_changed(changed, group.start as isize - 1) {
+                        group.start -= 1;
+                    }
+                    other_group.previous(other);
+                }
+            }
+        }
+
+        if !group.next(changed) {
+            break;
+        }
+        other_group.next(other);
+    }
+}
+
+// A possibly empty run of changed lines, `start..end`, followed by an
+// unchanged line or the end
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Group {
+        let end = changed.iter().take_while(|&&c| c).count();
+        Group { start: 0, end }
+    }
+
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+}
+
+/// Write `chunks` as unified diff hunks with `context` unchanged lines
+/// around each change. Hunk headers name the closest line above the hunk
+/// that starts with a letter, `_` or `$`, as git does for C-like code.
+pub fn write_hunks(out: &mut Vec<u8>, old: &[&[u8]], new: &[&[u8]], chunks: &[Chunk], context: usize) {
+    let mut funcname: &[u8] = b"";
+    // Lines at or above this were searched for a function name already
+    let mut searched: Option<usize> = None;
+
+    let mut rest = chunks;
+    while let Some(first) = rest.first() {
+        // Changes close enough for their context to touch share a hunk
+        let mut count = 1;
+        while count < rest.len() && rest[count].old.start - rest[count - 1].old.end <= 2 * context {
+            count += 1;
+        }
+        let (hunk, remaining) = rest.split_at(count);
+        rest = remaining;
+        let last = &hunk[count - 1];
+
+        let old_start = first.old.start.saturating_sub(context);
+        let new_start = first.new.start.saturating_sub(context);
+        let old_end = (last.old.end + context).min(old.len());
+        let new_end = (last.new.end + context).min(new.len());
+
+        let mut line = old_start;
+        while line > 0 && searched.map_or(true, |searched| line - 1 > searched) {
+            line -= 1;
+            if let Some(name) = function_name(old[line]) {
+                funcname = name;
+                break;
+            }
+        }
+        searched = old_start.checked_sub(1);
+
+        out.extend_from_slice(b"@@ -");
+        out.extend_from_slice(range_header(old_start, old_end - old_start).as_bytes());
+        out.extend_from_slice(b" +");
+        out.extend_from_slice(range_header(new_start, new_end - new_start).as_bytes());
+        out.extend_from_slice(b" @@");
+        if !funcname.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(funcname);
+        }
+        out.push(b'\n');
+
+        let mut position = old_start;
+        for chunk in hunk {
+            for line in &old[position..chunk.old.start] {
+                write_line(out, b' ', line);
+            }
+            for line in &old[chunk.old.clone()] {
+                write_line(out, b'-', line);
+            }
+            for line in &new[chunk.new.clone()] {
+                write_line(out, b'+', line);
+            }
+            position = chunk.old.end;
+        }
+        for line in &old[position..old_end] {
+            write_line(out, b' ', line);
+        }
+    }
+}
+
+// "start,count" of a hunk header, 1-based; an empty range names the line
+// before it and a single line leaves out the count
+fn range_header(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{count}", start + 1),
+    }
+}
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+// The start of `line` if it looks like a function or other definition
+fn function_name(line: &[u8]) -> Option<&[u8]> {
+    let first = *line.first()?;
+    if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+        return None;
+    }
+    let line = &line[..line.len().min(FUNCNAME_LEN)];
+    let end = line.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |end| end + 1);
+    Some(&line[..end])
+}
+
+/// A path whose blob, mode or presence differs between two snapshots. Each
+/// side is a mode and object ID, `None` where the path does not exist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: Vec<u8>,
+    pub old: Option<(u32, Oid)>,
+    pub new: Option<(u32, Oid)>,
+    pub change: Change,
+    /// Where the old side came from, if it was another path.
+    pub rename: Option<Rename>,
+}
+
+impl FileChange {
+    /// The status letter of the raw and `--name-status` formats, with the
+    /// similarity for renames and copies, e.g. `M` or `R086`.
+    pub fn status(&self) -> String {
+        match &self.rename {
+            Some(rename) => format!("{}{:03}", if rename.copy { 'C' } else { 'R' }, rename.similarity()),
+            None => self.change.letter().to_string(),
+        }
+    }
+
+    /// The path of the old side.
+    pub fn old_path(&self) -> &[u8] {
+        self.rename.as_ref().map_or(&self.path, |rename| &rename.from)
+    }
+}
+
+/// The source of a renamed or copied file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rename {
+    pub from: Vec<u8>,
+    /// How alike the two sides are, out of [`rename::MAX_SCORE`].
+    pub score: u32,
+    /// Whether the source still exists.
+    pub copy: bool,
+}
+
+impl Rename {
+    /// The similarity as a percentage.
+    pub fn similarity(&self) -> u32 {
+        self.score * 100 / rename::MAX_SCORE
+    }
+}
+
+/// The paths that differ between two snapshots, sorted.
+pub fn changes(old: &BTreeMap<Vec<u8>, (u32, Oid)>, new: &BTreeMap<Vec<u8>, (u32, Oid)>) -> Vec<FileChange> {
+    let mut paths: Vec<&Vec<u8>> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let (old, new) = (old.get(path).copied(), new.get(path).copied());
+            let change = status::compare(old, new)?;
+            Some(FileChange { path: path.clone(), old, new, change, rename: None })
+        })
+        .collect()
+}
+
+/// What [`diff_trees`] reports.
+#[derive(Clone, Debug, Default)]
+pub struct TreeDiffOptions {
+    /// Descend into subtrees that differ rather than reporting them.
+    pub recursive: bool,
+    /// Report subtrees even when descending into them.
+    pub show_trees: bool,
+    /// Only compare these paths and what is below them; all if empty.
+    pub paths: Vec<Vec<u8>>,
+}
+
+/// The entries that differ between two trees (either may be absent, as for
+/// a root commit), in tree order. Subtrees with the same ID on both sides
+/// are skipped without being read. A path that is a tree on one side and
+/// not on the other is reported as a deletion and an addition.
+pub fn diff_trees(
+    odb: &ObjectDatabase,
+    old: Option<&Oid>,
+    new: Option<&Oid>,
+    options: &TreeDiffOptions,
+) -> Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    diff_subtrees(odb, old, new, b"", options, &mut changes)?;
+    Ok(changes)
+}
+
+fn diff_subtrees(
+    odb: &ObjectDatabase,
+    old: Option<&Oid>,
+    new: Option<&Oid>,
+    prefix: &[u8],
+    options: &TreeDiffOptions,
+    changes: &mut Vec<FileChange>,
+) -> Result<()> {
+    let read = |tree: Option<&Oid>| -> Result<Vec<TreeEntry>> {
+        Ok(match tree {
+            Some(oid) => odb.read_tree(oid)?.entries,
+            None => Vec::new(),
+        })
+    };
+    let (old_entries, new_entries) = (read(old)?, read(new)?);
+
+    // Both lists are in tree order, so walk them side by side
+    let (mut i, mut j) = (0, 0);
+    while i < old_entries.len() || j < new_entries.len() {
+        let order = match (old_entries.get(i), new_entries.get(j)) {
+            (Some(a), Some(b)) => tree_order(&a.name, a.is_tree(), &b.name, b.is_tree()),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        let (a, b) = match order {
+            Ordering::Less => (old_entries.get(i), None),
+            Ordering::Greater => (None, new_entries.get(j)),
+            Ordering::Equal => (old_entries.get(i), new_entries.get(j)),
+        };
+        i += usize::from(a.is_some());
+        j += usize::from(b.is_some());
+
+        let side = |entry: Option<&TreeEntry>| entry.map(|entry| (entry.mode, entry.oid));
+        if side(a) == side(b) {
+            continue;
+        }
+        let entry = a.or(b).unwrap();
+        let path = worktree::join_path(prefix, &entry.name);
+
+        // A filter matches the entry, or everything below it, or names
+        // something below it so that it has to be descended into
+        let matched = options.paths.is_empty()
+            || options.paths.iter().any(|spec| {
+                spec.is_empty() || *spec == path || (path.starts_with(spec) && path[spec.len()] == b'/')
+            });
+        let leads_to_match = entry.is_tree()
+            && options.paths.iter().any(|spec| {
+                spec.len() > path.len() && spec.starts_with(&path) && spec[path.len()] == b'/'
+            });
+        if !matched && !leads_to_match {
+            continue;
+        }
+
+        let descend = entry.is_tree() && options.recursive;
+        if !descend || options.show_trees {
+            let (old, new) = (side(a), side(b));
+            let change = status::compare(old, new).unwrap_or(Change::Modified);
+            changes.push(FileChange { path: path.clone(), old, new, change, rename: None });
+        }
+        if descend {
+            diff_subtrees(odb, a.map(|a| &a.oid), b.map(|b| &b.oid), &path, options, changes)?;
+        }
+    }
+    Ok(())
+}
+
+/// The merged (stage 0) entries of the index, by path.
+pub fn index_files(index: &Index) -> BTreeMap<Vec<u8>, (u32, Oid)> {
+    index.entries().iter().filter(|entry| entry.stage() == 0).map(|entry| (entry.path.clone(), (entry.mode, entry.oid))).collect()
+}
+
+/// The merged index entries as they are in the work tree: the mode and
+/// object ID each file has there, skipping those that no longer exist.
+/// Files whose stat data matches the index are not read.
+pub fn worktree_files(repo: &Repository, index: &Index) -> Result<BTreeMap<Vec<u8>, (u32, Oid)>> {
+    let work_tree = repo.require_work_tree()?;
+    let index_mtime = status::index_mtime(&repo.index_path())?;
+
+    let mut files = BTreeMap::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage() == 0) {
+        let file = worktree::work_path(work_tree, &entry.path);
+        let Some(meta) = fs::symlink_metadata(&file).ok().filter(|meta| !meta.is_dir()) else { continue };
+        // Submodules are not looked into
+        let side = if entry.mode == MODE_GITLINK || status::stat_clean(entry, &meta, index_mtime) {
+            (entry.mode, entry.oid)
+        } else {
+            (worktree::file_mode(&meta), Oid::hash(ObjectKind::Blob, &worktree::read_entry(&file, &meta)?))
+        };
+        files.insert(entry.path.clone(), side);
+    }
+    Ok(files)
+}
+
+/// The contents of one side of a change: the blob, or with `from_worktree`
+/// the file in the work tree. Missing sides are empty and submodules read as
+/// the commit they are at.
+pub fn read_side(repo: &Repository, path: &[u8], side: Option<(u32, Oid)>, from_worktree: bool) -> Result<Vec<u8>> {
+    match side {
+        None => Ok(Vec::new()),
+        Some((MODE_GITLINK, oid)) => Ok(format!("Subproject commit {oid}\n").into_bytes()),
+        Some(_) if from_worktree => {
+            let file = worktree::work_path(repo.require_work_tree()?, path);
+            worktree::read_entry(&file, &fs::symlink_metadata(&file)?)
+        }
+        Some((_, oid)) => Ok(repo.odb().read_raw(&oid)?.1),
+    }
+}
+
+/// Settings for [`write_patch`].
+#[derive(Clone, Copy, Debug)]
+pub struct PatchOptions {
+    pub algorithm: Algorithm,
+    /// Unchanged lines shown around each change.
+    pub context: usize,
+    /// Quote paths with bytes outside ASCII (`core.quotePath`).
+    pub quote_high: bool,
+}
+
+/// Write one change as a `diff --git` patch, given the contents of both
+/// sides. A file that became a symlink or the other way round is written as
+/// a deletion followed by an addition.
+pub fn write_patch(
+    out: &mut Vec<u8>,
+    odb: &ObjectDatabase,
+    change: &FileChange,
+    old_data: &[u8],
+    new_data: &[u8],
+    options: &PatchOptions,
+) -> Result<()> {
+    if change.change == Change::TypeChanged {
+        let deleted = FileChange { new: None, change: Change::Deleted, ..change.clone() };
+        let added = FileChange { old: None, change: Change::Added, ..change.clone() };
+        write_patch(out, odb, &deleted, old_data, b"", options)?;
+        return write_patch(out, odb, &added, b"", new_data, options);
+    }
+
+    let quote = |prefix: &[u8], path: &[u8]| quote::quote_path(&[prefix, path].concat(), options.quote_high);
+    let (old_name, new_name) = (quote(b"a/", change.old_path()), quote(b"b/", &change.path));
+    out.extend_from_slice(b"diff --git ");
+    out.extend_from_slice(&old_name);
+    out.push(b' ');
+    out.extend_from_slice(&new_name);
+    out.push(b'\n');
+
+    match (change.old, change.new) {
+        (None, Some((mode, _))) => out.extend_from_slice(format!("new file mode {mode:06o}\n").as_bytes()),
+        (Some((mode, _)), None) => out.extend_from_slice(format!("deleted file mode {mode:06o}\n").as_bytes()),
+        (Some((old_mode, _)), Some((new_mode, _))) if old_mode != new_mode => {
+            out.extend_from_slice(format!("old mode {old_mode:06o}\nnew mode {new_mode:06o}\n").as_bytes())
+        }
+        _ => {}
+    }
+    if let Some(rename) = &change.rename {
+        let kind = if rename.copy { "copy" } else { "rename" };
+        out.extend_from_slice(format!("similarity index {}%\n{kind} from ", rename.similarity()).as_bytes());
+        out.extend_from_slice(&quote(b"", &rename.from));
+        out.extend_from_slice(format!("\n{kind} to ").as_bytes());
+        out.extend_from_slice(&quote(b"", &change.path));
+        out.push(b'\n');
+    }
+
+    let old_oid = change.old.map_or(Oid::ZERO, |(_, oid)| oid);
+    let new_oid = change.new.map_or(Oid::ZERO, |(_, oid)| oid);
+    if old_oid == new_oid {
+        return Ok(());
+    }
+    let abbrev = |oid: &Oid| -> Result<String> {
+        if *oid == Oid::ZERO {
+            Ok(oid.to_hex()[..DEFAULT_ABBREV].to_string())
+        } else {
+            odb.abbreviate(oid, DEFAULT_ABBREV)
+        }
+    };
+    out.extend_from_slice(format!("index {}..{}", abbrev(&old_oid)?, abbrev(&new_oid)?).as_bytes());
+    if let (Some((old_mode, _)), Some((new_mode, _))) = (change.old, change.new) {
+        if old_mode == new_mode {
+            out.extend_from_slice(format!(" {old_mode:06o}").as_bytes());
+        }
+    }
+    out.push(b'\n');
+
+    let old_label = if change.old.is_some() { old_name } else { b"/dev/null".to_vec() };
+    let new_label = if change.new.is_some() { new_name } else { b"/dev/null".to_vec() };
+    if is_binary(old_data) || is_binary(new_data) {
+        out.extend_from_slice(b"Binary files ");
+        out.extend_from_slice(&old_label);
+        out.extend_from_slice(b" and ");
+        out.extend_from_slice(&new_label);
+        out.extend_from_slice(b" differ\n");
+        return Ok(());
+    }
+
+    let (old_lines, new_lines) = (lines(old_data), lines(new_data));
+    let chunks = diff_lines(&old_lines, &new_lines, options.algorithm);
+    if chunks.is_empty() {
+        return Ok(());
+    }
+    // A tab after a name with spaces keeps it apart from any timestamp
+    for (marker, label) in [(&b"--- "[..], old_label), (b"+++ ", new_label)] {
+        out.extend_from_slice(marker);
+        out.extend_from_slice(&label);
+        if label.contains(&b' ') {
+            out.push(b'\t');
+        }
+        out.push(b'\n');
+    }
+    write_hunks(out, &old_lines, &new_lines, &chunks, options.context);
+    Ok(())
+}
+
+/// One line of a diffstat.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatEntry {
+    /// The path as shown, already quoted.
+    pub name: Vec<u8>,
+    pub counts: StatCounts,
+}
+
+/// How much of a file changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatCounts {
+    Lines { added: usize, deleted: usize },
+    /// Sizes in bytes before and after.
+    Binary { old_size: usize, new_size: usize },
+    Unmerged,
+}
+
+impl StatCounts {
+    /// Count the lines added and removed between two versions of a file.
+    pub fn compute(old_data: &[u8], new_data: &[u8], algorithm: Algorithm) -> StatCounts {
+        if is_binary(old_data) || is_binary(new_data) {
+            return StatCounts::Binary { old_size: old_data.len(), new_size: new_data.len() };
+        }
+        let chunks = diff_lines(&lines(old_data), &lines(new_data), algorithm);
+        StatCounts::Lines {
+            added: chunks.iter().map(|chunk| chunk.new.len()).sum(),
+            deleted: chunks.iter().map(|chunk| chunk.old.len()).sum(),
+        }
+    }
+}
+
+/// Write a diffstat: a line per file with a `+`/`-` graph scaled to fit
+/// `width` columns, and a summary line.
+pub fn write_stat(out: &mut Vec<u8>, entries: &[StatEntry], width: usize) {
+    let mut max_change = 0;
+    let mut number_width = 0;
+    for entry in entries {
+        match entry.counts {
+            StatCounts::Lines { added, deleted } => max_change = max_change.max(added + deleted),
+            // Counts line up with "Bin"
+            StatCounts::Binary { .. } => number_width = 3,
+            StatCounts::Unmerged => {}
+        }
+    }
+    number_width = number_width.max(max_change.to_string().len());
+    let max_name = entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+
+    // The name gets up to 5/8 of the width and the graph the rest, less
+    // " | ", the count and a space either side
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = max_change;
+    let mut name_width = max_name;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width + number_width + 6 > width * 3 / 8 {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+    for entry in entries {
+        out.push(b' ');
+        let mut name = &entry.name[..];
+        if name.len() > name_width {
+            // Keep the end of the path, from a directory boundary if there is one
+            out.extend_from_slice(b"...");
+            let keep = name_width.saturating_sub(3);
+            name = &name[name.len() - keep..];
+            if let Some(slash) = name.iter().position(|&b| b == b'/') {
+                name = &name[slash..];
+            }
+            out.extend_from_slice(name);
+            out.resize(out.len() + keep - name.len(), b' ');
+        } else {
+            out.extend_from_slice(name);
+            out.resize(out.len() + name_width - name.len(), b' ');
+        }
+        out.extend_from_slice(b" |");
+
+        match entry.counts {
+            StatCounts::Unmerged => {
+                out.extend_from_slice(b" Unmerged\n");
+                continue;
+            }
+            StatCounts::Binary { old_size, new_size } => {
+                out.extend_from_slice(format!(" {:>number_width$}", "Bin").as_bytes());
+                if old_size != 0 || new_size != 0 {
+                    out.extend_from_slice(format!(" {old_size} -> {new_size} bytes").as_bytes());
+                }
+            }
+            StatCounts::Lines { added, deleted } => {
+                insertions += added;
+                deletions += deleted;
+                let total = added + deleted;
+                out.extend_from_slice(format!(" {total:>number_width$}").as_bytes());
+                if total > 0 {
+                    out.push(b' ');
+                }
+
+                let (mut plus, mut minus) = (added, deleted);
+                if graph_width <= max_change {
+                    let scale = |n: usize| if n == 0 { 0 } else { 1 + n * (graph_width - 1) / max_change };
+                    let mut scaled = scale(total);
+                    if scaled < 2 && added > 0 && deleted > 0 {
+                        scaled = 2;
+                    }
+                    if added < deleted {
+                        plus = scale(added);
+                        minus = scaled - plus;
+                    } else {
+                        minus = scale(deleted);
+                        plus = scaled - minus;
+                    }
+                }
+                out.resize(out.len() + plus, b'+');
+                out.resize(out.len() + minus, b'-');
+            }
+        }
+        out.push(b'\n');
+        files += 1;
+    }
+
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    if files == 0 {
+        out.extend_from_slice(b" 0 files changed\n");
+        return;
+    }
+    out.extend_from_slice(format!(" {files} file{} changed", plural(files)).as_bytes());
+    if insertions > 0 || deletions == 0 {
+        out.extend_from_slice(format!(", {insertions} insertion{}(+)", plural(insertions)).as_bytes());
+    }
+    if deletions > 0 || insertions == 0 {
+        out.extend_from_slice(format!(", {deletions} deletion{}(-)", plural(deletions)).as_bytes());
+    }
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram];
+
+    // Small texts over a few distinct lines, so that they share plenty
+    fn texts(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |n: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % n) as usize
+        };
+        (0..count)
+            .map(|_| (0..next(12)).flat_map(|_| [b"a\n", b"b\n", b"{\n", b"}\n", b"x\n"][next(5)].to_vec()).collect())
+            .collect()
+    }
+
+    // Length of the longest common subsequence, by dynamic programming
+    fn lcs(a: &[&[u8]], b: &[&[u8]]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+            }
+        }
+        table[0][0]
+    }
+
+    // Apply a unified diff from `write_hunks` to `old`
+    fn apply(old: &[&[u8]], patch: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut position = 0;
+        let mut previous = b' ';
+        for line in lines(patch) {
+            match line[0] {
+                b'@' => {
+                    // "@@ -start,count +start,count @@"
+                    let header = &line[4..];
+                    let end = header.iter().position(|&b| b == b' ').unwrap();
+                    let range = std::str::from_utf8(&header[..end]).unwrap();
+                    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+                    let start: usize = start.parse().unwrap();
+                    let until = if count == "0" { start } else { start - 1 };
+                    old[position..until].iter().for_each(|line| out.extend_from_slice(line));
+                    position = until;
+                }
+                b' ' | b'-' => {
+                    assert_eq!(old[position], &line[1..line.len() - usize::from(!old[position].ends_with(b"\n"))]);
+                    if line[0] == b' ' {
+                        out.extend_from_slice(old[position]);
+                    }
+                    position += 1;
+                }
+                b'+' => out.extend_from_slice(&line[1..]),
+                // An added last line without a newline
+                b'\\' if previous == b'+' => {
+                    out.pop();
+                }
+                b'\\' => {}
+                _ => panic!("unexpected patch line {line:?}"),
+            }
+            previous = line[0];
+        }
+        old[position..].iter().for_each(|line| out.extend_from_slice(line));
+        out
+    }
+
+    #[test]
+    fn myers_is_minimal() {
+        let texts = texts(200);
+        for pair in texts.chunks(2) {
+            let (old, new) = (lines(&pair[0]), lines(&pair[1]));
+            let chunks = diff_lines(&old, &new, Algorithm::Myers);
+            let edits: usize = chunks.iter().map(|chunk| chunk.old.len() + chunk.new.len()).sum();
+            assert_eq!(edits, old.len() + new.len() - 2 * lcs(&old, &new), "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn patches_apply() {
+        let mut texts = texts(200);
+        texts.push(b"a\nb".to_vec());
+        texts.push(b"a\nc".to_vec());
+        for pair in texts.chunks(2) {
+            let (old, new) = (lines(&pair[0]), lines(&pair[1]));
+            for algorithm in ALGORITHMS {
+                for context in [0, 1, 3] {
+                    let mut patch = Vec::new();
+                    write_hunks(&mut patch, &old, &new, &diff_lines(&old, &new, algorithm), context);
+                    assert_eq!(apply(&old, &patch), pair[1], "{algorithm:?} {old:?} -> {new:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn patience_and_histogram_anchors() {
+        // As git diff --diff-algorithm=patience and =histogram give them:
+        // patience anchors on the lines unique to both sides (a and b),
+        // histogram on the rarest line of the old side ({)
+        let (old, new) = (lines(b"c\na\nb\n{\n"), lines(b"x\n{\na\n{\nb\n"));
+        let chunk = |old: Range<usize>, new: Range<usize>| Chunk { old, new };
+        let patience = [chunk(0..1, 0..2), chunk(2..2, 3..4), chunk(3..4, 5..5)];
+        assert_eq!(diff_lines(&old, &new, Algorithm::Patience), patience);
+        assert_eq!(diff_lines(&old, &new, Algorithm::Histogram), [chunk(0..3, 0..1), chunk(4..4, 2..5)]);
+
+        let (old, new) = (lines(b"{\n}\nx\nc\n"), lines(b"c\n{\nc\nx\nb\n"));
+        let patience = [chunk(0..0, 0..1), chunk(1..2, 2..3), chunk(3..4, 4..5)];
+        assert_eq!(diff_lines(&old, &new, Algorithm::Patience), patience);
+        assert_eq!(diff_lines(&old, &new, Algorithm::Histogram), [chunk(0..3, 0..0), chunk(4..4, 1..5)]);
+    }
+}
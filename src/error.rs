@@ -0,0 +1,79 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::object::ObjectKind;
+use crate::oid::Oid;
+
+pub type Result<T, E = RitError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum RitError {
+    #[error("object {0} not found")]
+    ObjectNotFound(String),
+    #[error("object {oid} is corrupt: {reason}")]
+    CorruptObject { oid: Oid, reason: String },
+    /// An object body that does not parse; `ObjectDatabase` attaches the ID
+    /// and reports it as `CorruptObject`.
+    #[error("malformed object: {0}")]
+    MalformedObject(String),
+    #[error("{oid} is a {actual}, not a {expected}")]
+    WrongObjectType { oid: Oid, expected: ObjectKind, actual: ObjectKind },
+    #[error("short object ID {prefix} is ambiguous; candidates are:{}", format_candidates(.candidates))]
+    AmbiguousOid { prefix: String, candidates: Vec<(Oid, String)> },
+    #[error("invalid object id: {0}")]
+    InvalidOid(String),
+    #[error("not a rit repository (or any of the parent directories): {}", .0.display())]
+    NotARepository(PathBuf),
+    #[error("unable to create '{}': File exists; another rit process may be running", .0.display())]
+    Locked(PathBuf),
+    #[error("ambiguous argument '{0}': unknown revision or path not in the working tree")]
+    BadRevision(String),
+    #[error("{0}")]
+    RefUpdateRejected(String),
+    #[error("packfile {} is corrupt: {reason}", .path.display())]
+    CorruptPack { path: PathBuf, reason: String },
+    /// `fsck` found this many errors; they have already been reported.
+    #[error("fsck found {0} error{}", if *.0 == 1 { "" } else { "s" })]
+    FsckFailed(usize),
+    #[error("index file is corrupt: {0}")]
+    CorruptIndex(String),
+    #[error("reflog of {name} is corrupt: {reason}")]
+    CorruptReflog { name: String, reason: String },
+    /// No name or email is configured for the author or committer role.
+    #[error(
+        "{} identity unknown: set user.name and user.email in the config, or GIT_{0}_NAME and GIT_{0}_EMAIL",
+        .0.to_lowercase()
+    )]
+    UnknownIdentity(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// A request git refuses with "fatal:" and status 128 rather than a
+    /// usage error.
+    #[error("{0}")]
+    Fatal(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl RitError {
+    /// Process exit status for this error. Repository and lookup failures use
+    /// git's fatal code 128 and bad arguments its usage code 129; corruption
+    /// and I/O failures use the sysexits values so scripts can tell them apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RitError::ObjectNotFound(_) | RitError::NotARepository(_) | RitError::Locked(_) => 128,
+            RitError::AmbiguousOid { .. } | RitError::BadRevision(_) | RitError::RefUpdateRejected(_) => 128,
+            RitError::UnknownIdentity(_) | RitError::Fatal(_) => 128,
+            RitError::InvalidOid(_) | RitError::InvalidArgument(_) | RitError::WrongObjectType { .. } => 129,
+            RitError::CorruptObject { .. } | RitError::MalformedObject(_) | RitError::CorruptIndex(_) => 65,
+            RitError::CorruptPack { .. } | RitError::CorruptReflog { .. } | RitError::FsckFailed(_) => 65,
+            RitError::Io(_) => 74,
+        }
+    }
+}
+
+fn format_candidates(candidates: &[(Oid, String)]) -> String {
+    candidates.iter().map(|(oid, kind)| format!("\n  {oid} {kind}")).collect()
+}
@@ -0,0 +1,584 @@
+//! Consistency checks over the whole object database: every object hashes
+//! to its ID and is well formed, everything it links to exists, and refs,
+//! reflogs and the index point at objects that do.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::error::{Result, RitError};
+use crate::index::Index;
+use crate::object::{tree_order, ObjectKind, MODE_EXECUTABLE, MODE_FILE, MODE_GITLINK, MODE_SYMLINK, MODE_TREE};
+use crate::oid::Oid;
+use crate::pack::Pack;
+use crate::refs::HEAD;
+use crate::repository::Repository;
+
+/// How serious a finding is. Only errors make a check fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Not a problem, e.g. dangling objects or an unborn HEAD.
+    Info,
+}
+
+/// One line of the report, worded like git's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn error(message: String) -> Finding {
+        Finding { severity: Severity::Error, message }
+    }
+
+    fn info(message: String) -> Finding {
+        Finding { severity: Severity::Info, message }
+    }
+
+    // "error in tree <oid>: treeNotSorted: not properly sorted"
+    fn in_object(severity: Severity, kind: ObjectKind, oid: &Oid, id: &str, text: &str) -> Finding {
+        let level = if severity == Severity::Error { "error" } else { "warning" };
+        Finding { severity, message: format!("{level} in {} {oid}: {id}: {text}", kind.as_str()) }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// What `fsck` reports besides problems.
+#[derive(Clone, Copy, Debug)]
+pub struct FsckOptions {
+    /// Report unreachable objects that nothing else refers to.
+    pub dangling: bool,
+    /// Report every unreachable object instead of only the dangling ones.
+    pub unreachable: bool,
+}
+
+impl Default for FsckOptions {
+    fn default() -> Self {
+        FsckOptions { dangling: true, unreachable: false }
+    }
+}
+
+// A link from one object to another, with the type the source expects
+struct Link {
+    to: Oid,
+    kind: ObjectKind,
+}
+
+/// Check every loose and packed object, the links between them and the
+/// refs, reflogs and index entries pointing into the database. Problems are
+/// returned rather than raised; only failing to read the repository itself
+/// is an error.
+pub fn fsck(repo: &Repository, options: FsckOptions) -> Result<Vec<Finding>> {
+    let odb = repo.odb();
+    let mut findings = Vec::new();
+    let mut objects: HashMap<Oid, ObjectKind> = HashMap::new();
+    let mut links: HashMap<Oid, Vec<Link>> = HashMap::new();
+
+    let mut check = |oid: Oid, kind: ObjectKind, data: &[u8], findings: &mut Vec<Finding>| {
+        if objects.insert(oid, kind).is_none() {
+            links.insert(oid, check_object(&oid, kind, data, findings));
+        }
+    };
+
+    let mut loose = odb.loose_objects()?;
+    loose.sort();
+    for (oid, path) in loose {
+        match odb.read_raw(&oid) {
+            Ok((kind, data)) => {
+                let actual = Oid::hash(kind, &data);
+                if actual != oid {
+                    findings.push(Finding::error(format!("error: {actual}: hash-path mismatch, found at: {}", path.display())));
+                    continue;
+                }
+                check(oid, kind, &data, &mut findings);
+            }
+            Err(e) => findings.push(Finding::error(format!("error: {}: {e}", path.display()))),
+        }
+    }
+
+    let external = |base: &Oid| odb.read_raw(base);
+    for idx in odb.pack_indexes()? {
+        // Packs are opened here rather than through the database so that
+        // one with an unreadable index does not stop the others being checked
+        let pack = match Pack::open(&idx) {
+            Ok(pack) => pack,
+            Err(e) => {
+                findings.push(Finding::error(format!("error: {e}")));
+                continue;
+            }
+        };
+
+        // Checksums and CRCs first; a bad pack still has its readable
+        // objects checked one by one
+        let verified = match pack.verify(&external) {
+            Ok(_) => true,
+            Err(e) => {
+                findings.push(Finding::error(format!("error: {e}")));
+                false
+            }
+        };
+
+        for oid in pack.index().oids() {
+            match pack.read(&oid, &external) {
+                Ok(Some((kind, data))) => {
+                    if !verified && Oid::hash(kind, &data) != oid {
+                        findings.push(Finding::error(format!(
+                            "error: {oid}: hash mismatch in packfile {}",
+                            pack.path().display()
+                        )));
+                        continue;
+                    }
+                    check(oid, kind, &data, &mut findings);
+                }
+                Ok(None) => {}
+                Err(e) => findings.push(Finding::error(format!("error: {oid}: {e}"))),
+            }
+        }
+    }
+
+    // Broken links, each missing object reported once
+    let mut referenced = HashSet::new();
+    let mut missing = HashSet::new();
+    let mut sources: Vec<&Oid> = links.keys().collect();
+    sources.sort();
+    for from in sources {
+        for link in &links[from] {
+            referenced.insert(link.to);
+            match objects.get(&link.to) {
+                None => {
+                    findings.push(Finding::error(format!(
+                        "broken link from {:>7} {from}\n              to {:>7} {}",
+                        objects[from].as_str(),
+                        link.kind.as_str(),
+                        link.to
+                    )));
+                    if missing.insert(link.to) {
+                        findings.push(Finding::error(format!("missing {} {}", link.kind.as_str(), link.to)));
+                    }
+                }
+                Some(&actual) if actual != link.kind => findings.push(Finding::error(format!(
+                    "error: {} is a {actual}, not a {} (linked from {} {from})",
+                    link.to,
+                    link.kind,
+                    objects[from]
+                ))),
+                Some(_) => {}
+            }
+        }
+    }
+
+    let roots = check_roots(repo, &objects, &mut findings)?;
+
+    // Everything reachable from the roots through links to existing objects
+    let mut reachable: HashSet<Oid> = HashSet::new();
+    let mut pending = roots;
+    while let Some(oid) = pending.pop() {
+        if objects.contains_key(&oid) && reachable.insert(oid) {
+            pending.extend(links.get(&oid).into_iter().flatten().map(|link| link.to));
+        }
+    }
+
+    let mut unreachable: Vec<(&Oid, &ObjectKind)> = objects.iter().filter(|(oid, _)| !reachable.contains(oid)).collect();
+    unreachable.sort_by_key(|(oid, _)| **oid);
+    for (oid, kind) in unreachable {
+        if options.unreachable {
+            findings.push(Finding::info(format!("unreachable {} {oid}", kind.as_str())));
+        } else if options.dangling && !referenced.contains(oid) {
+            findings.push(Finding::info(format!("dangling {} {oid}", kind.as_str())));
+        }
+    }
+
+    Ok(findings)
+}
+
+// Check that HEAD, refs, reflogs and the index point at objects that exist,
+// returning those that do as the roots of reachability
+fn check_roots(repo: &Repository, objects: &HashMap<Oid, ObjectKind>, findings: &mut Vec<Finding>) -> Result<Vec<Oid>> {
+    let mut roots = Vec::new();
+    let mut names = vec![HEAD.to_string()];
+    let mut refs: Vec<(String, Oid)> = Vec::new();
+
+    match repo.refs().resolve(HEAD)? {
+        Some(oid) => refs.push((HEAD.to_string(), oid)),
+        None => {
+            let branch = repo.refs().resolve_name(HEAD)?;
+            let short = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+            findings.push(Finding::info(format!("notice: HEAD points to an unborn branch ({short})")));
+        }
+    }
+    for (name, oid) in repo.refs().list()? {
+        names.push(name.clone());
+        refs.push((name, oid));
+    }
+
+    for (name, oid) in refs {
+        if objects.contains_key(&oid) {
+            roots.push(oid);
+        } else {
+            findings.push(Finding::error(format!("error: {name}: invalid sha1 pointer {oid}")));
+        }
+    }
+
+    for name in &names {
+        let reflog = match repo.refs().read_reflog(name) {
+            Ok(reflog) => reflog,
+            Err(e @ RitError::CorruptReflog { .. }) => {
+                findings.push(Finding::error(format!("error: {e}")));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for entry in reflog {
+            for oid in [entry.old, entry.new] {
+                if oid == Oid::ZERO {
+                    continue;
+                }
+                if objects.contains_key(&oid) {
+                    roots.push(oid);
+                } else {
+                    findings.push(Finding::error(format!("error: {name}: invalid reflog entry {oid}")));
+                }
+            }
+        }
+    }
+
+    let index = match Index::load(&repo.index_path()) {
+        Ok(index) => index,
+        Err(e @ RitError::CorruptIndex(_)) => {
+            findings.push(Finding::error(format!("error: {e}")));
+            return Ok(roots);
+        }
+        Err(e) => return Err(e),
+    };
+    for entry in index.entries().iter().filter(|entry| entry.mode != MODE_GITLINK) {
+        if objects.contains_key(&entry.oid) {
+            roots.push(entry.oid);
+        } else {
+            findings.push(Finding::error(format!(
+                "error: {}: invalid sha1 pointer {} in index",
+                String::from_utf8_lossy(&entry.path),
+                entry.oid
+            )));
+        }
+    }
+
+    Ok(roots)
+}
+
+// Check an object's contents, returning the objects it links to
+fn check_object(oid: &Oid, kind: ObjectKind, data: &[u8], findings: &mut Vec<Finding>) -> Vec<Link> {
+    let result = match kind {
+        ObjectKind::Blob => Ok(Vec::new()),
+        ObjectKind::Tree => check_tree(oid, data, findings),
+        ObjectKind::Commit => check_commit(data),
+        ObjectKind::Tag => check_tag(data),
+    };
+    result.unwrap_or_else(|(id, text)| {
+        findings.push(Finding::in_object(Severity::Error, kind, oid, id, text));
+        Vec::new()
+    })
+}
+
+type Problem = (&'static str, &'static str);
+
+fn check_tree(oid: &Oid, mut data: &[u8], findings: &mut Vec<Finding>) -> Result<Vec<Link>, Problem> {
+    const BAD_TREE: Problem = ("badTree", "cannot be parsed as a tree");
+
+    let mut links = Vec::new();
+    let mut names: HashSet<&[u8]> = HashSet::new();
+    let mut previous: Option<(&[u8], bool)> = None;
+    let mut problems: Vec<(Severity, &str, &str)> = Vec::new();
+
+    while !data.is_empty() {
+        // "<mode> <name>\0<20 byte sha>"
+        let space = data.iter().position(|&b| b == b' ').ok_or(BAD_TREE)?;
+        let null = data.iter().position(|&b| b == 0).filter(|&null| null > space).ok_or(BAD_TREE)?;
+        let sha_end = null + 1 + Oid::LEN;
+        if data.len() < sha_end || space == 0 {
+            return Err(BAD_TREE);
+        }
+
+        let mode_text = &data[..space];
+        let mode = parse_mode(mode_text).ok_or(BAD_TREE)?;
+        let name = &data[space + 1..null];
+        let entry_oid = Oid::from_bytes(&data[null + 1..sha_end]).map_err(|_| BAD_TREE)?;
+        data = &data[sha_end..];
+
+        if mode_text[0] == b'0' {
+            problems.push((Severity::Warning, "zeroPaddedFilemode", "contains zero-padded file modes"));
+        }
+        if ![MODE_TREE, MODE_FILE, MODE_EXECUTABLE, MODE_SYMLINK, MODE_GITLINK].contains(&mode) {
+            problems.push((Severity::Warning, "badFilemode", "contains bad file modes"));
+        }
+        match name {
+            b"" => problems.push((Severity::Warning, "emptyName", "contains empty pathname")),
+            b"." => problems.push((Severity::Warning, "hasDot", "contains '.'")),
+            b".." => problems.push((Severity::Warning, "hasDotdot", "contains '..'")),
+            _ if name.eq_ignore_ascii_case(b".git") => problems.push((Severity::Warning, "hasDotgit", "contains '.git'")),
+            _ if name.contains(&b'/') => problems.push((Severity::Warning, "fullPathname", "contains full pathnames")),
+            _ => {}
+        }
+        if entry_oid == Oid::ZERO {
+            problems.push((Severity::Warning, "nullSha1", "contains entries pointing to null sha1"));
+        }
+
+        let is_tree = mode == MODE_TREE;
+        if !names.insert(name) {
+            problems.push((Severity::Error, "duplicateEntries", "contains duplicate file entries"));
+        } else if let Some((prev_name, prev_is_tree)) = previous {
+            if tree_order(prev_name, prev_is_tree, name, is_tree) != Ordering::Less {
+                problems.push((Severity::Error, "treeNotSorted", "not properly sorted"));
+            }
+        }
+        previous = Some((name, is_tree));
+
+        // Submodule commits live in another repository
+        if mode != MODE_GITLINK && entry_oid != Oid::ZERO {
+            let kind = if is_tree { ObjectKind::Tree } else { ObjectKind::Blob };
+            links.push(Link { to: entry_oid, kind });
+        }
+    }
+
+    // Each kind of problem is reported once per tree
+    let mut reported = HashSet::new();
+    for (severity, id, text) in problems {
+        if reported.insert(id) {
+            findings.push(Finding::in_object(severity, ObjectKind::Tree, oid, id, text));
+        }
+    }
+    Ok(links)
+}
+
+fn check_commit(data: &[u8]) -> Result<Vec<Link>, Problem> {
+    let mut headers = header_lines(data).into_iter().peekable();
+    let mut links = Vec::new();
+
+    let tree = headers
+        .next_if(|(key, _)| *key == b"tree")
+        .ok_or(("missingTree", "invalid format - expected 'tree' line"))?;
+    let tree = parse_oid(tree.1).ok_or(("badTreeSha1", "invalid 'tree' line format - bad sha1"))?;
+    links.push(Link { to: tree, kind: ObjectKind::Tree });
+
+    while let Some((_, value)) = headers.next_if(|(key, _)| *key == b"parent") {
+        let parent = parse_oid(value).ok_or(("badParentSha1", "invalid 'parent' line format - bad sha1"))?;
+        links.push(Link { to: parent, kind: ObjectKind::Commit });
+    }
+
+    let author = headers
+        .next_if(|(key, _)| *key == b"author")
+        .ok_or(("missingAuthor", "invalid format - expected 'author' line"))?;
+    check_ident(author.1)?;
+    let committer = headers
+        .next_if(|(key, _)| *key == b"committer")
+        .ok_or(("missingCommitter", "invalid format - expected 'committer' line"))?;
+    check_ident(committer.1)?;
+
+    Ok(links)
+}
+
+fn check_tag(data: &[u8]) -> Result<Vec<Link>, Problem> {
+    let mut headers = header_lines(data).into_iter().peekable();
+
+    let object = headers
+        .next_if(|(key, _)| *key == b"object")
+        .ok_or(("missingObject", "invalid format - expected 'object' line"))?;
+    let object = parse_oid(object.1).ok_or(("badObjectSha1", "invalid 'object' line format - bad sha1"))?;
+
+    let kind = headers
+        .next_if(|(key, _)| *key == b"type")
+        .ok_or(("missingTypeEntry", "invalid format - expected 'type' line"))?;
+    let kind = [ObjectKind::Blob, ObjectKind::Tree, ObjectKind::Commit, ObjectKind::Tag]
+        .into_iter()
+        .find(|candidate| candidate.as_str().as_bytes() == kind.1)
+        .ok_or(("badType", "invalid 'type' value"))?;
+
+    headers
+        .next_if(|(key, _)| *key == b"tag")
+        .ok_or(("missingTagEntry", "invalid format - expected 'tag' line"))?;
+    // Very old tags have no tagger
+    if let Some((_, tagger)) = headers.next_if(|(key, _)| *key == b"tagger") {
+        check_ident(tagger)?;
+    }
+
+    Ok(vec![Link { to: object, kind }])
+}
+
+// Header lines as (key, value) up to the blank line before the message,
+// skipping continuation lines of multi-line values like gpgsig
+fn header_lines(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    data.split(|&b| b == b'\n')
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.starts_with(b" "))
+        .map(|line| match line.iter().position(|&b| b == b' ') {
+            Some(space) => (&line[..space], &line[space + 1..]),
+            None => (line, &line[line.len()..]),
+        })
+        .collect()
+}
+
+fn parse_oid(hex: &[u8]) -> Option<Oid> {
+    // Uppercase IDs are not accepted in objects
+    if hex.iter().any(u8::is_ascii_uppercase) {
+        return None;
+    }
+    Oid::from_hex_bytes(hex).ok()
+}
+
+// An octal file mode
+fn parse_mode(text: &[u8]) -> Option<u32> {
+    if text.is_empty() || !text.iter().all(|b| (b'0'..=b'7').contains(b)) {
+        return None;
+    }
+    text.iter().try_fold(0u32, |mode, &digit| mode.checked_mul(8)?.checked_add(u32::from(digit - b'0')))
+}
+
+// "Name <email> <seconds> <+hhmm>"
+fn check_ident(ident: &[u8]) -> Result<(), Problem> {
+    let open = ident
+        .iter()
+        .position(|&b| b == b'<')
+        .ok_or(("missingEmail", "invalid author/committer line - missing email"))?;
+    if open > 0 && ident[open - 1] != b' ' {
+        return Err(("missingSpaceBeforeEmail", "invalid author/committer line - missing space before email"));
+    }
+    let close = open
+        + ident[open..]
+            .iter()
+            .position(|&b| b == b'>')
+            .ok_or(("badEmail", "invalid author/committer line - bad email"))?;
+    if ident[open + 1..close].contains(&b'<') {
+        return Err(("badEmail", "invalid author/committer line - bad email"));
+    }
+
+    let rest = ident[close + 1..]
+        .strip_prefix(b" ")
+        .ok_or(("missingSpaceBeforeDate", "invalid author/committer line - missing space before date"))?;
+    let (date, tz) = match rest.iter().position(|&b| b == b' ') {
+        Some(space) => (&rest[..space], &rest[space + 1..]),
+        None => (rest, &rest[rest.len()..]),
+    };
+    if date.is_empty() || !date.iter().all(u8::is_ascii_digit) {
+        return Err(("badDate", "invalid author/committer line - bad date"));
+    }
+    if tz.len() != 5 || !matches!(tz[0], b'+' | b'-') || !tz[1..].iter().all(u8::is_ascii_digit) {
+        return Err(("badTimezone", "invalid author/committer line - bad time zone"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::pack::{build_pack, write_pack_files, PackOptions};
+    use crate::repository::InitOptions;
+
+    fn commit(repo: &Repository, tree: &Oid, parents: &[Oid]) -> Oid {
+        let mut text = format!("tree {tree}\n");
+        for parent in parents {
+            text.push_str(&format!("parent {parent}\n"));
+        }
+        text.push_str("author A U Thor <author@example.com> 1112911993 +0000\n");
+        text.push_str("committer A U Thor <author@example.com> 1112911993 +0000\n\nmessage\n");
+        repo.odb().write_raw(ObjectKind::Commit, text.as_bytes()).unwrap()
+    }
+
+    fn tree(repo: &Repository, names: &[&str], oid: &Oid) -> Oid {
+        let mut data = Vec::new();
+        for name in names {
+            data.extend_from_slice(format!("100644 {name}\0").as_bytes());
+            data.extend_from_slice(oid.as_bytes());
+        }
+        repo.odb().write_raw(ObjectKind::Tree, &data).unwrap()
+    }
+
+    #[test]
+    fn reports_corruption_and_dangling_objects() {
+        let dir = std::env::temp_dir().join(format!("rit-fsck-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+        let messages = |findings: Vec<Finding>| -> Vec<String> { findings.into_iter().map(|f| f.message).collect() };
+
+        let options = FsckOptions::default();
+        assert_eq!(messages(fsck(&repo, options).unwrap()), ["notice: HEAD points to an unborn branch (main)"]);
+
+        let blob = repo.odb().write_raw(ObjectKind::Blob, b"content\n").unwrap();
+        let good = commit(&repo, &tree(&repo, &["file"], &blob), &[]);
+        repo.refs().update(HEAD, &good, None, "commit (initial)").unwrap();
+        assert_eq!(fsck(&repo, options).unwrap(), []);
+
+        let dangling = repo.odb().write_raw(ObjectKind::Blob, b"dangling\n").unwrap();
+        let unsorted = tree(&repo, &["b", "a"], &blob);
+        let missing_tree = Oid::hash(ObjectKind::Tree, b"missing");
+        let broken = commit(&repo, &missing_tree, &[good]);
+        repo.refs().update("refs/heads/broken", &broken, None, "branch").unwrap();
+        let gone = Oid::hash(ObjectKind::Commit, b"gone");
+        repo.refs().update("refs/heads/gone", &gone, None, "branch").unwrap();
+        let misplaced = Oid::hash(ObjectKind::Blob, b"misplaced");
+        let path = |oid: &Oid| dir.join(".git/objects").join(&oid.to_hex()[..2]).join(&oid.to_hex()[2..]);
+        fs::create_dir_all(path(&misplaced).parent().unwrap()).unwrap();
+        fs::copy(path(&blob), path(&misplaced)).unwrap();
+
+        let findings = fsck(&repo, options).unwrap();
+        let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+        let messages = messages(findings);
+        for expected in [
+            format!("error: {blob}: hash-path mismatch, found at: {}", path(&misplaced).display()),
+            format!("error in tree {unsorted}: treeNotSorted: not properly sorted"),
+            format!("broken link from  commit {broken}\n              to    tree {missing_tree}"),
+            format!("missing tree {missing_tree}"),
+            format!("error: refs/heads/gone: invalid sha1 pointer {gone}"),
+            format!("dangling blob {dangling}"),
+            format!("dangling tree {unsorted}"),
+        ] {
+            assert!(messages.contains(&expected), "{expected} not in {messages:#?}");
+        }
+        assert!(errors >= 5);
+        assert!(!messages.iter().any(|m| m.contains(&good.to_hex()) || m.contains(&format!("dangling commit {broken}"))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reports_unreadable_packs_and_entries() {
+        let dir = std::env::temp_dir().join(format!("rit-fsck-pack-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        let data: Vec<u8> = (0..100).flat_map(|n| format!("line {n}\n").into_bytes()).collect();
+        let packed = repo.odb().write_raw(ObjectKind::Blob, &data).unwrap();
+        let pack = build_pack(repo.odb(), &[(packed, Vec::new())], PackOptions::default()).unwrap();
+        let pack_dir = dir.join(".git/objects/pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        let path = write_pack_files(&pack_dir.join("pack"), &pack).unwrap();
+        fs::remove_file(repo.odb().object_path(&packed)).unwrap();
+
+        // The entry's size header runs past 64 bits, and another pack's
+        // index is not an index at all
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[12] |= 0x80;
+        bytes[13..23].fill(0xff);
+        fs::write(&path, &bytes).unwrap();
+        fs::write(pack_dir.join("pack-bad.idx"), "garbage").unwrap();
+        fs::write(pack_dir.join("pack-bad.pack"), "garbage").unwrap();
+
+        let findings = fsck(&repo, FsckOptions::default()).unwrap();
+        let errors: Vec<&str> =
+            findings.iter().filter(|f| f.severity == Severity::Error).map(|f| f.message.as_str()).collect();
+        let entry_error = format!("error: {packed}: ");
+        assert!(errors.iter().any(|m| m.starts_with(&entry_error) && m.contains("64 bits")), "{errors:#?}");
+        assert!(errors.iter().any(|m| m.contains("pack-bad.idx")), "{errors:#?}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
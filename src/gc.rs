@@ -0,0 +1,412 @@
+//! Housekeeping: packing loose objects and refs, and pruning objects that
+//! nothing refers to.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{Result, RitError};
+use crate::index::Index;
+use crate::object::{ObjectKind, MODE_GITLINK};
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+use crate::pack::{self, PackOptions};
+use crate::refs::{PackedRef, RefValue, ReflogEntry, Refs, HEAD};
+use crate::repository::Repository;
+use crate::revision;
+use crate::revwalk;
+
+/// Default grace period before unreachable objects are pruned.
+pub const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// What `repack` should do.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RepackOptions {
+    /// Pack every reachable object into one pack, not just loose ones.
+    pub all: bool,
+    /// Afterwards delete loose objects and, with `all`, packs made redundant.
+    pub delete_redundant: bool,
+    /// With `all` and `delete_redundant`, unreachable objects in deleted
+    /// packs modified after this time are kept as loose objects, so that
+    /// pruning can give them the same grace period as other loose objects.
+    pub keep_unreachable_since: Option<SystemTime>,
+    pub pack: PackOptions,
+}
+
+/// Objects that must be kept: everything reachable from refs, HEAD, reflogs
+/// and the index, and the HEADs, reflogs and indexes of linked worktrees,
+/// with the path each was found at. A ref or HEAD pointing at a missing
+/// object is an error, since packing without it would lose history.
+pub fn reachable_objects(repo: &Repository) -> Result<Vec<(Oid, Vec<u8>)>> {
+    let odb = repo.odb();
+    let mut tips: Vec<(String, Oid)> = Vec::new();
+    let mut logged: Vec<ReflogEntry> = Vec::new();
+    let mut indexes = vec![repo.index_path()];
+
+    if let Some(oid) = repo.refs().resolve(HEAD)? {
+        tips.push((HEAD.to_string(), oid));
+    }
+    logged.extend(repo.refs().read_reflog(HEAD)?);
+    for (name, oid) in repo.refs().list()? {
+        logged.extend(repo.refs().read_reflog(&name)?);
+        tips.push((name, oid));
+    }
+
+    // A linked worktree's HEAD is its own, but the branch it names is shared
+    for dir in linked_worktrees(repo)? {
+        let refs = Refs::new(&dir);
+        let head = match refs.read(HEAD)? {
+            Some(RefValue::Direct(oid)) => Some(oid),
+            Some(RefValue::Symbolic(target)) => repo.refs().resolve(&target)?,
+            None => None,
+        };
+        let name = dir.strip_prefix(repo.git_dir()).unwrap_or(&dir).join(HEAD);
+        tips.extend(head.map(|oid| (name.display().to_string(), oid)));
+        logged.extend(refs.read_reflog(HEAD)?);
+        indexes.push(dir.join("index"));
+    }
+
+    if let Some((name, _)) = tips.iter().find(|(_, oid)| !odb.exists(oid)) {
+        return Err(RitError::Fatal(format!("{name} does not point to a valid object")));
+    }
+    let mut roots: Vec<Oid> = tips.into_iter().map(|(_, oid)| oid).collect();
+    // Reflogs may mention objects that are long gone
+    let mentioned = logged.iter().flat_map(|entry| [entry.old, entry.new]);
+    roots.extend(mentioned.filter(|oid| *oid != Oid::ZERO && odb.exists(oid)));
+
+    let mut objects = revwalk::list_objects(repo, &roots, &[])?;
+
+    // Staged blobs are not reachable from any commit yet
+    let mut seen: HashSet<Oid> = objects.iter().map(|(oid, _)| *oid).collect();
+    for path in indexes {
+        for entry in Index::load(&path)?.entries() {
+            if entry.mode != MODE_GITLINK && seen.insert(entry.oid) && odb.exists(&entry.oid) {
+                objects.push((entry.oid, entry.path.clone()));
+            }
+        }
+    }
+    Ok(objects)
+}
+
+// The administrative directories of linked worktrees, `worktrees/<id>`
+// in the git directory
+fn linked_worktrees(repo: &Repository) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(repo.git_dir().join("worktrees")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.join(HEAD).is_file() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Pack reachable objects. Returns the new pack, or `None` if there was
+/// nothing to pack.
+pub fn repack(repo: &Repository, options: &RepackOptions) -> Result<Option<PathBuf>> {
+    let odb = repo.odb();
+    let pack_dir = odb.path().join("pack");
+    let reachable = reachable_objects(repo)?;
+
+    // Packs marked with a .keep file are left alone
+    let old_packs: Vec<_> = odb
+        .packs()?
+        .iter()
+        .filter(|p| !p.path().with_extension("keep").exists())
+        .collect();
+    let kept: HashSet<Oid> = odb
+        .packs()?
+        .iter()
+        .filter(|p| p.path().with_extension("keep").exists())
+        .flat_map(|p| p.index().oids())
+        .collect();
+
+    let objects: Vec<(Oid, Vec<u8>)> = reachable
+        .iter()
+        .filter(|(oid, _)| !kept.contains(oid))
+        .filter(|(oid, _)| options.all || !odb.packs().is_ok_and(|packs| packs.iter().any(|p| p.contains(oid))))
+        .cloned()
+        .collect();
+
+    let new_pack = if objects.is_empty() {
+        None
+    } else {
+        let data = pack::build_pack(odb, &objects, options.pack)?;
+        Some(pack::write_pack_files(&pack_dir.join("pack"), &data)?)
+    };
+
+    if options.delete_redundant {
+        if options.all {
+            let reachable: HashSet<Oid> = reachable.iter().map(|(oid, _)| *oid).collect();
+            for old in old_packs {
+                if Some(old.path()) == new_pack.as_deref() {
+                    continue;
+                }
+                if let Some(since) = options.keep_unreachable_since {
+                    loosen_unreachable(odb, old, &reachable, since)?;
+                }
+                for ext in ["idx", "pack"] {
+                    remove_if_exists(&old.path().with_extension(ext))?;
+                }
+            }
+        }
+        prune_packed(repo)?;
+    }
+
+    Ok(new_pack)
+}
+
+// Keep a deleted pack's unreachable objects around as loose objects dated
+// like the pack, unless the pack itself is older than `since`
+fn loosen_unreachable(
+    odb: &ObjectDatabase,
+    pack: &pack::Pack,
+    reachable: &HashSet<Oid>,
+    since: SystemTime,
+) -> Result<()> {
+    let modified = fs::metadata(pack.path())?.modified()?;
+    if modified < since {
+        return Ok(());
+    }
+
+    for oid in pack.index().oids().filter(|oid| !reachable.contains(oid)) {
+        let Some((kind, data)) = pack.read(&oid, &|base| odb.read_raw(base))? else { continue };
+        odb.write_loose(kind, &data)?;
+        File::options().write(true).open(odb.object_path(&oid))?.set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// Delete loose objects that are also in a pack. Returns how many were
+/// deleted.
+pub fn prune_packed(repo: &Repository) -> Result<usize> {
+    // Packs written since the database was opened are not in its cache
+    let odb = ObjectDatabase::new(repo.odb().path());
+    let packs = odb.packs()?;
+
+    let mut count = 0;
+    for (oid, path) in odb.loose_objects()? {
+        if packs.iter().any(|p| p.contains(&oid)) {
+            remove_if_exists(&path)?;
+            remove_empty_fanout(&path);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Delete unreachable loose objects last modified before `expire`, or with
+/// `dry_run` only find them. Returns the objects selected.
+pub fn prune(repo: &Repository, expire: SystemTime, dry_run: bool) -> Result<Vec<Oid>> {
+    let reachable: HashSet<Oid> = reachable_objects(repo)?.into_iter().map(|(oid, _)| oid).collect();
+
+    let mut pruned = Vec::new();
+    for (oid, path) in repo.odb().loose_objects()? {
+        if reachable.contains(&oid) || fs::metadata(&path)?.modified()? >= expire {
+            continue;
+        }
+        if !dry_run {
+            remove_if_exists(&path)?;
+            remove_empty_fanout(&path);
+        }
+        pruned.push(oid);
+    }
+    pruned.sort();
+    Ok(pruned)
+}
+
+/// Move every ref into `packed-refs`, recording what annotated tags peel to.
+pub fn pack_refs(repo: &Repository) -> Result<()> {
+    let mut packed = Vec::new();
+    for (name, oid) in repo.refs().list()? {
+        // Refs pointing at missing objects are left as they are
+        let peeled = match repo.odb().read_raw(&oid) {
+            Ok((ObjectKind::Tag, _)) => Some(revision::peel(repo, &oid, None)?),
+            Ok(_) => None,
+            Err(RitError::ObjectNotFound(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        packed.push(PackedRef { name, oid, peeled });
+    }
+
+    // Packed refs skipped above for a missing object stay packed
+    for old in repo.refs().packed_refs()? {
+        if !packed.iter().any(|r| r.name == old.name) {
+            packed.push(old);
+        }
+    }
+    packed.sort_by(|a, b| a.name.cmp(&b.name));
+    repo.refs().pack(&packed)
+}
+
+/// Run all housekeeping: pack refs, repack everything reachable into one
+/// pack, then prune unreachable loose objects older than `prune_expire`
+/// (if given).
+pub fn gc(repo: &Repository, prune_expire: Option<SystemTime>, pack: PackOptions) -> Result<()> {
+    pack_refs(repo)?;
+
+    let options = RepackOptions {
+        all: true,
+        delete_redundant: true,
+        keep_unreachable_since: Some(prune_expire.unwrap_or(UNIX_EPOCH)),
+        pack,
+    };
+    repack(repo, &options)?;
+
+    if let Some(expire) = prune_expire {
+        // Reopen so that the object database sees the new pack, not the
+        // ones repack deleted
+        let repo = Repository::open(repo.git_dir(), repo.work_tree().map(Path::to_path_buf))?;
+        prune(&repo, expire, false)?;
+    }
+    Ok(())
+}
+
+/// Parse an expiry date: `now`, `never`, `<n>.<unit>.ago` (seconds up to
+/// years), or Unix seconds. `never` is `None`.
+pub fn parse_expire(spec: &str) -> Result<Option<SystemTime>> {
+    let bad = || RitError::InvalidArgument(format!("malformed expiration date '{spec}'"));
+    let now = SystemTime::now();
+
+    match spec {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => return Ok(Some(now)),
+        _ => {}
+    }
+    if let Ok(secs) = spec.trim_start_matches('@').parse::<u64>() {
+        return Ok(Some(UNIX_EPOCH + Duration::from_secs(secs)));
+    }
+
+    // "2.weeks.ago", "3 days ago", "1.hour.ago"
+    let parts: Vec<&str> = spec.split(['.', ' ']).filter(|p| !p.is_empty()).collect();
+    let [n, unit, "ago"] = parts[..] else { return Err(bad()) };
+    let n: u64 = n.parse().map_err(|_| bad())?;
+    let unit_secs = match unit.trim_end_matches('s') {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => 60 * 60 * 24,
+        "week" => 60 * 60 * 24 * 7,
+        "month" => 60 * 60 * 24 * 30,
+        "year" => 60 * 60 * 24 * 365,
+        _ => return Err(bad()),
+    };
+    Ok(Some(now.checked_sub(Duration::from_secs(n * unit_secs)).unwrap_or(UNIX_EPOCH)))
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Remove an `objects/xx` directory once its last object is gone
+fn remove_empty_fanout(object_path: &Path) {
+    if let Some(dir) = object_path.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexEntry;
+    use crate::object::{Commit, Object, Signature, Tree};
+    use crate::repository::InitOptions;
+
+    #[test]
+    fn reflogs_need_not_be_utf8() {
+        let dir = std::env::temp_dir().join(format!("rit-gc-reflog-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        // A commit nothing but the reflog refers to, written with a Latin-1
+        // message as `git -c i18n.commitEncoding=ISO-8859-1 commit` does
+        let odb = repo.odb();
+        let tree = odb.write_object(&Object::Tree(Tree::default())).unwrap();
+        let ident = Signature::parse("A U Thor <author@example.com> 1700000000 +0000").unwrap();
+        let commit = Commit {
+            tree,
+            parents: Vec::new(),
+            author: ident.clone(),
+            committer: ident.clone(),
+            extra_headers: vec![("encoding".to_string(), b"ISO-8859-1".to_vec())],
+            message: b"caf\xe9\n".to_vec(),
+        };
+        let oid = odb.write_object(&Object::Commit(commit)).unwrap();
+        let line = [format!("{} {oid} {ident}\tcommit (initial): ", Oid::ZERO).as_bytes(), b"caf\xe9\n"].concat();
+        fs::create_dir_all(repo.git_dir().join("logs")).unwrap();
+        fs::write(repo.git_dir().join("logs").join(HEAD), line).unwrap();
+
+        let reachable = reachable_objects(&repo).unwrap();
+        assert!(reachable.iter().any(|(reached, _)| *reached == oid));
+        assert!(reachable.iter().any(|(reached, _)| *reached == tree));
+        assert_eq!(prune(&repo, SystemTime::now() + Duration::from_secs(60), true).unwrap(), Vec::new());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn linked_worktrees_keep_their_objects() {
+        let dir = std::env::temp_dir().join(format!("rit-gc-worktrees-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+        let odb = repo.odb();
+        let commit = |tree: &Oid, message: &str| {
+            let text = format!("tree {tree}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\n{message}\n");
+            odb.write_raw(ObjectKind::Commit, text.as_bytes()).unwrap()
+        };
+        let empty = odb.write_raw(ObjectKind::Tree, b"").unwrap();
+        repo.refs().update(HEAD, &commit(&empty, "main"), None, "commit (initial): main").unwrap();
+
+        // A worktree on a detached commit, with an older commit in its
+        // reflog, a reflog entry for an object long gone and a staged blob
+        let blob = odb.write_raw(ObjectKind::Blob, b"committed\n").unwrap();
+        let mut tree = b"100644 file\0".to_vec();
+        tree.extend_from_slice(blob.as_bytes());
+        let detached = commit(&odb.write_raw(ObjectKind::Tree, &tree).unwrap(), "detached");
+        let older = commit(&empty, "older");
+        let gone = Oid::hash(ObjectKind::Commit, b"gone");
+        let staged = odb.write_raw(ObjectKind::Blob, b"staged\n").unwrap();
+
+        let worktree = repo.git_dir().join("worktrees/wt");
+        fs::create_dir_all(worktree.join("logs")).unwrap();
+        fs::write(worktree.join(HEAD), format!("{detached}\n")).unwrap();
+        let log = format!("{gone} {older} A <a@b> 0 +0000\treset\n{older} {detached} A <a@b> 0 +0000\tcommit\n");
+        fs::write(worktree.join("logs").join(HEAD), log).unwrap();
+        let mut index = Index::default();
+        index.add(IndexEntry::from_metadata(b"staged".to_vec(), staged, 0o100644, &fs::metadata(&dir).unwrap()));
+        index.write(&worktree.join("index")).unwrap();
+
+        let reachable: HashSet<Oid> = reachable_objects(&repo).unwrap().into_iter().map(|(oid, _)| oid).collect();
+        for oid in [detached, blob, older, staged] {
+            assert!(reachable.contains(&oid), "{oid}");
+        }
+        gc(&repo, Some(SystemTime::now() + Duration::from_secs(60)), PackOptions::default()).unwrap();
+        let reopened = Repository::open(repo.git_dir(), None).unwrap();
+        for oid in [detached, blob, older, staged] {
+            reopened.odb().read_raw(&oid).unwrap();
+        }
+
+        // A branch or HEAD whose object is missing stops gc rather than
+        // losing what it pointed at
+        fs::write(repo.git_dir().join("refs/heads/broken"), format!("{gone}\n")).unwrap();
+        assert!(matches!(reachable_objects(&reopened), Err(RitError::Fatal(_))));
+        fs::remove_file(repo.git_dir().join("refs/heads/broken")).unwrap();
+        fs::write(worktree.join(HEAD), format!("{gone}\n")).unwrap();
+        assert!(matches!(reachable_objects(&reopened), Err(RitError::Fatal(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! ASCII history graph for `log --graph`.
+//!
+//! Each column ("lane") holds the commit expected next on that line of
+//! history. Lane `i` is drawn at character `2 * i`; a lane moving one
+//! column left or right between rows is drawn as `/` or `\` in the gap.
+
+use crate::oid::Oid;
+
+/// Graph text to print alongside one commit.
+pub struct GraphRow {
+    /// Prefix for the commit's first output line, with `*` in its lane.
+    pub commit: String,
+    /// Prefixes for the following lines, where a merge opens lanes for its
+    /// other parents or lanes join; printed on their own if the commit has
+    /// fewer lines.
+    pub edges: Vec<String>,
+    /// Prefix for any further lines.
+    pub padding: String,
+}
+
+#[derive(Default)]
+pub struct Graph {
+    lanes: Vec<Oid>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::default()
+    }
+
+    /// Prefix for a line between commits: every lane continues.
+    pub fn padding(&self) -> String {
+        vec!["|"; self.lanes.len()].join(" ")
+    }
+
+    /// Place the next commit, which must come after all of its children.
+    pub fn next(&mut self, oid: &Oid, parents: &[Oid]) -> GraphRow {
+        let idx = match self.lanes.iter().position(|lane| lane == oid) {
+            Some(idx) => idx,
+            None => {
+                self.lanes.push(*oid);
+                self.lanes.len() - 1
+            }
+        };
+
+        // Replace the commit's lane with its first parent, opening new lanes
+        // to the right for other parents not already on the graph
+        let mut lanes = Vec::new();
+        let mut moves = Vec::new();
+        for (i, lane) in self.lanes.iter().enumerate() {
+            if i != idx {
+                moves.push((i, lanes.len()));
+                lanes.push(*lane);
+                continue;
+            }
+            for (n, parent) in parents.iter().enumerate() {
+                if n == 0 || !self.lanes.contains(parent) && !lanes.contains(parent) {
+                    moves.push((i, lanes.len()));
+                    lanes.push(*parent);
+                }
+            }
+        }
+
+        // The commit line is as wide as the lanes it branches into
+        let width = 2 * self.lanes.len().max(lanes.len()) - 1;
+        let cells = |current: char| {
+            let cells: Vec<String> =
+                (0..self.lanes.len()).map(|i| if i == idx { current } else { '|' }.to_string()).collect();
+            format!("{:width$}", cells.join(" "))
+        };
+        let commit = cells('*');
+        // A parent already in a neighbouring lane gets an edge drawn into
+        // that lane, as in "|\|"
+        let mut edges = Vec::new();
+        let mut line: Vec<char> = self.padding().chars().collect();
+        for parent in &parents[parents.len().min(1)..] {
+            match self.lanes.iter().position(|lane| lane == parent) {
+                Some(j) if j == idx + 1 => line[2 * idx + 1] = '\\',
+                Some(j) if j + 1 == idx => line[2 * idx - 1] = '/',
+                _ => {}
+            }
+        }
+        if line.iter().any(|&c| c == '\\' || c == '/') {
+            edges.push(format!("{:width$}", line.into_iter().collect::<String>()));
+        }
+        edges.extend(render(&moves, width));
+
+        // Lanes waiting for the same commit join the leftmost one
+        while let Some((keep, dup)) = find_duplicate(&lanes) {
+            let moves: Vec<(usize, usize)> = (0..lanes.len())
+                .map(|i| match i {
+                    i if i == dup => (i, keep),
+                    i if i > dup => (i, i - 1),
+                    i => (i, i),
+                })
+                .collect();
+            edges.extend(render(&moves, width));
+            lanes.remove(dup);
+        }
+
+        // A root commit's lane ends, leaving a gap unless others move into it
+        let padding = if parents.is_empty() && edges.is_empty() {
+            cells(' ')
+        } else {
+            format!("{:width$}", vec!["|"; lanes.len()].join(" "))
+        };
+
+        self.lanes = lanes;
+        GraphRow { commit, edges, padding }
+    }
+}
+
+fn find_duplicate(lanes: &[Oid]) -> Option<(usize, usize)> {
+    for (j, lane) in lanes.iter().enumerate() {
+        if let Some(k) = lanes[..j].iter().position(|other| other == lane) {
+            return Some((k, j));
+        }
+    }
+    None
+}
+
+// Draw lanes moving from their old to their new column, one column per
+// line, padded to `width`
+fn render(moves: &[(usize, usize)], width: usize) -> Vec<String> {
+    let mut positions: Vec<usize> = moves.iter().map(|&(from, _)| from).collect();
+    let mut lines = Vec::new();
+
+    while positions.iter().zip(moves).any(|(pos, &(_, to))| *pos != to) {
+        let columns = 2 * positions.iter().chain(moves.iter().map(|(_, to)| to)).max().unwrap_or(&0) + 2;
+        let mut line = vec![' '; columns.max(width)];
+
+        for (pos, &(_, to)) in positions.iter_mut().zip(moves) {
+            if *pos == to {
+                line[2 * *pos] = '|';
+            } else if to < *pos {
+                line[2 * *pos - 1] = '/';
+                *pos -= 1;
+            } else {
+                line[2 * *pos + 1] = '\\';
+                *pos += 1;
+            }
+        }
+
+        line.truncate(columns.max(width));
+        let line: String = line.into_iter().collect();
+        lines.push(format!("{:width$}", line.trim_end()));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::ObjectKind;
+
+    #[test]
+    fn draws_a_merge_like_git() {
+        let oid = |name: &str| Oid::hash(ObjectKind::Commit, name.as_bytes());
+        // `git log --graph` order, each commit with its parents
+        let history = [("F", "M"), ("M", "E D"), ("D", "C"), ("C", "A"), ("E", "B"), ("B", "A"), ("A", "")];
+
+        let mut graph = Graph::new();
+        let mut lines = Vec::new();
+        for (name, parents) in history {
+            let parents: Vec<Oid> = parents.split_whitespace().map(oid).collect();
+            let row = graph.next(&oid(name), &parents);
+            lines.push(format!("{} {name}", row.commit));
+            lines.extend(row.edges.iter().map(|edge| format!("{edge} ")));
+        }
+        let expected = ["* F", "*   M", "|\\  ", "| * D", "| * C", "* | E", "* | B", "|/  ", "* A"];
+        assert_eq!(lines, expected);
+    }
+}
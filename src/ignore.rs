@@ -0,0 +1,568 @@
+//! Ignore rules: `.gitignore` and `.ritignore` files in the work tree,
+//! `info/exclude` in the git directory and the user's global excludes file,
+//! with git's pattern syntax and precedence.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::repository::Repository;
+
+/// Per-directory ignore files, read in this order so that `.ritignore`
+/// rules win over `.gitignore` ones in the same directory.
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".ritignore"];
+
+/// One line of an ignore file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    glob: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    // Matched against the path below `base` instead of just the last
+    // component, because the pattern contains a slash
+    anchored: bool,
+    // Directory of the file the pattern came from, with a trailing slash
+    // unless it is the top level
+    base: Vec<u8>,
+    /// File the pattern was read from, as shown by `check-ignore -v`.
+    pub source: String,
+    /// 1-based line number in `source`.
+    pub line: usize,
+    /// The pattern as written.
+    pub text: String,
+}
+
+impl Pattern {
+    /// Parse a line of an ignore file; `None` for blank lines and comments.
+    pub fn parse(line: &[u8], base: &[u8], source: &str, number: usize) -> Option<Pattern> {
+        if line.is_empty() || line[0] == b'#' {
+            return None;
+        }
+
+        // Trailing spaces are dropped unless escaped with a backslash
+        let mut end = line.len();
+        while end > 0 && line[end - 1] == b' ' && !(end >= 2 && line[end - 2] == b'\\') {
+            end -= 1;
+        }
+        let text = &line[..end];
+        if text.is_empty() {
+            return None;
+        }
+
+        let mut glob = text;
+        let negated = glob[0] == b'!';
+        if negated {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.ends_with(b"/");
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let anchored = glob.contains(&b'/');
+        if glob.starts_with(b"/") {
+            glob = &glob[1..];
+        }
+        if glob.is_empty() {
+            return None;
+        }
+
+        let mut base = base.to_vec();
+        if !base.is_empty() {
+            base.push(b'/');
+        }
+        Some(Pattern {
+            glob: glob.to_vec(),
+            negated,
+            dir_only,
+            anchored,
+            base,
+            source: source.to_string(),
+            line: number,
+            text: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    /// Whether the pattern starts with `!`, re-including what it matches.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Whether the pattern matches `path`, relative to the top of the work
+    /// tree.
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            match path.strip_prefix(&self.base[..]) {
+                Some(rest) => wildmatch(&self.glob, rest),
+                None => false,
+            }
+        } else {
+            let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+            wildmatch(&self.glob, name)
+        }
+    }
+}
+
+/// All the ignore rules that apply to one work tree. Ignore files in
+/// subdirectories are read as paths below them are checked.
+pub struct Ignore {
+    root: PathBuf,
+    global: Vec<Pattern>,
+    info: Vec<Pattern>,
+    // Patterns of each directory's ignore files, keyed by directory path
+    dirs: HashMap<Vec<u8>, Vec<Pattern>>,
+}
+
+impl Ignore {
+    /// Rules for the directory tree at `root`: its ignore files, the
+    /// `info/exclude` file of `git_dir` if given, and the global excludes
+    /// file (`core.excludesFile`, by default `$XDG_CONFIG_HOME/git/ignore`).
+    pub fn new(root: &Path, git_dir: Option<&Path>, config: &Config) -> Result<Ignore> {
+        let global = match config.get("core.excludesFile") {
+            Some(path) => Some(expand_home(path)),
+            None => env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+                .map(|dir| dir.join("git/ignore")),
+        };
+        let global = match global {
+            Some(path) => read_patterns(&path, b"", &path.display().to_string())?,
+            None => Vec::new(),
+        };
+
+        let info = match git_dir {
+            Some(git_dir) => {
+                let path = git_dir.join("info/exclude");
+                let source = path.strip_prefix(root).unwrap_or(&path).display().to_string();
+                read_patterns(&path, b"", &source)?
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Ignore { root: root.to_path_buf(), global, info, dirs: HashMap::new() })
+    }
+
+    /// Rules for a repository's work tree.
+    pub fn for_repo(repo: &Repository) -> Result<Ignore> {
+        Ignore::new(repo.require_work_tree()?, Some(repo.git_dir()), &repo.config()?)
+    }
+
+    /// The top of the directory tree the rules apply to.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The pattern deciding whether `path` (`/`-separated, relative to the
+    /// root) is ignored: the last matching pattern from the most specific
+    /// source. A negated pattern means the path is explicitly not ignored.
+    /// Everything inside an ignored directory is ignored, whatever patterns
+    /// say about it.
+    pub fn matching(&mut self, path: &[u8], is_dir: bool) -> Result<Option<&Pattern>> {
+        let slashes: Vec<usize> = path.iter().enumerate().filter(|(_, &b)| b == b'/').map(|(i, _)| i).collect();
+        self.load(b"")?;
+        for &slash in &slashes {
+            self.load(&path[..slash])?;
+        }
+
+        for &slash in &slashes {
+            if let Some(pattern) = self.find(&path[..slash], true) {
+                if !pattern.negated {
+                    return Ok(Some(pattern));
+                }
+            }
+        }
+        Ok(self.find(path, is_dir))
+    }
+
+    /// Whether `path` is ignored.
+    pub fn is_ignored(&mut self, path: &[u8], is_dir: bool) -> Result<bool> {
+        Ok(self.matching(path, is_dir)?.is_some_and(|pattern| !pattern.negated))
+    }
+
+    // Deeper ignore files take precedence, then info/exclude, then the
+    // global file; within a file the last matching line wins
+    fn find(&self, path: &[u8], is_dir: bool) -> Option<&Pattern> {
+        let mut dirs: Vec<&[u8]> = path.iter().enumerate().filter(|(_, &b)| b == b'/').map(|(i, _)| &path[..i]).collect();
+        dirs.insert(0, b"");
+
+        let per_dir = dirs.into_iter().rev().filter_map(|dir| self.dirs.get(dir));
+        per_dir
+            .chain([&self.info, &self.global])
+            .find_map(|patterns| patterns.iter().rev().find(|p| p.matches(path, is_dir)))
+    }
+
+    fn load(&mut self, dir: &[u8]) -> Result<()> {
+        if self.dirs.contains_key(dir) {
+            return Ok(());
+        }
+
+        let mut patterns = Vec::new();
+        for name in IGNORE_FILES {
+            let source = if dir.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{name}", String::from_utf8_lossy(dir))
+            };
+            let path = crate::worktree::work_path(&self.root, dir).join(name);
+            patterns.extend(read_patterns(&path, dir, &source)?);
+        }
+        self.dirs.insert(dir.to_vec(), patterns);
+        Ok(())
+    }
+}
+
+fn read_patterns(path: &Path, base: &[u8], source: &str) -> Result<Vec<Pattern>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    // Tolerates a byte order mark and CRLF line endings
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+
+    let lines = data.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    Ok(lines.enumerate().filter_map(|(i, line)| Pattern::parse(line, base, source, i + 1)).collect())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Match `text` against a glob the way git does for paths: `*`, `?` and
+/// `[...]` never match `/`, while `**` between slashes (or at either end)
+/// matches any number of directories.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    dowild(pattern, text) == Wild::Match
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Wild {
+    Match,
+    NoMatch,
+    // No later starting point can match either
+    AbortAll,
+    // Only a `**` further out can still match
+    AbortToStarStar,
+}
+
+// A port of git's dowild() with WM_PATHNAME; bytes past the end read as NUL
+fn dowild(p: &[u8], text: &[u8]) -> Wild {
+    let at = |s: &[u8], i: usize| s.get(i).copied().unwrap_or(0);
+    let (mut pi, mut ti) = (0, 0);
+
+    while pi < p.len() {
+        let mut p_ch = p[pi];
+        let t_ch = at(text, ti);
+        if t_ch == 0 && p_ch != b'*' {
+            return Wild::AbortAll;
+        }
+
+        match p_ch {
+            b'?' => {
+                if t_ch == b'/' {
+                    return Wild::NoMatch;
+                }
+            }
+            b'*' => {
+                pi += 1;
+                let match_slash = if at(p, pi) == b'*' {
+                    let first = pi - 1;
+                    while at(p, pi) == b'*' {
+                        pi += 1;
+                    }
+                    let after = at(p, pi);
+                    if (first == 0 || p[first - 1] == b'/') && (after == 0 || after == b'/') {
+                        // "**/" also matches no directory at all
+                        if after == b'/' && dowild(&p[pi + 1..], &text[ti..]) == Wild::Match {
+                            return Wild::Match;
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                };
+
+                if pi == p.len() {
+                    // A trailing star matches the rest of this component only
+                    if !match_slash && text[ti..].contains(&b'/') {
+                        return Wild::NoMatch;
+                    }
+                    return Wild::Match;
+                }
+                if !match_slash && p[pi] == b'/' {
+                    let Some(slash) = text[ti..].iter().position(|&b| b == b'/') else { return Wild::NoMatch };
+                    // Both slashes are consumed below
+                    ti += slash;
+                } else {
+                    while ti < text.len() {
+                        let matched = dowild(&p[pi..], &text[ti..]);
+                        if matched != Wild::NoMatch {
+                            if !match_slash || matched != Wild::AbortToStarStar {
+                                return matched;
+                            }
+                        } else if !match_slash && text[ti] == b'/' {
+                            return Wild::AbortToStarStar;
+                        }
+                        ti += 1;
+                    }
+                    return Wild::AbortAll;
+                }
+            }
+            b'[' => {
+                pi += 1;
+                p_ch = at(p, pi);
+                if p_ch == b'^' {
+                    p_ch = b'!';
+                }
+                let negated = p_ch == b'!';
+                if negated {
+                    pi += 1;
+                    p_ch = at(p, pi);
+                }
+
+                let mut prev_ch = 0;
+                let mut matched = false;
+                loop {
+                    if p_ch == 0 {
+                        return Wild::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        pi += 1;
+                        p_ch = at(p, pi);
+                        if p_ch == 0 {
+                            return Wild::AbortAll;
+                        }
+                        matched |= t_ch == p_ch;
+                    } else if p_ch == b'-' && prev_ch != 0 && at(p, pi + 1) != 0 && at(p, pi + 1) != b']' {
+                        pi += 1;
+                        p_ch = at(p, pi);
+                        if p_ch == b'\\' {
+                            pi += 1;
+                            p_ch = at(p, pi);
+                            if p_ch == 0 {
+                                return Wild::AbortAll;
+                            }
+                        }
+                        matched |= t_ch <= p_ch && t_ch >= prev_ch;
+                        p_ch = 0;
+                    } else if p_ch == b'[' && at(p, pi + 1) == b':' {
+                        // [:class:]
+                        let start = pi + 2;
+                        let Some(len) = p[start..].iter().position(|&b| b == b']') else { return Wild::AbortAll };
+                        let class = &p[start..start + len];
+                        let Some(class) = class.strip_suffix(b":") else {
+                            // Not a class after all: treat "[" as a literal
+                            matched |= t_ch == b'[';
+                            prev_ch = p_ch;
+                            pi += 1;
+                            p_ch = at(p, pi);
+                            if p_ch == b']' {
+                                break;
+                            }
+                            continue;
+                        };
+                        matched |= match class {
+                            b"alnum" => t_ch.is_ascii_alphanumeric(),
+                            b"alpha" => t_ch.is_ascii_alphabetic(),
+                            b"blank" => t_ch == b' ' || t_ch == b'\t',
+                            b"cntrl" => t_ch.is_ascii_control(),
+                            b"digit" => t_ch.is_ascii_digit(),
+                            b"graph" => t_ch.is_ascii_graphic(),
+                            b"lower" => t_ch.is_ascii_lowercase(),
+                            b"print" => t_ch.is_ascii_graphic() || t_ch == b' ',
+                            b"punct" => t_ch.is_ascii_punctuation(),
+                            b"space" => t_ch.is_ascii_whitespace() || t_ch == 0x0b,
+                            b"upper" => t_ch.is_ascii_uppercase(),
+                            b"xdigit" => t_ch.is_ascii_hexdigit(),
+                            _ => return Wild::AbortAll,
+                        };
+                        pi = start + len;
+                        p_ch = 0;
+                    } else {
+                        matched |= t_ch == p_ch;
+                    }
+
+                    prev_ch = p_ch;
+                    pi += 1;
+                    p_ch = at(p, pi);
+                    if p_ch == b']' {
+                        break;
+                    }
+                }
+                if matched == negated || t_ch == b'/' {
+                    return Wild::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    pi += 1;
+                    p_ch = at(p, pi);
+                }
+                if t_ch != p_ch {
+                    return Wild::NoMatch;
+                }
+            }
+        }
+
+        pi += 1;
+        ti += 1;
+    }
+
+    if ti < text.len() {
+        Wild::NoMatch
+    } else {
+        Wild::Match
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildmatch_like_git() {
+        // (text, pattern, matches) from git's t3070-wildmatch.sh, with
+        // WM_PATHNAME as ignore rules use it
+        let cases: &[(&str, &str, bool)] = &[
+            ("foo", "foo", true),
+            ("foo", "bar", false),
+            ("foo", "???", true),
+            ("foo", "??", false),
+            ("foo", "*", true),
+            ("foo", "f*", true),
+            ("foo", "*f", false),
+            ("foobar", "*ob*a*r*", true),
+            ("aaaaaaabababab", "*ab", true),
+            ("foo*", "foo\\*", true),
+            ("foobar", "foo\\*bar", false),
+            ("f\\oo", "f\\\\oo", true),
+            // Stars and question marks stop at slashes; ** crosses them
+            ("foo/bar", "*", false),
+            ("foo/bar", "foo?bar", false),
+            ("foo/baz/bar", "foo*bar", false),
+            ("foo/baz/bar", "foo**bar", false),
+            ("foobazbar", "foo**bar", true),
+            ("foo/baz/bar", "foo/**/bar", true),
+            ("foo/baz/bar", "foo/**/**/bar", true),
+            ("foo/b/a/z/bar", "foo/**/bar", true),
+            ("foo/bar", "foo/**/bar", true),
+            ("foo/bar", "foo/**/**/bar", true),
+            ("foo", "**/foo", true),
+            ("XXX/foo", "**/foo", true),
+            ("bar/baz/foo", "**/foo", true),
+            ("bar/baz/foo", "*/foo", false),
+            ("foo/bar/baz", "**/bar*", false),
+            ("deep/foo/bar/baz", "**/bar/*", true),
+            ("deep/foo/bar/baz/", "**/bar/*", false),
+            ("deep/foo/bar/baz/", "**/bar/**", true),
+            ("deep/foo/bar", "**/bar/*", false),
+            ("deep/foo/bar/", "**/bar/**", true),
+            ("foo/bar/baz", "**/bar**", false),
+            ("foo/bar/baz/x", "*/bar/**", true),
+            ("deep/foo/bar/baz/x", "*/bar/**", false),
+            ("deep/foo/bar/baz/x", "**/bar/*/*", true),
+            ("foo", "foo/**", false),
+            // Character classes
+            ("ball", "*[al]?", true),
+            ("ten", "[ten]", false),
+            ("ten", "**[!te]", true),
+            ("ten", "**[!ten]", false),
+            ("ten", "t[a-g]n", true),
+            ("ten", "t[!a-g]n", false),
+            ("ton", "t[!a-g]n", true),
+            ("ton", "t[^a-g]n", true),
+            ("a]b", "a[]]b", true),
+            ("a-b", "a[]-]b", true),
+            ("a]b", "a[]-]b", true),
+            ("aab", "a[]-]b", false),
+            ("aab", "a[]a-]b", true),
+            ("]", "]", true),
+            ("foo/bar", "foo[/]bar", false),
+            ("foo/bar", "foo[^a-z]bar", false),
+            ("foo-bar", "f[^eiu][^eiu][^eiu][^eiu][^eiu]r", true),
+            ("a1B", "[[:alpha:]][[:digit:]][[:upper:]]", true),
+            ("a", "[[:digit:][:upper:][:space:]]", false),
+            ("A", "[[:digit:][:upper:][:space:]]", true),
+            ("1", "[[:digit:][:upper:][:space:]]", true),
+            ("1", "[[:digit:][:upper:][:spaci:]]", false),
+            (" ", "[[:digit:][:upper:][:space:]]", true),
+            (".", "[[:digit:][:upper:][:space:]]", false),
+            (".", "[[:digit:][:punct:][:space:]]", true),
+            ("5", "[[:xdigit:]]", true),
+            ("f", "[[:xdigit:]]", true),
+            ("g", "[[:xdigit:]]", false),
+            ("a", "[a", false),
+        ];
+        for &(text, pattern, expected) in cases {
+            assert_eq!(wildmatch(pattern.as_bytes(), text.as_bytes()), expected, "{pattern:?} on {text:?}");
+        }
+    }
+
+    #[test]
+    fn patterns_follow_gitignore_rules() {
+        let pattern =
+            |line: &str, base: &str| Pattern::parse(line.as_bytes(), base.as_bytes(), ".gitignore", 1).unwrap();
+        let cases: &[(&str, &str, &str, bool, bool)] = &[
+            // (pattern, directory of its file, path, is a directory, matches)
+            ("*.o", "", "a/b/x.o", false, true),
+            ("build/", "", "build", true, true),
+            ("build/", "", "build", false, false),
+            ("build/", "", "src/build", true, true),
+            ("/x", "", "x", false, true),
+            ("/x", "", "a/x", false, false),
+            ("a/x", "", "b/a/x", false, false),
+            ("x/y", "sub", "sub/x/y", false, true),
+            ("x/y", "sub", "x/y", false, false),
+            ("doc/**/*.txt", "", "doc/a/b/c.txt", false, true),
+            ("trailing  ", "", "trailing", false, true),
+            ("escaped\\ ", "", "escaped ", false, true),
+            ("\\#hash", "", "#hash", false, true),
+            ("\\!bang", "", "!bang", false, true),
+        ];
+        for &(line, base, path, is_dir, expected) in cases {
+            assert_eq!(pattern(line, base).matches(path.as_bytes(), is_dir), expected, "{line:?} on {path:?}");
+        }
+        assert!(Pattern::parse(b"# comment", b"", ".gitignore", 1).is_none());
+        assert!(pattern("!keep", "").is_negated());
+    }
+
+    #[test]
+    fn negation_and_directories() {
+        let root = std::env::temp_dir().join(format!("rit-ignore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n!keep.log\nout/\n").unwrap();
+        fs::write(root.join("logs/.gitignore"), "!debug.log\n").unwrap();
+        fs::write(root.join("config"), format!("[core]\n\texcludesFile = {}\n", root.join("none").display())).unwrap();
+        let config = Config::load(&[root.join("config")]).unwrap();
+        let mut ignore = Ignore::new(&root, None, &config).unwrap();
+
+        let cases: &[(&str, bool, bool)] = &[
+            ("a.log", false, true),
+            ("keep.log", false, false),
+            ("logs/a.log", false, true),
+            // A deeper ignore file wins
+            ("logs/debug.log", false, false),
+            ("out", true, true),
+            ("out", false, false),
+            // Nothing inside an ignored directory can be re-included
+            ("out/keep.log", false, true),
+        ];
+        for &(path, is_dir, expected) in cases {
+            assert_eq!(ignore.is_ignored(path.as_bytes(), is_dir).unwrap(), expected, "{path}");
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,534 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+
+use crate::error::{Result, RitError};
+use crate::lockfile::Lockfile;
+use crate::object::{Object, TreeBuilder, TreeEntry, MODE_TREE};
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+const HEADER_LEN: usize = 12;
+// Fixed-size part of an entry: ten 32-bit stat fields, the OID and flags
+const ENTRY_FIXED_LEN: usize = 62;
+
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const NAME_MASK: u16 = 0x0fff;
+
+/// Extended flag of a path recorded with `add -N`: it is known, but its
+/// content is not staged yet.
+pub const INTENT_TO_ADD: u16 = 0x2000;
+/// Extended flag of a path left out of a sparse checkout.
+pub const SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_FLAGS: u16 = INTENT_TO_ADD | SKIP_WORKTREE;
+
+// Extensions giving offsets into the file, which are recomputed rather
+// than kept
+const OFFSET_EXTENSIONS: [&[u8; 4]; 2] = [b"EOIE", b"IEOT"];
+
+/// One staged file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub oid: Oid,
+    /// Stage and assume-valid bits; the name length is derived from `path`.
+    pub flags: u16,
+    /// `INTENT_TO_ADD` and `SKIP_WORKTREE`, which need a version 3 index.
+    pub extended_flags: u16,
+    /// Path relative to the work tree, `/`-separated.
+    pub path: Vec<u8>,
+}
+
+impl IndexEntry {
+    /// Build a stage 0 entry from a file's stat data.
+    pub fn from_metadata(path: Vec<u8>, oid: Oid, mode: u32, meta: &Metadata) -> IndexEntry {
+        // The on-disk format only has room for the low 32 bits
+        IndexEntry {
+            ctime: meta.ctime() as u32,
+            ctime_nsec: meta.ctime_nsec() as u32,
+            mtime: meta.mtime() as u32,
+            mtime_nsec: meta.mtime_nsec() as u32,
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            mode,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+            oid,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    /// Whether a file's stat data is what was recorded when it was staged,
+    /// meaning its contents can be assumed unchanged without rehashing.
+    pub fn stat_matches(&self, meta: &Metadata) -> bool {
+        self.mtime == meta.mtime() as u32
+            && self.mtime_nsec == meta.mtime_nsec() as u32
+            && self.ctime == meta.ctime() as u32
+            && self.ctime_nsec == meta.ctime_nsec() as u32
+            && self.size == meta.size() as u32
+            && self.ino == meta.ino() as u32
+            && self.dev == meta.dev() as u32
+            && self.uid == meta.uid()
+            && self.gid == meta.gid()
+    }
+
+    /// Merge stage: 0 for normal entries, 1-3 for conflicts.
+    pub fn stage(&self) -> u16 {
+        (self.flags & FLAG_STAGE_MASK) >> FLAG_STAGE_SHIFT
+    }
+
+    /// Whether the path was recorded with `add -N` and has no staged content.
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & INTENT_TO_ADD != 0
+    }
+
+    /// Whether the path is outside a sparse checkout, so that its work tree
+    /// file is not looked at.
+    pub fn is_skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE != 0
+    }
+
+    fn key(&self) -> (&[u8], u16) {
+        (&self.path, self.stage())
+    }
+}
+
+/// The staging area, stored in `.git/index` (version 2, or 3 when an entry
+/// has extended flags).
+#[derive(Clone, Debug, Default)]
+pub struct Index {
+    /// Sorted by path, then stage.
+    entries: Vec<IndexEntry>,
+    /// Optional extensions (caches such as the cached trees), by signature,
+    /// written back as read until an entry changes.
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Index {
+    /// Read the index at `path`; a missing file is an empty index.
+    pub fn load(path: &Path) -> Result<Index> {
+        match fs::read(path) {
+            Ok(data) => Index::parse(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Index::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parse a version 2 or 3 index. Optional extensions are kept as they
+    /// are; required ones, such as a split index's `link`, are not supported.
+    pub fn parse(data: &[u8]) -> Result<Index> {
+        if data.len() < HEADER_LEN + Oid::LEN {
+            return Err(corrupt("file too short"));
+        }
+
+        let (body, checksum) = data.split_at(data.len() - Oid::LEN);
+        if Sha1::digest(body).as_slice() != checksum {
+            return Err(corrupt("checksum mismatch"));
+        }
+
+        if &body[..4] != SIGNATURE {
+            return Err(corrupt("bad signature"));
+        }
+        let version = read_u32(body, 4)?;
+        if version != 2 && version != 3 {
+            return Err(corrupt(&format!("unsupported version {version}")));
+        }
+        let count = read_u32(body, 8)? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = HEADER_LEN;
+        for _ in 0..count {
+            let start = pos;
+            let field = |i: usize| read_u32(body, start + i * 4);
+
+            let oid_start = start + 40;
+            let oid = Oid::from_bytes(body.get(oid_start..oid_start + Oid::LEN).ok_or_else(|| corrupt("truncated entry"))?)?;
+            let flags = read_u16(body, start + 60)?;
+
+            pos = start + ENTRY_FIXED_LEN;
+            let mut extended_flags = 0;
+            if flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    return Err(corrupt("extended flags in a version 2 index"));
+                }
+                extended_flags = read_u16(body, pos)?;
+                if extended_flags & !EXTENDED_FLAGS != 0 {
+                    return Err(corrupt(&format!("unknown extended flags {extended_flags:#06x}")));
+                }
+                pos += 2;
+            }
+
+            let name_len = body
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(|| corrupt("unterminated path"))?;
+            let path = body[pos..pos + name_len].to_vec();
+
+            // Entries are NUL-padded to a multiple of 8 bytes
+            let entry_len = pos + name_len - start;
+            pos = start + (entry_len + 8) / 8 * 8;
+
+            entries.push(IndexEntry {
+                ctime: field(0)?,
+                ctime_nsec: field(1)?,
+                mtime: field(2)?,
+                mtime_nsec: field(3)?,
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                oid,
+                flags: flags & !(FLAG_EXTENDED | NAME_MASK),
+                extended_flags,
+                path,
+            });
+        }
+
+        if pos > body.len() {
+            return Err(corrupt("truncated entry"));
+        }
+
+        let mut extensions = Vec::new();
+        while pos < body.len() {
+            let truncated = || corrupt("truncated extension");
+            let signature: [u8; 4] = body.get(pos..pos + 4).ok_or_else(truncated)?.try_into().unwrap();
+            let size = read_u32(body, pos + 4)? as usize;
+            let data = body.get(pos + 8..).and_then(|rest| rest.get(..size)).ok_or_else(truncated)?;
+            pos += 8 + size;
+
+            // An extension whose name starts with a capital letter is an
+            // optional cache; any other changes what the entries mean
+            if !signature[0].is_ascii_uppercase() {
+                return Err(corrupt(&format!(
+                    "unsupported required extension '{}'",
+                    String::from_utf8_lossy(&signature)
+                )));
+            }
+            if !OFFSET_EXTENSIONS.contains(&&signature) {
+                extensions.push((signature, data.to_vec()));
+            }
+        }
+
+        let mut index = Index { entries, extensions };
+        index.entries.sort_by(|a, b| a.key().cmp(&b.key()));
+        Ok(index)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        // Like git, use version 3 only when it is needed
+        let version: u32 = if self.entries.iter().any(|e| e.extended_flags != 0) { 3 } else { 2 };
+        let mut out = Vec::new();
+        out.extend_from_slice(SIGNATURE);
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            let start = out.len();
+            for field in [
+                entry.ctime,
+                entry.ctime_nsec,
+                entry.mtime,
+                entry.mtime_nsec,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(entry.oid.as_bytes());
+
+            let name_len = entry.path.len().min(NAME_MASK as usize) as u16;
+            if entry.extended_flags != 0 {
+                out.extend_from_slice(&(entry.flags | FLAG_EXTENDED | name_len).to_be_bytes());
+                out.extend_from_slice(&entry.extended_flags.to_be_bytes());
+            } else {
+                out.extend_from_slice(&(entry.flags | name_len).to_be_bytes());
+            }
+            out.extend_from_slice(&entry.path);
+
+            // At least one NUL, padding the entry to a multiple of 8 bytes
+            let entry_len = out.len() - start;
+            out.resize(start + (entry_len + 8) / 8 * 8, 0);
+        }
+
+        for (signature, data) in &self.extensions {
+            out.extend_from_slice(signature);
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+
+        let checksum = Sha1::digest(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    /// Write the index to `path` through a lockfile.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut lock = Lockfile::acquire(path)?;
+        lock.write_all(&self.serialize())?;
+        lock.commit()
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The stage 0 entry for `path`.
+    pub fn get(&self, path: &[u8]) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|e| e.key().cmp(&(path, 0)))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Stage an entry, replacing any entry for the same path as well as
+    /// entries that would clash with it as a file or directory.
+    pub fn add(&mut self, entry: IndexEntry) {
+        // Refreshing an entry's stat data leaves the extensions valid
+        let content = |e: &IndexEntry| (e.mode, e.oid, e.flags, e.extended_flags);
+        let refresh = self.get(&entry.path).is_some_and(|old| content(old) == content(&entry));
+        if !refresh {
+            self.extensions.clear();
+        }
+        self.entries.retain(|e| e.path != entry.path);
+
+        // Adding "a/b" replaces a file "a"; adding "a" replaces "a/..."
+        let path = entry.path.clone();
+        self.entries.retain(|e| !is_parent(&e.path, &path) && !is_parent(&path, &e.path));
+
+        let pos = self.entries.partition_point(|e| e.key() < entry.key());
+        self.entries.insert(pos, entry);
+    }
+
+    /// Unstage `path` at every stage, returning whether anything was removed.
+    pub fn remove(&mut self, path: &[u8]) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        if self.entries.len() == before {
+            return false;
+        }
+        self.extensions.clear();
+        true
+    }
+
+    /// Paths of entries at or below `prefix` (every entry for an empty prefix).
+    pub fn paths_under(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let mut paths: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .filter(|e| prefix.is_empty() || e.path == prefix || is_parent(prefix, &e.path))
+            .map(|e| e.path.clone())
+            .collect();
+        paths.dedup();
+        paths
+    }
+
+    /// Write the staged content as tree objects, returning the root tree.
+    /// Intent-to-add entries have no content yet and are left out.
+    pub fn write_tree(&self, odb: &ObjectDatabase) -> Result<Oid> {
+        if self.entries.iter().any(|e| e.stage() != 0) {
+            return Err(RitError::InvalidArgument("cannot write a tree with unmerged entries".to_string()));
+        }
+        let entries: Vec<&IndexEntry> = self.entries.iter().filter(|e| !e.is_intent_to_add()).collect();
+        write_subtree(odb, &entries, 0)
+    }
+}
+
+// Build the tree for entries that all share the first `prefix_len` bytes of
+// their path. Entries sorted by full path keep each directory contiguous.
+fn write_subtree(odb: &ObjectDatabase, entries: &[&IndexEntry], prefix_len: usize) -> Result<Oid> {
+    let mut tree = TreeBuilder::new();
+    let mut i = 0;
+
+    while i < entries.len() {
+        let rest = &entries[i].path[prefix_len..];
+
+        match rest.iter().position(|&b| b == b'/') {
+            Some(slash) => {
+                let dir = &rest[..slash];
+                let end = i + entries[i..]
+                    .iter()
+                    .take_while(|e| e.path[prefix_len..].starts_with(dir) && e.path.get(prefix_len + slash) == Some(&b'/'))
+                    .count();
+
+                let oid = write_subtree(odb, &entries[i..end], prefix_len + slash + 1)?;
+                tree.insert(TreeEntry { mode: MODE_TREE, name: dir.to_vec(), oid })?;
+                i = end;
+            }
+            None => {
+                tree.insert(TreeEntry { mode: entries[i].mode, name: rest.to_vec(), oid: entries[i].oid })?;
+                i += 1;
+            }
+        }
+    }
+
+    odb.write_object(&Object::Tree(tree.build()))
+}
+
+// Whether `dir` is a leading directory of `path`
+fn is_parent(dir: &[u8], path: &[u8]) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path[dir.len()] == b'/'
+}
+
+fn corrupt(reason: &str) -> RitError {
+    RitError::CorruptIndex(reason.to_string())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = data.get(pos..pos + 4).ok_or_else(|| corrupt("unexpected end of file"))?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16> {
+    let bytes = data.get(pos..pos + 2).ok_or_else(|| corrupt("unexpected end of file"))?;
+    Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &[u8], flags: u16) -> IndexEntry {
+        let n = path.len() as u32;
+        IndexEntry {
+            ctime: n,
+            ctime_nsec: n + 1,
+            mtime: n + 2,
+            mtime_nsec: n + 3,
+            dev: n + 4,
+            ino: n + 5,
+            mode: 0o100644,
+            uid: n + 6,
+            gid: n + 7,
+            size: n + 8,
+            oid: Oid::hash(crate::object::ObjectKind::Blob, path),
+            flags,
+            extended_flags: 0,
+            path: path.to_vec(),
+        }
+    }
+
+    // Add an extension claiming to be `size` bytes long to serialized index
+    // data, fixing up the checksum
+    fn with_extension(data: &[u8], signature: &[u8; 4], size: usize, ext: &[u8]) -> Vec<u8> {
+        let mut data = data[..data.len() - Oid::LEN].to_vec();
+        data.extend_from_slice(signature);
+        data.extend_from_slice(&(size as u32).to_be_bytes());
+        data.extend_from_slice(ext);
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    #[test]
+    fn roundtrips_entries() {
+        let long = [b'd'; NAME_MASK as usize + 100];
+        let mut index = Index::default();
+        for e in [entry(b"a", 0), entry(b"b/c.txt", 0x8000), entry(&long, 0), entry(b"x", 2 << FLAG_STAGE_SHIFT)] {
+            index.add(e);
+        }
+
+        let parsed = Index::parse(&index.serialize()).unwrap();
+        assert_eq!(parsed.entries(), index.entries());
+        assert_eq!(parsed.serialize(), index.serialize());
+    }
+
+    #[test]
+    fn roundtrips_version_3_extended_flags() {
+        // Version 3 adds two bytes of flags after the name length when
+        // FLAG_EXTENDED is set
+        let mut entries = [entry(b"intent-to-add", 0), entry(b"skipped", 0), entry(b"z", 0)];
+        entries[0].extended_flags = INTENT_TO_ADD;
+        entries[1].extended_flags = SKIP_WORKTREE;
+        let mut data = Vec::new();
+        data.extend_from_slice(SIGNATURE);
+        data.extend_from_slice(&3u32.to_be_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for e in &entries {
+            let plain = IndexEntry { extended_flags: 0, ..e.clone() };
+            let v2 = Index { entries: vec![plain], extensions: Vec::new() }.serialize();
+            let fixed = &v2[HEADER_LEN..HEADER_LEN + ENTRY_FIXED_LEN];
+            let start = data.len();
+            data.extend_from_slice(fixed);
+            if e.extended_flags != 0 {
+                let pos = start + ENTRY_FIXED_LEN - 2;
+                let flags = read_u16(&data, pos).unwrap() | FLAG_EXTENDED;
+                data[pos..pos + 2].copy_from_slice(&flags.to_be_bytes());
+                data.extend_from_slice(&e.extended_flags.to_be_bytes());
+            }
+            data.extend_from_slice(&e.path);
+            let len = data.len() - start;
+            data.resize(start + (len + 8) / 8 * 8, 0);
+        }
+        let checksum = Sha1::digest(&data);
+        data.extend_from_slice(&checksum);
+
+        let mut parsed = Index::parse(&data).unwrap();
+        assert_eq!(parsed.entries(), &entries);
+        assert!(parsed.entries()[0].is_intent_to_add());
+        assert_eq!(parsed.serialize(), data);
+
+        // Without extended flags left, the index goes back to version 2
+        parsed.remove(b"intent-to-add");
+        parsed.remove(b"skipped");
+        assert_eq!(read_u32(&parsed.serialize(), 4).unwrap(), 2);
+
+        // Intent-to-add entries stay out of trees
+        let dir = std::env::temp_dir().join(format!("rit-index-ita-{}", std::process::id()));
+        let odb = ObjectDatabase::new(dir.join("objects"));
+        let mut index = Index::default();
+        index.add(entries[0].clone());
+        assert_eq!(index.write_tree(&odb).unwrap(), Oid::hash(crate::object::ObjectKind::Tree, b""));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keeps_optional_extensions_and_rejects_required_ones() {
+        let mut index = Index::default();
+        index.add(entry(b"a", 0));
+        let data = with_extension(&index.serialize(), b"TREE", 12, b"cached trees");
+
+        // Kept while only stat data changes, dropped when an entry does
+        let mut parsed = Index::parse(&data).unwrap();
+        assert_eq!(parsed.serialize(), data);
+        parsed.add(IndexEntry { mtime: 1, ..entry(b"a", 0) });
+        assert!(parsed.serialize().windows(4).any(|w| w == b"TREE"));
+        parsed.add(entry(b"b", 0));
+        assert!(!parsed.serialize().windows(4).any(|w| w == b"TREE"));
+
+        // A split or sparse index changes what the entries mean
+        for signature in [b"link", b"sdir"] {
+            let data = with_extension(&index.serialize(), signature, 20, &[0; 20]);
+            assert!(matches!(Index::parse(&data), Err(RitError::CorruptIndex(_))));
+        }
+        let truncated = with_extension(&index.serialize(), b"TREE", 100, b"cached trees");
+        assert!(matches!(Index::parse(&truncated), Err(RitError::CorruptIndex(_))));
+    }
+}
@@ -0,0 +1,33 @@
+//! Object database library behind the `rit` command line tool.
+
+pub mod config;
+pub mod delta;
+pub mod diff;
+pub mod error;
+pub mod fsck;
+pub mod gc;
+pub mod graph;
+pub mod ignore;
+pub mod index;
+pub mod lockfile;
+pub mod object;
+pub mod odb;
+pub mod oid;
+pub mod pack;
+pub mod pretty;
+pub mod quote;
+pub mod refs;
+pub mod rename;
+pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod status;
+pub mod worktree;
+
+pub use error::{Result, RitError};
+pub use index::{Index, IndexEntry};
+pub use object::{Commit, Object, ObjectKind, Signature, Tag, Tree, TreeBuilder, TreeEntry};
+pub use odb::ObjectDatabase;
+pub use oid::Oid;
+pub use refs::{RefValue, Refs};
+pub use repository::{InitOptions, Layout, Repository};
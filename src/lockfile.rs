@@ -0,0 +1,56 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RitError};
+
+/// Exclusive `<path>.lock` file, renamed over `path` on commit and removed
+/// if dropped without committing.
+pub struct Lockfile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl Lockfile {
+    pub fn acquire(path: &Path) -> Result<Lockfile> {
+        let mut lock_path = OsString::from(path.as_os_str());
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(RitError::Locked(lock_path)),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Lockfile { path: path.to_path_buf(), lock_path, file: Some(file) })
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.file.as_mut().expect("lockfile already committed").write_all(data)?;
+        Ok(())
+    }
+
+    /// Flush the new contents and atomically replace the target file.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().expect("lockfile already committed");
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.lock_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for Lockfile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
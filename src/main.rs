@@ -1,18 +1,42 @@
-use std::error::Error;
-use clap::{Parser, Subcommand};
-use flate2::bufread::ZlibDecoder;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Write, Read};
+use std::io::{self, BufRead, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
-use sha1::{Digest, Sha1};
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use anyhow;
-use anyhow::{Result};
+use std::process;
+
+use clap::{ArgAction, Parser, Subcommand};
+use rit::{
+    refs, revision, worktree, Commit, Index, InitOptions, Layout, Object, ObjectKind, Oid, RefValue, Repository, Result,
+    RitError, Signature, Tree,
+};
+use rit::diff::{self, Algorithm, FileChange, PatchOptions, StatCounts, StatEntry, TreeDiffOptions};
+use rit::fsck::{self, FsckOptions, Severity};
+use rit::gc::{self, RepackOptions};
+use rit::ignore::Ignore;
+use rit::object::MODE_TREE;
+use rit::graph::Graph;
+use rit::odb::DEFAULT_ABBREV;
+use rit::pack::{self, Pack, PackOptions};
+use rit::pretty::{self, Format};
+use rit::quote;
+use rit::rename::{self, RenameOptions};
+use rit::revwalk::{self, Order, RevWalk};
+use rit::status::{self, Change, UntrackedMode};
+
+// Columns a diffstat fits in, as git uses when not writing to a terminal
+const STAT_WIDTH: usize = 80;
 
 #[derive(Parser)]
 #[command(name = "rit", version = "0.1", about = "A mini git implementation")]
 struct Cli {
+    /// Run as if rit was started in <path> (may be given more than once;
+    /// only before the command, whose own -C is something else)
+    #[arg(short = 'C', value_name = "path")]
+    chdir: Vec<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -20,7 +44,19 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize a new, empty repository
-    Init,
+    Init {
+        /// Directory to create the repository in (default: current directory)
+        directory: Option<PathBuf>,
+        /// Create a bare repository without a work tree
+        #[arg(long)]
+        bare: bool,
+        /// Name of the branch HEAD points at
+        #[arg(short = 'b', long, value_name = "name")]
+        initial_branch: Option<String>,
+        /// Repository directory name: `git` for `.git`, `rit` for `.rit`
+        #[arg(long, default_value_t = Layout::Git)]
+        layout: Layout,
+    },
     /// Compute object ID and optionally create a blob from a file
     HashObject {
         /// Write the object into the object database
@@ -32,249 +68,1934 @@ enum Commands {
     /// Provide content or type of repository objects
     CatFile {
         /// Pretty-print the contents of objects
-        #[arg(short = 'p')]
+        #[arg(short = 'p', conflicts_with_all = ["show_type", "show_size"])]
         pretty_print: bool,
-        /// The object ID (SHA-1 hash)
-        oid: String,
+        /// Show the object type
+        #[arg(short = 't', conflicts_with = "show_size")]
+        show_type: bool,
+        /// Show the object size
+        #[arg(short = 's')]
+        show_size: bool,
+        /// The object to show, e.g. an object ID or HEAD:README.md
+        object: String,
     },
+    /// List the contents of a tree object
     LsTree {
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+
+        /// Show tree entries even when recursing into them
+        #[arg(short = 't')]
+        show_trees: bool,
+
+        /// Show only tree entries
+        #[arg(short = 'd')]
+        trees_only: bool,
+
+        /// Show the size of blobs
+        #[arg(short = 'l', long = "long")]
+        long: bool,
+
+        /// Show only the path of each entry
         #[clap(long)]
         name_only: bool,
 
-        tree_hash: String,
+        /// Show object IDs abbreviated to at least <n> hex digits
+        #[arg(long, value_name = "n", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        abbrev: Option<usize>,
+
+        /// Terminate entries with NUL instead of newline and never quote names
+        #[arg(short = 'z')]
+        null_terminated: bool,
+
+        /// Show paths from the top of the tree instead of the current directory
+        #[arg(long)]
+        full_name: bool,
+
+        /// List the whole tree, taking paths from its top, wherever rit is run
+        #[arg(long)]
+        full_tree: bool,
+
+        /// A tree, or a commit or tag that peels to one (e.g. HEAD~2:src)
+        tree_ish: String,
+
+        /// Only show entries at or under these paths, relative to the current
+        /// directory
+        paths: Vec<PathBuf>,
     },
+    /// Create a tree object from the index, or from a directory
     WriteTree{
+        /// Snapshot this directory instead of the index
         path: Option<PathBuf>,
-    }
+    },
+    /// Record the staged changes as a new commit on the current branch
+    Commit {
+        /// Commit message; several are joined as separate paragraphs
+        #[arg(short = 'm', required = true)]
+        message: Vec<String>,
+        /// Allow a commit that records the same tree as its parent
+        #[arg(long)]
+        allow_empty: bool,
+    },
+    /// Update the object a ref points at, optionally checking its old value
+    UpdateRef {
+        /// Delete the ref instead of updating it
+        #[arg(short = 'd', conflicts_with = "new")]
+        delete: bool,
+        /// Reason recorded in the reflog
+        #[arg(short = 'm', default_value = "")]
+        message: String,
+        /// The ref to update, e.g. refs/heads/main or HEAD
+        #[arg(value_name = "ref")]
+        name: String,
+        /// New object ID (omitted with -d)
+        #[arg(required_unless_present = "delete")]
+        new: Option<String>,
+        /// Value the ref must currently have; all zeros if it must not exist
+        old: Option<String>,
+    },
+    /// Read or set a symbolic ref such as HEAD
+    SymbolicRef {
+        /// Shorten refs/heads/main to main when reading
+        #[arg(long)]
+        short: bool,
+        /// The symbolic ref, e.g. HEAD
+        #[arg(value_name = "name")]
+        name: String,
+        /// New target ref; prints the current target when omitted
+        #[arg(value_name = "ref")]
+        target: Option<String>,
+    },
+    /// Print the object IDs that names refer to
+    RevParse {
+        /// Abbreviate object IDs to at least <n> hex digits
+        #[arg(long, value_name = "n", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        short: Option<usize>,
+        /// Revisions such as HEAD~2, main^2, v1.0^{tree} or @{-1}
+        #[arg(required = true)]
+        revs: Vec<String>,
+    },
+    /// Add file contents to the index
+    Add {
+        /// Also add files that are ignored
+        #[arg(short, long)]
+        force: bool,
+        /// Files or directories to stage
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Remove files from the index and work tree
+    Rm {
+        /// Only remove from the index, keeping the work tree file
+        #[arg(long)]
+        cached: bool,
+        /// Allow recursive removal when a directory is given
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Remove files even if they have staged or local changes
+        #[arg(short, long)]
+        force: bool,
+        /// Files or directories to remove
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Create a new commit object from a tree
+    CommitTree {
+        /// The tree object the commit points at
+        tree: String,
+        /// Parent commit (may be given more than once)
+        #[arg(short = 'p')]
+        parents: Vec<String>,
+        /// Commit message
+        #[arg(short = 'm')]
+        message: String,
+    },
+    /// Show commit logs
+    Log {
+        /// One line per commit: abbreviated ID and subject
+        #[arg(long)]
+        oneline: bool,
+        /// Show at most <n> commits
+        #[arg(short = 'n', long = "max-count", value_name = "n")]
+        max_count: Option<usize>,
+        /// oneline, short, medium, full, or a template such as "format:%h %s"
+        #[arg(long, visible_alias = "pretty", value_name = "format")]
+        format: Option<String>,
+        /// Draw the history graph next to the log
+        #[arg(long)]
+        graph: bool,
+        /// Show no parent before its children, otherwise by commit date
+        #[arg(long, conflicts_with = "topo_order")]
+        date_order: bool,
+        /// Show no parent before its children, keeping lines of history together
+        #[arg(long)]
+        topo_order: bool,
+        /// Commits to start from (default: HEAD)
+        revs: Vec<String>,
+        /// Only show commits that change these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Pack the objects listed on standard input into <base-name>-<hash>.pack and .idx
+    PackObjects {
+        /// Read revisions (such as main, ^v1.0 or v1.0..main) instead of object IDs
+        /// and pack everything they reach
+        #[arg(long)]
+        revs: bool,
+        /// Write the pack to standard output instead of files
+        #[arg(long)]
+        stdout: bool,
+        /// Number of similar objects tried as delta bases for each object
+        #[arg(long, default_value_t = PackOptions::default().window)]
+        window: usize,
+        /// Longest delta chain allowed
+        #[arg(long, default_value_t = PackOptions::default().depth)]
+        depth: usize,
+        /// Prefix of the files to write, e.g. .git/objects/pack/pack
+        #[arg(required_unless_present = "stdout", conflicts_with = "stdout")]
+        base_name: Option<PathBuf>,
+    },
+    /// Pack refs and objects and prune unreachable objects
+    Gc {
+        /// Prune unreachable loose objects older than this ("now", "never", "2.weeks.ago")
+        #[arg(long, value_name = "date", default_value = gc::DEFAULT_PRUNE_EXPIRE)]
+        prune: String,
+        /// Keep all unreachable objects
+        #[arg(long)]
+        no_prune: bool,
+        /// Search much harder for deltas
+        #[arg(long)]
+        aggressive: bool,
+    },
+    /// Pack loose objects reachable from refs, HEAD, reflogs and the index
+    Repack {
+        /// Pack everything reachable into a single pack
+        #[arg(short = 'a')]
+        all: bool,
+        /// Delete loose objects, and with -a old packs, that are now redundant
+        #[arg(short = 'd')]
+        delete: bool,
+        /// Number of similar objects tried as delta bases for each object
+        #[arg(long, default_value_t = PackOptions::default().window)]
+        window: usize,
+        /// Longest delta chain allowed
+        #[arg(long, default_value_t = PackOptions::default().depth)]
+        depth: usize,
+    },
+    /// Delete unreachable loose objects
+    Prune {
+        /// Only prune objects older than this
+        #[arg(long, value_name = "date", default_value = "now")]
+        expire: String,
+        /// Report what would be pruned without deleting anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Report pruned objects
+        #[arg(short = 'v', long)]
+        verbose: bool,
+    },
+    /// Move refs into packed-refs
+    PackRefs,
+    /// Check packs and their indexes
+    VerifyPack {
+        /// List every object, then delta chain statistics
+        #[arg(short = 'v', long)]
+        verbose: bool,
+        /// Only show delta chain statistics
+        #[arg(short = 's', long)]
+        stat_only: bool,
+        /// .idx or .pack files
+        #[arg(required = true)]
+        packs: Vec<PathBuf>,
+    },
+    /// Show which ignore rule, if any, excludes each path
+    CheckIgnore {
+        /// Also show the matching pattern and where it comes from
+        #[arg(short, long)]
+        verbose: bool,
+        /// With -v, also list paths that match no pattern
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+        /// Check tracked files too, instead of treating them as not ignored
+        #[arg(long)]
+        no_index: bool,
+        /// Paths to check
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show staged, unstaged and untracked changes
+    Status {
+        /// Give the output in the short format
+        #[arg(short, long)]
+        short: bool,
+        /// Show the branch in the short and porcelain formats
+        #[arg(short, long)]
+        branch: bool,
+        /// Stable output for scripts: v1 (the short format) or v2
+        #[arg(long, value_name = "version", num_args = 0..=1, require_equals = true, default_missing_value = "v1")]
+        porcelain: Option<String>,
+        /// Untracked files to show: no, normal (directories as a whole) or all
+        #[arg(
+            short = 'u',
+            long = "untracked-files",
+            value_name = "mode",
+            num_args = 0..=1,
+            default_value = "normal",
+            default_missing_value = "all"
+        )]
+        untracked_files: UntrackedMode,
+        /// Terminate entries with NUL and never quote paths; implies --porcelain
+        #[arg(short = 'z')]
+        null_terminated: bool,
+    },
+    /// Show changes between the index and the work tree, or between trees
+    Diff {
+        /// Compare the index with HEAD (or <rev>) instead of the work tree
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+        /// Show a diffstat instead of the patch
+        #[arg(long)]
+        stat: bool,
+        /// Show only the names of changed files
+        #[arg(long, conflicts_with = "stat")]
+        name_only: bool,
+        /// Show only the names of changed files and how they changed (A, D, M, T or U)
+        #[arg(long, conflicts_with_all = ["stat", "name_only"])]
+        name_status: bool,
+        /// Lines of context around each change
+        #[arg(short = 'U', long = "unified", value_name = "n", default_value_t = 3)]
+        context: usize,
+        /// myers, patience or histogram
+        #[arg(long, value_name = "algorithm", default_value = "myers")]
+        diff_algorithm: Algorithm,
+        /// Use the patience diff algorithm
+        #[arg(long, conflicts_with_all = ["diff_algorithm", "histogram"])]
+        patience: bool,
+        /// Use the histogram diff algorithm
+        #[arg(long, conflicts_with = "diff_algorithm")]
+        histogram: bool,
+        /// One revision to compare with the work tree (or the index with
+        /// --cached), or two to compare with each other, also as <rev>..<rev>
+        revs: Vec<String>,
+        /// Only show changes to these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Compare the trees of two tree-ishes, or a commit with its first parent
+    DiffTree {
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Show subtrees as well as their changes; implies -r
+        #[arg(short = 't')]
+        show_trees: bool,
+        /// Detect renames, optionally with the similarity they need, e.g. -M75% (default 50%)
+        #[arg(short = 'M', long, value_name = "n", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        find_renames: Option<String>,
+        /// Detect copies as well as renames, with the same similarity as -M;
+        /// given twice, the same as --find-copies-harder
+        #[arg(
+            short = 'C',
+            long,
+            value_name = "n",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "",
+            action = ArgAction::Append
+        )]
+        find_copies: Vec<String>,
+        /// Look for copy sources among unmodified files too; implies -C
+        #[arg(long)]
+        find_copies_harder: bool,
+        /// Show a patch instead of the raw listing; implies -r
+        #[arg(short = 'p', long)]
+        patch: bool,
+        /// Show a diffstat instead of the raw listing; implies -r
+        #[arg(long, conflicts_with = "patch")]
+        stat: bool,
+        /// Show only the names of changed entries
+        #[arg(long, conflicts_with_all = ["patch", "stat"])]
+        name_only: bool,
+        /// Show only the names of changed entries and how they changed
+        #[arg(long, conflicts_with_all = ["patch", "stat", "name_only"])]
+        name_status: bool,
+        /// Compare a commit without parents with the empty tree
+        #[arg(long)]
+        root: bool,
+        /// A commit to compare with its parent, or the first of two tree-ishes
+        tree_ish: String,
+        /// The tree-ish to compare the first one with
+        other: Option<String>,
+        /// Only show changes to these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Verify the connectivity and validity of the objects in the database
+    Fsck {
+        /// Report every unreachable object, not just dangling ones
+        #[arg(long)]
+        unreachable: bool,
+        /// Do not report dangling objects
+        #[arg(long)]
+        no_dangling: bool,
+    },
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let cli = Cli::parse_from(attach_short_values(env::args_os().collect()));
+
+    if let Err(e) = run(cli) {
+        eprintln!("fatal: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+// Git takes the optional values of diff-tree's -M and -C attached, as in
+// "-M75%", which clap only accepts after an '=', so add one
+fn attach_short_values(mut args: Vec<OsString>) -> Vec<OsString> {
+    // Skip options before the command, -C taking the next argument
+    let mut command = 1;
+    while args.get(command).is_some_and(|arg| arg.as_bytes().starts_with(b"-")) {
+        command += if args[command] == "-C" { 2 } else { 1 };
+    }
+    if args.get(command).map_or(true, |name| name != "diff-tree") {
+        return args;
+    }
+
+    for arg in args.iter_mut().skip(command + 1) {
+        let bytes = arg.as_bytes();
+        if bytes == b"--" {
+            break;
+        }
+        if bytes.len() > 2 && (bytes.starts_with(b"-M") || bytes.starts_with(b"-C")) && bytes[2] != b'=' {
+            *arg = OsString::from_vec([&bytes[..2], b"=", &bytes[2..]].concat());
+        }
+    }
+    args
+}
+
+fn run(cli: Cli) -> Result<()> {
+    // Each -C is relative to the previous one, like git
+    for dir in &cli.chdir {
+        env::set_current_dir(dir)?;
+    }
 
     match cli.command {
-        Commands::Init => init(),
+        Commands::Init { directory, bare, initial_branch, layout } => {
+            init(directory, InitOptions { bare, layout, initial_branch })
+        }
         Commands::HashObject { write, file } => hash_object(write, file),
-        Commands::CatFile { pretty_print, oid } => cat_file(pretty_print, &oid),
-        Commands::LsTree { name_only, tree_hash } => {
-         ls_tree(name_only, &tree_hash)
-        },
-        Commands::WriteTree { path } => {
-            let path = path.as_deref().unwrap_or(Path::new("."));
-            // Now path is a &Path, defaulting to current directory
-            match write_tree(Some(path)){
-                Ok(hash) => println!("{}", hash),
-                Err(e) => println!("Error: {}", e)
+        Commands::CatFile { pretty_print, show_type, show_size, object } => {
+            let mode = if show_type {
+                CatMode::Type
+            } else if show_size {
+                CatMode::Size
+            } else if pretty_print {
+                CatMode::Pretty
+            } else {
+                CatMode::Raw
+            };
+            cat_file(&repo()?, mode, &object)
+        }
+        Commands::LsTree {
+            recursive,
+            show_trees,
+            trees_only,
+            long,
+            name_only,
+            abbrev,
+            null_terminated,
+            full_name,
+            full_tree,
+            tree_ish,
+            paths,
+        } => {
+            let options = LsTreeOptions {
+                recursive,
+                // -r -d would otherwise show nothing
+                show_trees: show_trees || (recursive && trees_only),
+                trees_only,
+                long,
+                name_only,
+                abbrev,
+                null_terminated,
+                full_name: full_name || full_tree,
+                full_tree,
+            };
+            ls_tree(&repo()?, &tree_ish, &paths, &options)
+        }
+        Commands::WriteTree { path } => write_tree(path),
+        Commands::Commit { message, allow_empty } => commit(&message, allow_empty),
+        Commands::UpdateRef { delete, message, name, new, old } => {
+            update_ref(&name, new.as_deref(), old.as_deref(), delete, &message)
+        }
+        Commands::SymbolicRef { short, name, target } => symbolic_ref(&name, target.as_deref(), short),
+        Commands::RevParse { short, revs } => rev_parse(&revs, short),
+        Commands::Add { force, paths } => add(&paths, force),
+        Commands::Rm { cached, recursive, force, paths } => rm(&repo()?, &paths, cached, recursive, force),
+        Commands::CommitTree { tree, parents, message } => commit_tree(&repo()?, &tree, &parents, message),
+        Commands::Log { oneline, max_count, format, graph, date_order, topo_order, revs, paths } => {
+            let format = match format {
+                Some(format) => Format::parse(&format)?,
+                None if oneline => Format::Oneline,
+                None => Format::Medium,
+            };
+            // The graph needs children drawn before their parents
+            let order = if date_order {
+                Order::DateTopo
+            } else if topo_order || graph {
+                Order::Topo
+            } else {
+                Order::Date
+            };
+            log(&repo()?, &revs, &paths, LogOptions { format, order, graph, max_count })
+        }
+        // --stdout is exactly the case without a base name
+        Commands::PackObjects { revs, window, depth, base_name, .. } => {
+            pack_objects(&repo()?, base_name.as_deref(), revs, PackOptions { window, depth })
+        }
+        Commands::Gc { prune, no_prune, aggressive } => {
+            let expire = if no_prune { None } else { gc::parse_expire(&prune)? };
+            // git's --aggressive window; deeper chains only slow reads down
+            let window = if aggressive { 250 } else { PackOptions::default().window };
+            gc::gc(&repo()?, expire, PackOptions { window, ..PackOptions::default() })
+        }
+        Commands::Repack { all, delete, window, depth } => {
+            let options = RepackOptions {
+                all,
+                delete_redundant: delete,
+                keep_unreachable_since: None,
+                pack: PackOptions { window, depth },
+            };
+            gc::repack(&repo()?, &options).map(|_| ())
+        }
+        Commands::Prune { expire, dry_run, verbose } => prune(&repo()?, &expire, dry_run, verbose),
+        Commands::PackRefs => gc::pack_refs(&repo()?),
+        Commands::VerifyPack { verbose, stat_only, packs } => verify_pack(&packs, verbose, stat_only),
+        Commands::CheckIgnore { verbose, non_matching, no_index, paths } => {
+            // Like grep, exit with 1 when nothing is ignored
+            if !check_ignore(&repo()?, &paths, verbose, non_matching, no_index)? {
+                process::exit(1);
             }
+            Ok(())
+        }
+        Commands::Status { short, branch, porcelain, untracked_files, null_terminated } => {
+            let format = match porcelain.as_deref() {
+                Some("v1" | "1") => StatusFormat::Porcelain,
+                Some("v2" | "2") => StatusFormat::PorcelainV2,
+                Some(other) => return Err(RitError::InvalidArgument(format!("unsupported porcelain version '{other}'"))),
+                None if short => StatusFormat::Short,
+                None if null_terminated => StatusFormat::Porcelain,
+                None => StatusFormat::Long,
+            };
+            status(&repo()?, format, branch, untracked_files, null_terminated)
+        }
+        Commands::Diff {
+            cached,
+            stat,
+            name_only,
+            name_status,
+            context,
+            diff_algorithm,
+            patience,
+            histogram,
+            revs,
+            paths,
+        } => {
+            let output = if stat {
+                DiffOutput::Stat
+            } else if name_only {
+                DiffOutput::NameOnly
+            } else if name_status {
+                DiffOutput::NameStatus
+            } else {
+                DiffOutput::Patch
+            };
+            let algorithm = if patience {
+                Algorithm::Patience
+            } else if histogram {
+                Algorithm::Histogram
+            } else {
+                diff_algorithm
+            };
+            let repo = repo()?;
+            let options = PatchOptions { algorithm, context, quote_high: quote_path_setting(&repo)? };
+            diff(&repo, &revs, &paths, cached, output, &options)
+        }
+        Commands::DiffTree {
+            recursive,
+            show_trees,
+            find_renames,
+            mut find_copies,
+            find_copies_harder,
+            patch,
+            stat,
+            name_only,
+            name_status,
+            root,
+            tree_ish,
+            other,
+            paths,
+        } => {
+            let output = if patch {
+                DiffOutput::Patch
+            } else if stat {
+                DiffOutput::Stat
+            } else if name_only {
+                DiffOutput::NameOnly
+            } else if name_status {
+                DiffOutput::NameStatus
+            } else {
+                DiffOutput::Raw
+            };
+            // -M and -C share one threshold, as in git
+            let find_copies_harder = find_copies_harder || find_copies.len() > 1;
+            let copies = !find_copies.is_empty() || find_copies_harder;
+            let score = find_copies.pop().or(find_renames).or_else(|| find_copies_harder.then(String::new));
+            let renames = match score {
+                Some(score) => Some(RenameOptions { min_score: rename::parse_score(&score)?, copies }),
+                None => None,
+            };
+            let options = DiffTreeOptions {
+                output,
+                tree: TreeDiffOptions {
+                    recursive: recursive || show_trees || patch || stat,
+                    show_trees,
+                    paths: Vec::new(),
+                },
+                renames,
+                find_copies_harder,
+                root,
+            };
+            diff_tree(&repo()?, &tree_ish, other.as_deref(), &paths, options)
+        }
+        Commands::Fsck { unreachable, no_dangling } => {
+            fsck(&repo()?, FsckOptions { dangling: !no_dangling, unreachable })
         }
     }
 }
 
-fn init() {
-    fs::create_dir(".git").unwrap();
-    fs::create_dir(".git/objects").unwrap();
-    fs::create_dir(".git/refs").unwrap();
-    fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
-    println!("Initialized rit directory");
+// The repository containing the current directory
+fn repo() -> Result<Repository> {
+    Repository::discover(&env::current_dir()?)
 }
 
-fn hash_object(write: bool, file: PathBuf) {
-    let data = fs::read(&file).unwrap();
+fn init(directory: Option<PathBuf>, options: InitOptions) -> Result<()> {
+    // Repository::init creates the directory once the options are known to
+    // be valid
+    let path = match directory {
+        Some(dir) => dir,
+        None => env::current_dir()?,
+    };
 
-    // Git's blob header format: "blob <size>\0"
-    let header = format!("blob {}\0", data.len());
+    let (repo, existed) = Repository::init(&path, &options)?;
+    let verb = if existed { "Reinitialized existing" } else { "Initialized empty" };
+    println!("{} rit repository in {}", verb, repo.git_dir().display());
+    Ok(())
+}
 
-    // Concatenate header + file data
-    let mut store = Vec::new();
-    store.extend_from_slice(header.as_bytes());
-    store.extend_from_slice(&data);
+fn hash_object(write: bool, file: PathBuf) -> Result<()> {
+    let oid = if write {
+        worktree::hash_file(repo()?.odb(), &file)?
+    } else {
+        Oid::hash(ObjectKind::Blob, &worktree::read_file(&file)?)
+    };
 
-    // Compute SHA-1 over the uncompressed data
-    let mut hasher = Sha1::new();
-    hasher.update(&store);
-    let oid = hasher.finalize();
-    let hex = format!("{:x}", oid);
+    println!("{}", oid);
+    Ok(())
+}
 
-    if write {
-        // Compress before writing
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&store).unwrap();
-        let compressed = encoder.finish().unwrap();
+#[derive(Clone, Copy)]
+enum CatMode {
+    Raw,
+    Pretty,
+    Type,
+    Size,
+}
 
-        // Write into .rit/objects/xx/yyyy...
-        let dir = format!(".git/objects/{}", &hex[..2]);
-        let file_path = format!("{}/{}", dir, &hex[2..]);
-        fs::create_dir_all(&dir).unwrap();
-        fs::write(file_path, compressed).unwrap();
+fn cat_file(repo: &Repository, mode: CatMode, rev: &str) -> Result<()> {
+    let oid = revision::resolve(repo, rev)?;
+    let odb = repo.odb();
+    let (kind, content) = odb.read_raw(&oid)?;
+    let mut stdout = io::stdout().lock();
+
+    match mode {
+        CatMode::Type => writeln!(stdout, "{}", kind)?,
+        CatMode::Size => writeln!(stdout, "{}", content.len())?,
+        CatMode::Raw => {
+            // Normal mode → print header + raw content
+            writeln!(stdout, "{} {}", kind, content.len())?;
+            stdout.write_all(&content)?;
+        }
+        CatMode::Pretty if kind == ObjectKind::Tree => {
+            let quote_high = quote_path_setting(repo)?;
+            for entry in &odb.read_tree(&oid)?.entries {
+                write!(stdout, "{:06o} {} {}\t", entry.mode, entry.kind(), entry.oid)?;
+                stdout.write_all(&quote::quote_path(&entry.name, quote_high))?;
+                writeln!(stdout)?;
+            }
+        }
+        // Blobs, commits and tags are stored as their printable form
+        CatMode::Pretty => stdout.write_all(&content)?,
     }
 
-    println!("{}", hex);
+    Ok(())
 }
 
+struct LsTreeOptions {
+    recursive: bool,
+    show_trees: bool,
+    trees_only: bool,
+    long: bool,
+    name_only: bool,
+    abbrev: Option<usize>,
+    null_terminated: bool,
+    full_name: bool,
+    full_tree: bool,
+}
 
-fn cat_file(pretty_print: bool, sha: &str) {
-    let path = format!(".git/objects/{}/{}", &sha[..2], &sha[2..]);
-    let compressed = fs::read(path).unwrap();
+fn ls_tree(repo: &Repository, tree_ish: &str, paths: &[PathBuf], options: &LsTreeOptions) -> Result<()> {
+    let oid = revision::resolve_as(repo, tree_ish, ObjectKind::Tree)?;
+    let tree = repo.odb().read_tree(&oid)?;
 
-    let mut decoder = ZlibDecoder::new(&compressed[..]);
-    let mut decompressed = Vec::new();
-    decoder.read_to_end(&mut decompressed).unwrap();
+    // Paths are taken from the current directory, which alone is listed
+    // when none are given. A trailing slash or a final "." or ".." means
+    // the directory's contents rather than its own entry
+    let (cwd, paths): (Vec<u8>, Vec<Vec<u8>>) = match repo.work_tree() {
+        Some(work_tree) if !options.full_tree => {
+            let cwd = worktree::repo_path(work_tree, Path::new("."))?;
+            let mut specs = Vec::new();
+            for path in paths {
+                let mut spec = worktree::repo_path(work_tree, path)?;
+                let bytes = path.as_os_str().as_bytes();
+                let last = bytes.rsplit(|&b| b == b'/').find(|part| !part.is_empty());
+                if !spec.is_empty() && (bytes.ends_with(b"/") || matches!(last, Some(b".") | Some(b".."))) {
+                    spec.push(b'/');
+                }
+                specs.push(spec);
+            }
+            if specs.is_empty() && !cwd.is_empty() {
+                specs.push([&cwd[..], b"/"].concat());
+            }
+            (cwd, specs)
+        }
+        _ => (Vec::new(), paths.iter().map(|path| path.as_os_str().as_bytes().to_vec()).collect()),
+    };
+
+    let quote_high = quote_path_setting(repo)?;
+    let display = |path: &[u8]| {
+        let shown = if options.full_name || cwd.is_empty() {
+            path.to_vec()
+        } else if path == &cwd[..] {
+            b"./".to_vec()
+        } else {
+            relative_path(path, &cwd)
+        };
+        if options.null_terminated {
+            shown
+        } else {
+            quote::quote_path(&shown, quote_high)
+        }
+    };
 
-    // Find the first null byte (\0)
-    if let Some(null_pos) = decompressed.iter().position(|&b| b == 0) {
-        let header = &decompressed[..null_pos]; // e.g. "blob 2122"
-        let content = &decompressed[null_pos + 1..];
+    let mut stdout = io::stdout().lock();
+    list_tree(repo, &tree, b"", &paths, options, &display, &mut stdout)
+}
+
+// List one level of a tree whose entries are at `prefix`, descending into
+// subtrees as the options and path filters ask. `display` gives the name
+// printed for an entry's path
+fn list_tree(
+    repo: &Repository,
+    tree: &Tree,
+    prefix: &[u8],
+    paths: &[Vec<u8>],
+    options: &LsTreeOptions,
+    display: &dyn Fn(&[u8]) -> Vec<u8>,
+    out: &mut impl Write,
+) -> Result<()> {
+    for entry in &tree.entries {
+        let path = worktree::join_path(prefix, &entry.name);
+
+        // A filter matches the entry, or everything below it, or names
+        // something below it so that it has to be descended into
+        let matched = paths.is_empty()
+            || paths.iter().any(|spec| {
+                spec.is_empty()
+                    || *spec == path
+                    || (path.starts_with(spec) && (spec.ends_with(b"/") || path[spec.len()] == b'/'))
+            });
+        let leads_to_match =
+            paths.iter().any(|spec| spec.len() > path.len() && spec.starts_with(&path) && spec[path.len()] == b'/');
+        if !matched && !leads_to_match {
+            continue;
+        }
 
-        let header_str = String::from_utf8_lossy(header);
+        let descend = entry.is_tree() && (leads_to_match || options.recursive);
+        let show = if entry.is_tree() { !descend || options.show_trees } else { !options.trees_only };
+        if show {
+            if !options.name_only {
+                let oid = match options.abbrev {
+                    Some(len) => repo.odb().abbreviate(&entry.oid, len)?,
+                    None => entry.oid.to_hex(),
+                };
+                write!(out, "{:06o} {} {}", entry.mode, entry.kind(), oid)?;
+                if options.long {
+                    let size = match entry.kind() {
+                        ObjectKind::Blob => repo.odb().read_raw(&entry.oid)?.1.len().to_string(),
+                        _ => "-".to_string(),
+                    };
+                    write!(out, " {size:>7}")?;
+                }
+                write!(out, "\t")?;
+            }
+            out.write_all(&display(&path))?;
+            out.write_all(if options.null_terminated { b"\0" } else { b"\n" })?;
+        }
+
+        if descend {
+            let subtree = repo.odb().read_tree(&entry.oid)?;
+            list_tree(repo, &subtree, &path, paths, options, display, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+// core.quotePath: whether names are shown with non-ASCII bytes escaped
+fn quote_path_setting(repo: &Repository) -> Result<bool> {
+    Ok(repo.config()?.get_bool("core.quotePath")?.unwrap_or(true))
+}
 
-        // Split header into type + size
-        let mut parts = header_str.split_whitespace();
-        let obj_type = parts.next().unwrap_or("");
-        let _size = parts.next().unwrap_or("");
+fn write_tree(path: Option<PathBuf>) -> Result<()> {
+    let repo = repo()?;
+    let index_path = repo.index_path();
 
-        if obj_type == "blob" {
-            if pretty_print {
-                // Pretty print → just print the blob content as is
-                print!("{}", String::from_utf8_lossy(content));
+    let oid = match path {
+        Some(path) => {
+            // A directory outside the work tree only has its own ignore files
+            let inside = repo.work_tree().is_some_and(|work_tree| worktree::repo_path(work_tree, &path).is_ok());
+            let mut ignore = if inside {
+                Ignore::for_repo(&repo)?
             } else {
-                // Normal mode → print header + raw content
-                println!("{}", header_str);
-                println!("{}", String::from_utf8_lossy(content));
+                Ignore::new(&worktree::absolute_path(&path)?, None, &repo.config()?)?
+            };
+            worktree::write_tree(repo.odb(), &path, &mut ignore)?
+        }
+        // Before anything has been staged, snapshot the whole work tree
+        None if !index_path.exists() => {
+            worktree::write_tree(repo.odb(), repo.require_work_tree()?, &mut Ignore::for_repo(&repo)?)?
+        }
+        None => Index::load(&index_path)?.write_tree(repo.odb())?,
+    };
+
+    println!("{}", oid);
+    Ok(())
+}
+
+fn commit(messages: &[String], allow_empty: bool) -> Result<()> {
+    let repo = repo()?;
+    let index = Index::load(&repo.index_path())?;
+
+    // HEAD usually names a branch, which does not exist before the first commit
+    let branch = repo.refs().resolve_name(refs::HEAD)?;
+    let parent = repo.refs().resolve(&branch)?;
+
+    let nothing_to_commit = || RitError::InvalidArgument("nothing to commit".to_string());
+    if parent.is_none() && index.is_empty() && !allow_empty {
+        return Err(nothing_to_commit());
+    }
+
+    let tree = index.write_tree(repo.odb())?;
+    if let Some(parent) = parent {
+        if repo.odb().read_commit(&parent)?.tree == tree && !allow_empty {
+            return Err(nothing_to_commit());
+        }
+    }
+
+    let mut message = messages.join("\n\n");
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    let config = repo.config()?;
+    let commit = Commit {
+        tree,
+        parents: parent.into_iter().collect(),
+        author: Signature::from_env("AUTHOR", &config)?,
+        committer: Signature::from_env("COMMITTER", &config)?,
+        extra_headers: Vec::new(),
+        message: message.into_bytes(),
+    };
+    let summary = commit.summary();
+    let oid = repo.odb().write_object(&Object::Commit(commit))?;
+
+    // Fail rather than lose a commit made concurrently on the same branch
+    let root = if parent.is_none() { " (root-commit)" } else { "" };
+    let reason = if parent.is_none() { "commit (initial)" } else { "commit" };
+    let expected = parent.unwrap_or(Oid::ZERO);
+    repo.refs().update(&branch, &oid, Some(&expected), &format!("{reason}: {summary}"))?;
+
+    let short_branch = branch.strip_prefix("refs/heads/").unwrap_or("detached HEAD");
+    let short_oid = repo.odb().abbreviate(&oid, DEFAULT_ABBREV)?;
+    println!("[{}{} {}] {}", short_branch, root, short_oid, summary);
+    Ok(())
+}
+
+fn update_ref(name: &str, new: Option<&str>, old: Option<&str>, delete: bool, message: &str) -> Result<()> {
+    let repo = repo()?;
+    let old = old.map(|old| resolve_or_zero(&repo, old)).transpose()?;
+
+    match new {
+        Some(new) if !delete => repo.refs().update(name, &revision::resolve(&repo, new)?, old.as_ref(), message),
+        _ => repo.refs().delete(name, old.as_ref()),
+    }
+}
+
+fn symbolic_ref(name: &str, target: Option<&str>, short: bool) -> Result<()> {
+    let repo = repo()?;
+
+    if let Some(target) = target {
+        return repo.refs().set_symbolic(name, target);
+    }
+
+    match repo.refs().read(name)? {
+        Some(RefValue::Symbolic(target)) => {
+            let shown = if short { target.strip_prefix("refs/heads/").unwrap_or(&target) } else { &target };
+            println!("{}", shown);
+            Ok(())
+        }
+        _ => Err(RitError::InvalidArgument(format!("ref {name} is not a symbolic ref"))),
+    }
+}
+
+// An expected old value: a revision, or all zeros for "must not exist"
+fn resolve_or_zero(repo: &Repository, rev: &str) -> Result<Oid> {
+    match Oid::from_hex(rev) {
+        Ok(oid) if oid == Oid::ZERO => Ok(oid),
+        _ => revision::resolve(repo, rev),
+    }
+}
+
+fn rev_parse(revs: &[String], short: Option<usize>) -> Result<()> {
+    let repo = repo()?;
+
+    for rev in revs {
+        let oid = revision::resolve(&repo, rev)?;
+        match short {
+            Some(len) => println!("{}", repo.odb().abbreviate(&oid, len)?),
+            None => println!("{}", oid),
+        }
+    }
+
+    Ok(())
+}
+
+fn add(paths: &[PathBuf], force: bool) -> Result<()> {
+    let repo = repo()?;
+    let mut index = Index::load(&repo.index_path())?;
+    let mut ignore = if force { None } else { Some(Ignore::for_repo(&repo)?) };
+
+    for path in paths {
+        worktree::add_to_index(&repo, &mut index, ignore.as_mut(), path)?;
+    }
+
+    index.write(&repo.index_path())
+}
+
+fn rm(repo: &Repository, paths: &[PathBuf], cached: bool, recursive: bool, force: bool) -> Result<()> {
+    let work_tree = repo.require_work_tree()?;
+    let mut index = Index::load(&repo.index_path())?;
+
+    // Check every pathspec before removing anything
+    let mut files = Vec::new();
+    for path in paths {
+        let prefix = worktree::repo_path(work_tree, path)?;
+        let tracked = index.paths_under(&prefix);
+
+        if tracked.is_empty() {
+            return Err(RitError::InvalidArgument(format!("pathspec '{}' did not match any files", path.display())));
+        }
+        if !recursive && tracked != [prefix] {
+            return Err(RitError::InvalidArgument(format!("not removing '{}' recursively without -r", path.display())));
+        }
+        files.extend(tracked);
+    }
+    files.sort();
+    files.dedup();
+
+    if !force {
+        check_rm_changes(repo, &index, &files, cached)?;
+    }
+
+    for file in files {
+        index.remove(&file);
+        if !cached {
+            match fs::remove_file(worktree::work_path(work_tree, &file)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
             }
-        } else {
-            eprintln!("Unsupported object type: {}", obj_type);
+        }
+        println!("rm '{}'", String::from_utf8_lossy(&file));
+    }
+
+    index.write(&repo.index_path())
+}
+
+// Refuse, as git does without -f, to remove files whose content would be
+// lost: staged content that matches neither HEAD nor the work tree, and
+// unless only the index entry goes, any staged or local change
+fn check_rm_changes(repo: &Repository, index: &Index, files: &[Vec<u8>], cached: bool) -> Result<()> {
+    let work_tree = repo.require_work_tree()?;
+    let odb = repo.odb();
+    let head_files = match repo.refs().resolve(refs::HEAD)? {
+        Some(commit) => Some(status::tree_files(odb, &odb.read_commit(&commit)?.tree)?),
+        None => None,
+    };
+
+    let (mut both, mut staged, mut local) = (Vec::new(), Vec::new(), Vec::new());
+    for file in files {
+        let Some(entry) = index.get(file) else { continue };
+        let path = worktree::work_path(work_tree, file);
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) if !meta.is_dir() => meta,
+            _ => continue,
+        };
+
+        let oid = Oid::hash(ObjectKind::Blob, &worktree::read_entry(&path, &meta)?);
+        let local_changes = worktree::file_mode(&meta) != entry.mode || oid != entry.oid;
+        let staged_changes = head_files.as_ref().map_or(true, |head| head.get(file) != Some(&(entry.mode, entry.oid)));
+        let name = String::from_utf8_lossy(file).into_owned();
+        if local_changes && staged_changes {
+            // An intent-to-add entry has no content of its own to lose
+            if !cached || !entry.is_intent_to_add() {
+                both.push(name);
+            }
+        } else if !cached && staged_changes {
+            staged.push(name);
+        } else if !cached && local_changes {
+            local.push(name);
         }
     }
+
+    let mut problems = Vec::new();
+    let mut report = |files: &[String], what: &str, hint: &str| {
+        if !files.is_empty() {
+            let (subject, verb) = if files.len() == 1 { ("file", "has") } else { ("files", "have") };
+            let list: String = files.iter().map(|file| format!("\n    {file}")).collect();
+            problems.push(format!("the following {subject} {verb} {what}:{list}\n({hint})"));
+        }
+    };
+    report(&both, "staged content different from both the\nfile and the HEAD", "use -f to force removal");
+    report(&staged, "changes staged in the index", "use --cached to keep the file, or -f to force removal");
+    report(&local, "local modifications", "use --cached to keep the file, or -f to force removal");
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(RitError::Fatal(problems.join("\n")))
+    }
+}
+
+fn commit_tree(repo: &Repository, tree: &str, parents: &[String], mut message: String) -> Result<()> {
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+
+    let config = repo.config()?;
+    let commit = Commit {
+        tree: revision::resolve_as(repo, tree, ObjectKind::Tree)?,
+        parents: parents
+            .iter()
+            .map(|p| revision::resolve_as(repo, p, ObjectKind::Commit))
+            .collect::<Result<_>>()?,
+        author: Signature::from_env("AUTHOR", &config)?,
+        committer: Signature::from_env("COMMITTER", &config)?,
+        extra_headers: Vec::new(),
+        message: message.into_bytes(),
+    };
+
+    println!("{}", repo.odb().write_object(&Object::Commit(commit))?);
+    Ok(())
+}
+
+struct LogOptions {
+    format: Format,
+    order: Order,
+    graph: bool,
+    max_count: Option<usize>,
 }
 
-fn ls_tree(name_only: bool, tree_hash: &str) {
-    let path = format!(".git/objects/{}/{}", &tree_hash[..2], &tree_hash[2..]);
-    let compressed = fs::read(path).unwrap();
+fn log(repo: &Repository, revs: &[String], paths: &[PathBuf], options: LogOptions) -> Result<()> {
+    let tips: Vec<Oid> = match revs {
+        [] => match repo.refs().resolve(refs::HEAD)? {
+            Some(oid) => vec![oid],
+            None => {
+                let branch = repo.refs().resolve_name(refs::HEAD)?;
+                let branch = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+                return Err(RitError::Fatal(format!(
+                    "your current branch '{branch}' does not have any commits yet"
+                )));
+            }
+        },
+        _ => revs.iter().map(|rev| revision::resolve_as(repo, rev, ObjectKind::Commit)).collect::<Result<_>>()?,
+    };
 
-    let mut decoder = ZlibDecoder::new(&compressed[..]);
-    let mut decompressed = Vec::new();
-    decoder.read_to_end(&mut decompressed).unwrap();
+    let paths = match repo.work_tree() {
+        Some(work_tree) => paths.iter().map(|path| worktree::repo_path(work_tree, path)).collect::<Result<_>>()?,
+        None => paths.iter().map(|path| path.to_string_lossy().into_owned().into_bytes()).collect(),
+    };
 
-    // Strip off "tree <size>\0"
-    let null_pos = decompressed.iter().position(|&b| b == 0).unwrap();
-    let mut entries = &decompressed[null_pos + 1..];
+    let entries = RevWalk::new(repo).order(options.order).paths(paths).limit(options.max_count).run(&tips)?;
+    if matches!(&options.format, Format::Template { template, terminated: true } if template.is_empty()) {
+        return Ok(());
+    }
 
-    while !entries.is_empty() {
-        // mode + filename until \0
-        let null_pos = entries.iter().position(|&b| b == 0).unwrap();
-        let header = &entries[..null_pos]; // "100644 filename.txt"
+    let decorations = pretty::Decorations::load(repo)?;
+    let mut graph = Graph::new();
+    let mut stdout = io::stdout().lock();
+    let mut ended_line = true;
 
-        let mut parts = header.splitn(2, |&b| b == b' ');
-        let mode = parts.next().unwrap();
-        let filename = parts.next().unwrap();
+    for (i, entry) in entries.iter().enumerate() {
+        let text = pretty::format_commit(repo.odb(), &entry.oid, &entry.commit, &options.format, &decorations)?;
+        let padding = graph.padding();
+        let row = options.graph.then(|| graph.next(&entry.oid, &entry.parents));
 
-        // SHA1 → 20 raw bytes after \0
-        let sha_start = null_pos + 1;
-        let sha_end = sha_start + 20;
-        let sha_bytes = &entries[sha_start..sha_end];
-        let sha = hex::encode(sha_bytes);
+        if i > 0 && !options.format.is_terminated() {
+            // A separating newline after a complete line gets the graph too,
+            // as wide as the commit that follows
+            if let (Some(row), true) = (&row, ended_line) {
+                write!(stdout, "{padding:width$} ", width = row.commit.len())?;
+            }
+            writeln!(stdout)?;
+        }
+        ended_line = text.ends_with('\n');
+
+        let Some(row) = row else {
+            stdout.write_all(text.as_bytes())?;
+            if options.format.is_terminated() {
+                writeln!(stdout)?;
+            }
+            continue;
+        };
+
+        // Lines after the first take the graph's edges as prefixes while
+        // there are any left; the rest follow on their own
+        let mut edges = row.edges.iter();
+        let mut lines = Vec::new();
+        for (n, line) in text.strip_suffix('\n').unwrap_or(&text).split('\n').enumerate() {
+            let prefix = match n {
+                0 => &row.commit,
+                _ => edges.next().unwrap_or(&row.padding),
+            };
+            lines.push(format!("{prefix} {line}"));
+        }
+        lines.extend(edges.map(|edge| format!("{edge} ")));
+        write!(stdout, "{}", lines.join("\n"))?;
 
-        if name_only {
-            println!("{}", String::from_utf8_lossy(filename));
+        if ended_line {
+            writeln!(stdout)?;
+        }
+        if options.format.is_terminated() {
+            if ended_line {
+                write!(stdout, "{} ", row.padding)?;
+            }
+            writeln!(stdout)?;
+        }
+    }
+
+    Ok(())
+}
+
+// With no base name, the pack goes to standard output
+fn pack_objects(repo: &Repository, base_name: Option<&Path>, revs: bool, options: PackOptions) -> Result<()> {
+    let mut objects = Vec::new();
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if !revs {
+            // "<oid> [<path>]", as printed by rev-list --objects
+            let (oid, name) = line.split_once(' ').unwrap_or((line, ""));
+            objects.push((Oid::from_hex(oid)?, name.as_bytes().to_vec()));
+        } else if let Some((from, to)) = line.split_once("..") {
+            let or_head = |rev: &str| if rev.is_empty() { refs::HEAD.to_string() } else { rev.to_string() };
+            exclude.push(revision::resolve(repo, &or_head(from))?);
+            include.push(revision::resolve(repo, &or_head(to))?);
+        } else if let Some(rev) = line.strip_prefix('^') {
+            exclude.push(revision::resolve(repo, rev)?);
         } else {
-            println!(
-                "{} {} {}",
-                String::from_utf8_lossy(mode),
-                sha,
-                String::from_utf8_lossy(filename)
-            );
+            include.push(revision::resolve(repo, line)?);
         }
+    }
+    if revs {
+        objects = revwalk::list_objects(repo, &include, &exclude)?;
+    }
 
-        // Move to next entry
-        entries = &entries[sha_end..];
+    let data = pack::build_pack(repo.odb(), &objects, options)?;
+    match base_name {
+        Some(base_name) => {
+            pack::write_pack_files(base_name, &data)?;
+            println!("{}", data.checksum);
+        }
+        None => io::stdout().lock().write_all(&data.pack)?,
     }
+    Ok(())
 }
 
-fn write_tree(path: Option<&Path>) -> Result<String, Box<dyn Error>> {
-    let path = path.unwrap_or_else(|| Path::new(".")); // default to current dir
+fn verify_pack(paths: &[PathBuf], verbose: bool, stat_only: bool) -> Result<()> {
+    // REF_DELTA bases outside the pack can only be found inside a repository
+    let repo = repo().ok();
+    let external = |oid: &Oid| match &repo {
+        Some(repo) => repo.odb().read_raw(oid),
+        None => Err(RitError::ObjectNotFound(oid.to_hex())),
+    };
 
-    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    for path in paths {
+        let pack = Pack::open(&path.with_extension("idx"))?;
+        let entries = pack.verify(&external)?;
 
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        let name = entry.file_name();
-        let name = name.to_string_lossy().to_string();
+        if verbose && !stat_only {
+            for entry in &entries {
+                let mut line =
+                    format!("{} {:<6} {} {} {}", entry.oid, entry.kind.as_str(), entry.size, entry.packed_size, entry.offset);
+                if let Some((depth, base)) = entry.delta {
+                    line.push_str(&format!(" {depth} {base}"));
+                }
+                println!("{line}");
+            }
+        }
 
-        if name == ".git" {
-            continue;
+        if verbose || stat_only {
+            let mut chains: Vec<usize> = Vec::new();
+            for entry in &entries {
+                let depth = entry.delta.map_or(0, |(depth, _)| depth);
+                if chains.len() <= depth {
+                    chains.resize(depth + 1, 0);
+                }
+                chains[depth] += 1;
+            }
+            println!("non delta: {} objects", chains.first().copied().unwrap_or(0));
+            for (depth, &count) in chains.iter().enumerate().skip(1) {
+                if count > 0 {
+                    println!("chain length = {depth}: {count} object{}", if count == 1 { "" } else { "s" });
+                }
+            }
         }
+        if verbose && !stat_only {
+            println!("{}: ok", pack.path().display());
+        }
+    }
 
-        if path.is_dir() {
-            // recursive write_tree for subdir
-            let hash = write_tree(Some(&path))?;
-            let mode = "40000"; // tree mode
-            let mut entry_bytes = Vec::new();
-            entry_bytes.extend_from_slice(format!("{mode} {name}\0").as_bytes());
-            entry_bytes.extend_from_slice(&hex_to_raw(&hash));
-            entries.push((name, entry_bytes));
-        } else if path.is_file() {
-            // hash file contents like `git hash-object -w`
-            let data = fs::read(&path)?;
-            let object = format!("blob {}\0", data.len());
-            let mut store = Vec::new();
-            store.extend_from_slice(object.as_bytes());
-            store.extend_from_slice(&data);
+    Ok(())
+}
 
-            let mut hasher = Sha1::new();
-            hasher.update(&store);
-            let hash = hasher.finalize();
-            let hex = hex::encode(&hash);
+fn prune(repo: &Repository, expire: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let Some(expire) = gc::parse_expire(expire)? else {
+        return Ok(());
+    };
 
-            // write blob object
-            write_object(&hex, &store)?;
+    // Read the types for the report while the objects still exist
+    let mut report = Vec::new();
+    if dry_run || verbose {
+        for oid in gc::prune(repo, expire, true)? {
+            let kind = repo.odb().read_raw(&oid).map(|(kind, _)| kind.to_string());
+            report.push(format!("{} {}", oid, kind.unwrap_or_else(|_| "unknown".to_string())));
+        }
+    }
+    if !dry_run {
+        gc::prune(repo, expire, false)?;
+    }
+
+    for line in report {
+        println!("{}", line);
+    }
+    Ok(())
+}
 
-            let mode = "100644"; // regular file
-            let mut entry_bytes = Vec::new();
-            entry_bytes.extend_from_slice(format!("{mode} {name}\0").as_bytes());
-            entry_bytes.extend_from_slice(&hash[..]);
-            entries.push((name, entry_bytes));
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DiffOutput {
+    Raw,
+    Patch,
+    Stat,
+    NameOnly,
+    NameStatus,
+}
+
+fn diff(
+    repo: &Repository,
+    revs: &[String],
+    paths: &[PathBuf],
+    cached: bool,
+    output: DiffOutput,
+    options: &PatchOptions,
+) -> Result<()> {
+    // "a..b" is the same as "a b", either side defaulting to HEAD
+    let mut trees = Vec::new();
+    for rev in revs {
+        let sides: Vec<&str> = match rev.split_once("..") {
+            Some((from, to)) => vec![from, to],
+            None => vec![rev],
+        };
+        for side in sides {
+            let side = if side.is_empty() { "HEAD" } else { side };
+            let tree = revision::resolve_as(repo, side, ObjectKind::Tree)?;
+            trees.push(status::tree_files(repo.odb(), &tree)?);
         }
     }
 
-    // ✅ Sort entries by filename (lexicographic order, ASCII)
+    let odb = repo.odb();
+    let index = Index::load(&repo.index_path())?;
+    let compares_index = trees.is_empty() || cached;
+    let (mut old, mut new, from_worktree) = match (trees.len(), cached) {
+        (0, false) => (diff::index_files(&index), diff::worktree_files(repo, &index)?, true),
+        (0, true) => {
+            let head = match repo.refs().resolve(refs::HEAD)? {
+                Some(commit) => status::tree_files(odb, &odb.read_commit(&commit)?.tree)?,
+                None => Default::default(),
+            };
+            (head, diff::index_files(&index), false)
+        }
+        (1, true) => (trees.remove(0), diff::index_files(&index), false),
+        (1, false) => (trees.remove(0), diff::worktree_files(repo, &index)?, true),
+        (2, false) => (trees.remove(0), trees.remove(0), false),
+        _ => return Err(RitError::InvalidArgument("diff takes at most two revisions, without --cached".to_string())),
+    };
+
+    let specs = pathspecs(repo, paths)?;
+
+    // Conflicted paths are only noted, but where the work tree is compared
+    // with the index, "our" side is diffed against the file too
+    let mut entries: Vec<(Vec<u8>, Option<FileChange>)> = Vec::new();
+    if compares_index {
+        for entry in index.entries().iter().filter(|entry| entry.stage() != 0) {
+            if entries.last().map(|(path, _)| path) != Some(&entry.path) {
+                entries.push((entry.path.clone(), None));
+                old.remove(&entry.path);
+                new.remove(&entry.path);
+            }
+        }
+        if from_worktree {
+            let work_tree = repo.require_work_tree()?;
+            for entry in index.entries().iter().filter(|entry| entry.stage() == 2) {
+                let file = worktree::work_path(work_tree, &entry.path);
+                old.insert(entry.path.clone(), (entry.mode, entry.oid));
+                if let Ok(meta) = fs::symlink_metadata(&file) {
+                    let oid = Oid::hash(ObjectKind::Blob, &worktree::read_entry(&file, &meta)?);
+                    new.insert(entry.path.clone(), (worktree::file_mode(&meta), oid));
+                }
+            }
+        }
+    }
+    entries.extend(diff::changes(&old, &new).into_iter().map(|change| (change.path.clone(), Some(change))));
+    entries.retain(|(path, _)| pathspec_matches(&specs, path));
     entries.sort_by(|a, b| a.0.cmp(&b.0));
 
-    // Now build the tree object
-    let mut body = Vec::new();
-    for (_, entry_bytes) in entries {
-        body.extend_from_slice(&entry_bytes);
+    let out = write_diff(repo, &entries, output, from_worktree, options)?;
+    io::stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+// Command line paths as repository paths, relative to the current directory
+// when there is a work tree
+fn pathspecs(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<Vec<u8>>> {
+    match repo.work_tree() {
+        Some(work_tree) => paths.iter().map(|path| worktree::repo_path(work_tree, path)).collect(),
+        None => Ok(paths.iter().map(|path| path.as_os_str().as_bytes().to_vec()).collect()),
     }
+}
 
-    let mut header = format!("tree {}\0", body.len()).into_bytes();
-    header.extend_from_slice(&body);
+// Whether `path` is one of `specs` or below one; no specs match everything
+fn pathspec_matches(specs: &[Vec<u8>], path: &[u8]) -> bool {
+    specs.is_empty()
+        || specs.iter().any(|spec| {
+            spec.is_empty() || path == &spec[..] || (path.starts_with(spec) && path.get(spec.len()) == Some(&b'/'))
+        })
+}
 
-    let mut hasher = Sha1::new();
-    hasher.update(&header);
-    let tree_hash = hasher.finalize();
-    let hex = hex::encode(&tree_hash);
+// The changes in `entries` (a `None` change being an unmerged path) in the
+// format `output` asks for. Only the new side of a change may come from the
+// work tree
+fn write_diff(
+    repo: &Repository,
+    entries: &[(Vec<u8>, Option<FileChange>)],
+    output: DiffOutput,
+    from_worktree: bool,
+    options: &PatchOptions,
+) -> Result<Vec<u8>> {
+    let odb = repo.odb();
+    let quote = |path: &[u8]| quote::quote_path(path, options.quote_high);
+    let mut out = Vec::new();
+    match output {
+        DiffOutput::Raw => {
+            let zero = Oid::ZERO;
+            for (path, change) in entries {
+                let Some(change) = change else {
+                    out.extend_from_slice(format!(":000000 000000 {zero} {zero} U\t").as_bytes());
+                    out.extend_from_slice(&quote(path));
+                    out.push(b'\n');
+                    continue;
+                };
+                let (old_mode, old_oid) = change.old.unwrap_or((0, zero));
+                let (new_mode, new_oid) = change.new.unwrap_or((0, zero));
+                let line = format!(":{old_mode:06o} {new_mode:06o} {old_oid} {new_oid} {}\t", change.status());
+                out.extend_from_slice(line.as_bytes());
+                out.extend_from_slice(&quote(change.old_path()));
+                if change.rename.is_some() {
+                    out.push(b'\t');
+                    out.extend_from_slice(&quote(path));
+                }
+                out.push(b'\n');
+            }
+        }
+        DiffOutput::Patch => {
+            for (path, change) in entries {
+                match change {
+                    None => {
+                        out.extend_from_slice(b"* Unmerged path ");
+                        out.extend_from_slice(&quote(path));
+                        out.push(b'\n');
+                    }
+                    Some(change) => {
+                        let old_data = diff::read_side(repo, change.old_path(), change.old, false)?;
+                        let new_data = diff::read_side(repo, path, change.new, from_worktree)?;
+                        diff::write_patch(&mut out, odb, change, &old_data, &new_data, options)?;
+                    }
+                }
+            }
+        }
+        DiffOutput::Stat => {
+            let mut stats = Vec::new();
+            for (path, change) in entries {
+                let (name, counts) = match change {
+                    None => (quote(path), StatCounts::Unmerged),
+                    Some(change) => {
+                        let old_data = diff::read_side(repo, change.old_path(), change.old, false)?;
+                        let new_data = diff::read_side(repo, path, change.new, from_worktree)?;
+                        (stat_name(change, options), StatCounts::compute(&old_data, &new_data, options.algorithm))
+                    }
+                };
+                stats.push(StatEntry { name, counts });
+            }
+            if !stats.is_empty() {
+                diff::write_stat(&mut out, &stats, STAT_WIDTH);
+            }
+        }
+        DiffOutput::NameOnly | DiffOutput::NameStatus => {
+            for (path, change) in entries {
+                if output == DiffOutput::NameStatus {
+                    match change {
+                        None => out.push(b'U'),
+                        Some(change) => out.extend_from_slice(change.status().as_bytes()),
+                    }
+                    out.push(b'\t');
+                    if let Some(change) = change.as_ref().filter(|change| change.rename.is_some()) {
+                        out.extend_from_slice(&quote(change.old_path()));
+                        out.push(b'\t');
+                    }
+                }
+                out.extend_from_slice(&quote(path));
+                out.push(b'\n');
+            }
+        }
+    }
+    Ok(out)
+}
 
-    write_object(&hex, &header)?;
+// How a diffstat names a change: "dir/{old => new}" for renames and copies,
+// unless either name needs quoting
+fn stat_name(change: &FileChange, options: &PatchOptions) -> Vec<u8> {
+    let quote = |path: &[u8]| quote::quote_path(path, options.quote_high);
+    if change.rename.is_none() {
+        return quote(&change.path);
+    }
+    let (from, to) = (quote(change.old_path()), quote(&change.path));
+    if from == change.old_path() && to == change.path {
+        rename::stat_name(&from, &to)
+    } else {
+        [&from[..], b" => ", &to[..]].concat()
+    }
+}
 
-    Ok(hex)
+struct DiffTreeOptions {
+    output: DiffOutput,
+    tree: TreeDiffOptions,
+    renames: Option<RenameOptions>,
+    find_copies_harder: bool,
+    root: bool,
 }
 
-fn write_object(hash: &str, data: &[u8]) -> io::Result<()> {
-    let dir = format!(".git/objects/{}", &hash[..2]);
-    let file = format!("{}/{}", dir, &hash[2..]);
+fn diff_tree(
+    repo: &Repository,
+    tree_ish: &str,
+    other: Option<&str>,
+    paths: &[PathBuf],
+    options: DiffTreeOptions,
+) -> Result<()> {
+    let odb = repo.odb();
 
-    fs::create_dir_all(&dir)?;
+    // A lone commit is compared with its first parent and named before its
+    // changes; merges and (without --root) root commits show nothing
+    let (old, new, commit) = match other {
+        Some(other) => {
+            let old = revision::resolve_as(repo, tree_ish, ObjectKind::Tree)?;
+            (Some(old), revision::resolve_as(repo, other, ObjectKind::Tree)?, None)
+        }
+        None => {
+            let oid = revision::resolve_as(repo, tree_ish, ObjectKind::Commit)?;
+            let commit = odb.read_commit(&oid)?;
+            match commit.parents[..] {
+                [] if options.root => (None, commit.tree, Some(oid)),
+                [parent] => (Some(odb.read_commit(&parent)?.tree), commit.tree, Some(oid)),
+                _ => return Ok(()),
+            }
+        }
+    };
+
+    let specs = pathspecs(repo, paths)?;
+    let tree_options = TreeDiffOptions { paths: specs.clone(), ..options.tree };
+    let mut changes = diff::diff_trees(odb, old.as_ref(), Some(&new), &tree_options)?;
+    if let Some(renames) = &options.renames {
+        // Files the change left alone can be copy sources too
+        let mut unchanged = Vec::new();
+        if let (Some(old), true) = (&old, options.find_copies_harder) {
+            let changed: HashSet<&[u8]> = changes.iter().map(|change| &change.path[..]).collect();
+            for (path, (mode, oid)) in status::tree_files(odb, old)? {
+                if !changed.contains(&path[..]) && pathspec_matches(&specs, &path) {
+                    unchanged.push((path, mode, oid));
+                }
+            }
+        }
+        changes = rename::detect_renames(odb, changes, renames, &unchanged)?;
+    }
+
+    // Patches and diffstats are about files, even with -t
+    if matches!(options.output, DiffOutput::Patch | DiffOutput::Stat) {
+        changes.retain(|change| change.old.or(change.new).is_some_and(|(mode, _)| mode != MODE_TREE));
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut out = commit.map_or_else(Vec::new, |oid| format!("{oid}\n").into_bytes());
+    let entries: Vec<_> = changes.into_iter().map(|change| (change.path.clone(), Some(change))).collect();
+    let patch_options = PatchOptions { algorithm: Algorithm::Myers, context: 3, quote_high: quote_path_setting(repo)? };
+    out.extend(write_diff(repo, &entries, options.output, false, &patch_options)?);
+    io::stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusFormat {
+    Long,
+    Short,
+    Porcelain,
+    PorcelainV2,
+}
+
+fn status(repo: &Repository, format: StatusFormat, show_branch: bool, untracked: UntrackedMode, nul: bool) -> Result<()> {
+    let status = status::status(repo, untracked)?;
+    let quote_high = quote_path_setting(repo)?;
+    let mut out = Vec::new();
+
+    // Porcelain paths are relative to the top of the work tree, others to
+    // the current directory
+    let cwd = worktree::repo_path(repo.require_work_tree()?, Path::new("."))?;
+    let show_path = |path: &[u8]| {
+        let path = match format {
+            StatusFormat::Long | StatusFormat::Short => relative_path(path, &cwd),
+            _ => path.to_vec(),
+        };
+        if nul {
+            path
+        } else {
+            quote::quote_path(&path, quote_high)
+        }
+    };
+    let end = if nul { b'\0' } else { b'\n' };
+    let branch = status.branch.as_deref().map(|name| name.strip_prefix("refs/heads/").unwrap_or(name));
+
+    match format {
+        StatusFormat::Long => return long_status(repo, &status, untracked, show_path),
+        StatusFormat::Short | StatusFormat::Porcelain => {
+            if show_branch {
+                match (branch, status.head) {
+                    (Some(branch), Some(_)) => out.extend_from_slice(format!("## {branch}").as_bytes()),
+                    (Some(branch), None) => out.extend_from_slice(format!("## No commits yet on {branch}").as_bytes()),
+                    (None, _) => out.extend_from_slice(b"## HEAD (no branch)"),
+                }
+                out.push(end);
+            }
+            for file in &status.files {
+                let code = match file.conflict_code() {
+                    Some(code) => code.to_string(),
+                    None => [file.staged, file.unstaged].map(|c| c.map_or(' ', Change::letter)).iter().collect(),
+                };
+                out.extend_from_slice(format!("{code} ").as_bytes());
+                out.extend_from_slice(&show_path(&file.path));
+                out.push(end);
+            }
+            for path in &status.untracked {
+                out.extend_from_slice(b"?? ");
+                out.extend_from_slice(&show_path(path));
+                out.push(end);
+            }
+        }
+        StatusFormat::PorcelainV2 => {
+            if show_branch {
+                let oid = status.head.map_or("(initial)".to_string(), |oid| oid.to_hex());
+                out.extend_from_slice(format!("# branch.oid {oid}").as_bytes());
+                out.push(end);
+                out.extend_from_slice(format!("# branch.head {}", branch.unwrap_or("(detached)")).as_bytes());
+                out.push(end);
+            }
+            let mode = |entry: Option<(u32, Oid)>| format!("{:06o}", entry.map_or(0, |(mode, _)| mode));
+            let oid = |entry: Option<(u32, Oid)>| entry.map_or(Oid::ZERO, |(_, oid)| oid);
+            let worktree_mode = |file: &status::FileStatus| format!("{:06o}", file.worktree_mode.unwrap_or(0));
+            for file in &status.files {
+                let line = match (file.conflict, file.conflict_code()) {
+                    (Some([base, ours, theirs]), Some(code)) => format!(
+                        "u {code} N... {} {} {} {} {} {} {} ",
+                        mode(base),
+                        mode(ours),
+                        mode(theirs),
+                        worktree_mode(file),
+                        oid(base),
+                        oid(ours),
+                        oid(theirs)
+                    ),
+                    _ => {
+                        let code: String = [file.staged, file.unstaged].map(|c| c.map_or('.', Change::letter)).iter().collect();
+                        format!(
+                            "1 {code} N... {} {} {} {} {} ",
+                            mode(file.head),
+                            mode(file.index),
+                            worktree_mode(file),
+                            oid(file.head),
+                            oid(file.index)
+                        )
+                    }
+                };
+                out.extend_from_slice(line.as_bytes());
+                out.extend_from_slice(&show_path(&file.path));
+                out.push(end);
+            }
+            for path in &status.untracked {
+                out.extend_from_slice(b"? ");
+                out.extend_from_slice(&show_path(path));
+                out.push(end);
+            }
+        }
+    }
+
+    io::stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+fn long_status(
+    repo: &Repository,
+    status: &status::Status,
+    untracked: UntrackedMode,
+    show_path: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<()> {
+    let mut out = Vec::new();
+    let label = |change: Change| match change {
+        Change::Added => "new file:",
+        Change::Modified => "modified:",
+        Change::Deleted => "deleted:",
+        Change::TypeChanged => "typechange:",
+    };
+    let section = |out: &mut Vec<u8>, title: &str, hint: Option<&str>, lines: Vec<(String, &[u8])>| {
+        if lines.is_empty() {
+            return;
+        }
+        out.extend_from_slice(format!("{title}\n").as_bytes());
+        if let Some(hint) = hint {
+            out.extend_from_slice(format!("  ({hint})\n").as_bytes());
+        }
+        for (prefix, path) in lines {
+            out.extend_from_slice(format!("\t{prefix}").as_bytes());
+            out.extend_from_slice(&show_path(path));
+            out.push(b'\n');
+        }
+        out.push(b'\n');
+    };
+
+    match (&status.branch, status.head) {
+        (Some(branch), _) => {
+            out.extend_from_slice(format!("On branch {}\n", branch.strip_prefix("refs/heads/").unwrap_or(branch)).as_bytes())
+        }
+        (None, Some(head)) => out.extend_from_slice(
+            format!("HEAD detached at {}\n", repo.odb().abbreviate(&head, DEFAULT_ABBREV)?).as_bytes(),
+        ),
+        (None, None) => out.extend_from_slice(b"Not currently on any branch.\n"),
+    }
+    if status.head.is_none() {
+        out.extend_from_slice(b"\nNo commits yet\n\n");
+    }
+
+    let staged: Vec<(String, &[u8])> = status
+        .files
+        .iter()
+        .filter_map(|file| file.staged.map(|change| (format!("{:<12}", label(change)), &file.path[..])))
+        .collect();
+    let unmerged: Vec<(String, &[u8])> = status
+        .files
+        .iter()
+        .filter_map(|file| {
+            let label = match file.conflict_code()? {
+                "DD" => "both deleted:",
+                "AU" => "added by us:",
+                "UD" => "deleted by them:",
+                "UA" => "added by them:",
+                "DU" => "deleted by us:",
+                "AA" => "both added:",
+                _ => "both modified:",
+            };
+            Some((format!("{label:<17}"), &file.path[..]))
+        })
+        .collect();
+    let unstaged: Vec<(String, &[u8])> = status
+        .files
+        .iter()
+        .filter_map(|file| file.unstaged.map(|change| (format!("{:<12}", label(change)), &file.path[..])))
+        .collect();
+    let untracked_files: Vec<(String, &[u8])> = status.untracked.iter().map(|path| (String::new(), &path[..])).collect();
+
+    let has_staged = !staged.is_empty();
+    let has_unstaged = !unstaged.is_empty() || !unmerged.is_empty();
+    let has_untracked = !untracked_files.is_empty();
+    let unstage_hint = status.head.is_none().then_some("use \"rit rm --cached <file>...\" to unstage");
+    section(&mut out, "Changes to be committed:", unstage_hint, staged);
+    section(&mut out, "Unmerged paths:", Some("use \"rit add/rm <file>...\" as appropriate to mark resolution"), unmerged);
+    section(
+        &mut out,
+        "Changes not staged for commit:",
+        Some("use \"rit add/rm <file>...\" to update what will be committed"),
+        unstaged,
+    );
+    section(
+        &mut out,
+        "Untracked files:",
+        Some("use \"rit add <file>...\" to include in what will be committed"),
+        untracked_files,
+    );
+    if untracked == UntrackedMode::No && has_staged {
+        out.extend_from_slice(b"Untracked files not listed (use -u option to show untracked files)\n");
+    }
+
+    let summary = if has_staged {
+        None
+    } else if has_unstaged {
+        Some("no changes added to commit (use \"rit add\")")
+    } else if has_untracked {
+        Some("nothing added to commit but untracked files present (use \"rit add\" to track)")
+    } else if status.head.is_none() {
+        Some("nothing to commit (create/copy files and use \"rit add\" to track)")
+    } else if untracked == UntrackedMode::No {
+        Some("nothing to commit (use -u to show untracked files)")
+    } else {
+        Some("nothing to commit, working tree clean")
+    };
+    if let Some(summary) = summary {
+        out.extend_from_slice(format!("{summary}\n").as_bytes());
+    }
+
+    io::stdout().lock().write_all(&out)?;
+    Ok(())
+}
+
+// `path` relative to the directory `cwd`, both repository paths
+fn relative_path(path: &[u8], cwd: &[u8]) -> Vec<u8> {
+    let mut path_parts: Vec<&[u8]> = path.split(|&b| b == b'/').collect();
+    let mut cwd_parts: Vec<&[u8]> = cwd.split(|&b| b == b'/').filter(|part| !part.is_empty()).collect();
+
+    // Untracked directories keep their trailing slash
+    let common = path_parts.iter().zip(&cwd_parts).take_while(|(a, b)| a == b).count();
+    let common = common.min(path_parts.len() - 1);
+    path_parts.drain(..common);
+    cwd_parts.drain(..common);
+
+    let mut parts: Vec<&[u8]> = vec![b".."; cwd_parts.len()];
+    parts.extend(path_parts);
+    parts.join(&b'/')
+}
+
+// Returns whether any path was ignored
+fn check_ignore(repo: &Repository, paths: &[PathBuf], verbose: bool, non_matching: bool, no_index: bool) -> Result<bool> {
+    let work_tree = repo.require_work_tree()?;
+    let index = if no_index { Index::default() } else { Index::load(&repo.index_path())? };
+    let mut ignore = Ignore::for_repo(repo)?;
+    let quote_high = quote_path_setting(repo)?;
+    let mut stdout = io::stdout().lock();
+
+    let mut any_ignored = false;
+    for path in paths {
+        let relative = worktree::repo_path(work_tree, path)?;
+        let is_dir = path.as_os_str().as_bytes().ends_with(b"/") || worktree::work_path(work_tree, &relative).is_dir();
+
+        // Tracked files are never ignored
+        let pattern = if index.get(&relative).is_some() { None } else { ignore.matching(&relative, is_dir)? };
+        let ignored = pattern.is_some_and(|pattern| !pattern.is_negated());
+        // git counts a negated match shown by -v as a match
+        any_ignored |= ignored || (verbose && pattern.is_some());
 
-    if !Path::new(&file).exists() {
-        let f = fs::File::create(&file)?;
-        let mut encoder = ZlibEncoder::new(f, Compression::default());
-        encoder.write_all(data)?;
-        encoder.finish()?;
+        match pattern {
+            Some(pattern) if verbose => {
+                write!(stdout, "{}:{}:{}\t", pattern.source, pattern.line, pattern.text)?
+            }
+            None if verbose && non_matching => write!(stdout, "::\t")?,
+            _ if ignored => {}
+            _ => continue,
+        }
+        stdout.write_all(&quote::quote_path(path.as_os_str().as_bytes(), quote_high))?;
+        writeln!(stdout)?;
+    }
+
+    Ok(any_ignored)
+}
+
+fn fsck(repo: &Repository, options: FsckOptions) -> Result<()> {
+    let findings = fsck::fsck(repo, options)?;
+
+    // Problems go to stderr like git's; dangling objects and notices to stdout
+    let mut errors = 0;
+    for finding in &findings {
+        match finding.severity {
+            Severity::Error => {
+                errors += 1;
+                eprintln!("{finding}");
+            }
+            Severity::Warning => eprintln!("{finding}"),
+            Severity::Info => println!("{finding}"),
+        }
     }
 
+    if errors > 0 {
+        return Err(RitError::FsckFailed(errors));
+    }
     Ok(())
 }
 
-// Convert hex string to raw 20-byte SHA-1
-fn hex_to_raw(hex: &str) -> Vec<u8> {
-    hex::decode(hex).expect("Invalid hex")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(repo: &Repository, entries: &[(&str, &str, Oid)]) -> Oid {
+        let mut data = Vec::new();
+        for (mode, name, oid) in entries {
+            data.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+            data.extend_from_slice(oid.as_bytes());
+        }
+        repo.odb().write_raw(ObjectKind::Tree, &data).unwrap()
+    }
+
+    #[test]
+    fn ls_tree_filters_like_git() {
+        let dir = std::env::temp_dir().join(format!("rit-ls-tree-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        // a.txt, dir/b.txt, dir/sub/c
+        let blob = |data: &str| repo.odb().write_raw(ObjectKind::Blob, data.as_bytes()).unwrap();
+        let sub = tree(&repo, &[("100644", "c", blob("c\n"))]);
+        let dir_tree = tree(&repo, &[("100644", "b.txt", blob("b\n")), ("40000", "sub", sub)]);
+        let root = tree(&repo, &[("100644", "a.txt", blob("hi\n")), ("40000", "dir", dir_tree)]);
+        assert_eq!(root.to_hex(), "c07700b9c2bcac14973bdbc2834a685c2bd3d06a");
+
+        let ls = |flags: &str, paths: &[&str]| {
+            let options = LsTreeOptions {
+                recursive: flags.contains('r'),
+                show_trees: flags.contains('t') || (flags.contains('r') && flags.contains('d')),
+                trees_only: flags.contains('d'),
+                long: flags.contains('l'),
+                name_only: flags.contains('n'),
+                abbrev: None,
+                null_terminated: false,
+                full_name: true,
+                full_tree: true,
+            };
+            let paths: Vec<Vec<u8>> = paths.iter().map(|path| path.as_bytes().to_vec()).collect();
+            let tree = repo.odb().read_tree(&root).unwrap();
+            let mut out = Vec::new();
+            list_tree(&repo, &tree, b"", &paths, &options, &|path| path.to_vec(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            ls("", &[]),
+            "100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057\ta.txt\n\
+             040000 tree 31928a3936f90df7a77a3337781b401dee0c4168\tdir\n"
+        );
+        assert_eq!(
+            ls("l", &["a.txt", "dir"]),
+            "100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057       3\ta.txt\n\
+             040000 tree 31928a3936f90df7a77a3337781b401dee0c4168       -\tdir\n"
+        );
+        assert_eq!(ls("rn", &[]), "a.txt\ndir/b.txt\ndir/sub/c\n");
+        assert_eq!(ls("rtn", &[]), "a.txt\ndir\ndir/b.txt\ndir/sub\ndir/sub/c\n");
+        assert_eq!(ls("dn", &[]), "dir\n");
+        assert_eq!(ls("rdn", &[]), "dir\ndir/sub\n");
+        assert_eq!(ls("n", &["dir/sub"]), "dir/sub\n");
+        assert_eq!(ls("n", &["dir/"]), "dir/b.txt\ndir/sub\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rm_refuses_to_lose_changes() {
+        let dir = std::env::temp_dir().join(format!("rit-rm-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+        for name in ["clean", "modified", "staged"] {
+            std::fs::write(dir.join(name), format!("{name}\n")).unwrap();
+        }
+        let mut index = Index::default();
+        worktree::add_to_index(&repo, &mut index, None, &dir).unwrap();
+        let tree = index.write_tree(repo.odb()).unwrap();
+        let commit = format!("tree {tree}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nfirst\n");
+        let commit = repo.odb().write_raw(ObjectKind::Commit, commit.as_bytes()).unwrap();
+        repo.refs().update(refs::HEAD, &commit, None, "commit (initial): first").unwrap();
+
+        std::fs::write(dir.join("staged"), "staged again\n").unwrap();
+        worktree::add_to_index(&repo, &mut index, None, &dir.join("staged")).unwrap();
+        index.write(&repo.index_path()).unwrap();
+        std::fs::write(dir.join("modified"), "modified again\n").unwrap();
+
+        // Nothing is removed when any file would lose changes or any path is unknown
+        for (files, cached) in [(&["clean", "modified"][..], false), (&["staged"], false), (&["clean", "nope"], true)] {
+            let paths: Vec<PathBuf> = files.iter().map(|file| dir.join(file)).collect();
+            assert!(rm(&repo, &paths, cached, false, false).is_err(), "{files:?}");
+        }
+        assert!(dir.join("clean").exists());
+        assert_eq!(Index::load(&repo.index_path()).unwrap().entries().len(), 3);
+
+        rm(&repo, &[dir.join("clean"), dir.join("staged")], true, false, false).unwrap();
+        rm(&repo, &[dir.join("modified")], false, false, true).unwrap();
+        assert!(dir.join("clean").exists() && dir.join("staged").exists() && !dir.join("modified").exists());
+        assert!(Index::load(&repo.index_path()).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_on_unborn_branch_is_fatal() {
+        let dir = std::env::temp_dir().join(format!("rit-log-{}", process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        let options = LogOptions { format: Format::Medium, order: Order::Date, graph: false, max_count: None };
+        let err = log(&repo, &[], &[], options).unwrap_err();
+        assert_eq!(err.to_string(), "your current branch 'main' does not have any commits yet");
+        assert_eq!(err.exit_code(), 128);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
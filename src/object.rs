@@ -0,0 +1,771 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::error::{Result, RitError};
+use crate::oid::Oid;
+
+/// The four kinds of object stored in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = RitError;
+
+    fn from_str(s: &str) -> Result<ObjectKind> {
+        match s {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => Err(malformed(format!("unknown object type: {s}"))),
+        }
+    }
+}
+
+/// A parsed object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Object {
+    Blob(Vec<u8>),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+fn malformed(reason: impl Into<String>) -> RitError {
+    RitError::MalformedObject(reason.into())
+}
+
+impl Object {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Blob(_) => ObjectKind::Blob,
+            Object::Tree(_) => ObjectKind::Tree,
+            Object::Commit(_) => ObjectKind::Commit,
+            Object::Tag(_) => ObjectKind::Tag,
+        }
+    }
+
+    /// Parse an object body (without the "<kind> <size>\0" header).
+    pub fn parse(kind: ObjectKind, data: &[u8]) -> Result<Object> {
+        Ok(match kind {
+            ObjectKind::Blob => Object::Blob(data.to_vec()),
+            ObjectKind::Tree => Object::Tree(Tree::parse(data)?),
+            ObjectKind::Commit => Object::Commit(Commit::parse(data)?),
+            ObjectKind::Tag => Object::Tag(Tag::parse(data)?),
+        })
+    }
+
+    /// Serialize the object body (without header).
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Object::Blob(data) => data.clone(),
+            Object::Tree(tree) => tree.serialize(),
+            Object::Commit(commit) => commit.serialize(),
+            Object::Tag(tag) => tag.serialize(),
+        }
+    }
+
+    pub fn oid(&self) -> Oid {
+        Oid::hash(self.kind(), &self.serialize())
+    }
+}
+
+/// Mode of a subtree entry.
+pub const MODE_TREE: u32 = 0o40000;
+/// Mode of a regular, non-executable file.
+pub const MODE_FILE: u32 = 0o100644;
+/// Mode of an executable file.
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+/// Mode of a symbolic link.
+pub const MODE_SYMLINK: u32 = 0o120000;
+/// Mode of a submodule commit.
+pub const MODE_GITLINK: u32 = 0o160000;
+
+/// One entry in a tree object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub oid: Oid,
+}
+
+impl TreeEntry {
+    /// Object type the entry points at, derived from its mode.
+    pub fn kind(&self) -> ObjectKind {
+        match self.mode {
+            MODE_TREE => ObjectKind::Tree,
+            MODE_GITLINK => ObjectKind::Commit,
+            _ => ObjectKind::Blob,
+        }
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+}
+
+/// Git's order for tree entries: names compare bytewise, except that a
+/// subtree compares as if its name ended in '/'.
+pub fn tree_order(a: &[u8], a_is_tree: bool, b: &[u8], b_is_tree: bool) -> Ordering {
+    let a = a.iter().chain(a_is_tree.then_some(&b'/'));
+    let b = b.iter().chain(b_is_tree.then_some(&b'/'));
+    a.cmp(b)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    pub fn parse(mut data: &[u8]) -> Result<Tree> {
+        let mut entries = Vec::new();
+
+        while !data.is_empty() {
+            // "<mode> <name>\0<20 byte sha>"
+            let space = data.iter().position(|&b| b == b' ').ok_or_else(|| malformed("truncated tree entry"))?;
+            let null = data.iter().position(|&b| b == 0).ok_or_else(|| malformed("truncated tree entry"))?;
+            if null < space {
+                return Err(malformed("tree entry has no mode"));
+            }
+
+            let mode = std::str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|m| u32::from_str_radix(m, 8).ok())
+                .ok_or_else(|| malformed("invalid tree entry mode"))?;
+            let name = data[space + 1..null].to_vec();
+
+            let sha_end = null + 1 + Oid::LEN;
+            if data.len() < sha_end {
+                return Err(malformed("truncated tree entry"));
+            }
+            let oid = Oid::from_bytes(&data[null + 1..sha_end])?;
+
+            entries.push(TreeEntry { mode, name, oid });
+            data = &data[sha_end..];
+        }
+
+        Ok(Tree { entries })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for entry in &self.entries {
+            body.extend_from_slice(format!("{:o} ", entry.mode).as_bytes());
+            body.extend_from_slice(&entry.name);
+            body.push(0);
+            body.extend_from_slice(entry.oid.as_bytes());
+        }
+        body
+    }
+}
+
+/// Collects entries for a new tree and puts them in git's canonical order,
+/// so equal contents always hash to the same tree. Names git would refuse
+/// to check out are rejected.
+#[derive(Clone, Debug, Default)]
+pub struct TreeBuilder {
+    entries: Vec<TreeEntry>,
+    names: HashSet<Vec<u8>>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> TreeBuilder {
+        TreeBuilder::default()
+    }
+
+    /// Add an entry, failing if its name is empty, `.`, `..`, `.git` (in
+    /// any case), contains `/` or NUL, or is already taken.
+    pub fn insert(&mut self, entry: TreeEntry) -> Result<()> {
+        let name = &entry.name;
+        let invalid = matches!(&name[..], b"" | b"." | b"..")
+            || name.eq_ignore_ascii_case(b".git")
+            || name.iter().any(|&b| b == b'/' || b == 0);
+        if invalid {
+            return Err(RitError::InvalidArgument(format!(
+                "invalid tree entry name '{}'",
+                String::from_utf8_lossy(name)
+            )));
+        }
+        if !self.names.insert(name.clone()) {
+            return Err(RitError::InvalidArgument(format!(
+                "duplicate tree entry '{}'",
+                String::from_utf8_lossy(name)
+            )));
+        }
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// The tree with its entries sorted.
+    pub fn build(mut self) -> Tree {
+        self.entries.sort_by(|a, b| tree_order(&a.name, a.is_tree(), &b.name, b.is_tree()));
+        Tree { entries: self.entries }
+    }
+}
+
+/// Author, committer or tagger line: "Name <email> <seconds> <+hhmm>".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    /// Offset from UTC in minutes.
+    pub offset: i32,
+}
+
+impl Signature {
+    /// Build a signature for `role` ("AUTHOR" or "COMMITTER") the way git
+    /// does: the name and email from GIT_<ROLE>_NAME/EMAIL, else from
+    /// `<role>.name`/`<role>.email` or `user.name`/`user.email` in `config`;
+    /// the time from GIT_<ROLE>_DATE, else now in the local timezone. It is
+    /// an error if no name or email is set.
+    pub fn from_env(role: &str, config: &Config) -> Result<Signature> {
+        match (identity(role, "name", config), identity(role, "email", config)) {
+            (Some(name), Some(email)) => Signature::with_date(role, name, email),
+            _ => Err(RitError::UnknownIdentity(role.to_string())),
+        }
+    }
+
+    /// Like `from_env`, but a missing name or email falls back to the login
+    /// name, as git does for reflog entries.
+    pub fn from_env_or_login(role: &str, config: &Config) -> Result<Signature> {
+        let login = env::var("USER").or_else(|_| env::var("LOGNAME")).unwrap_or_else(|_| "unknown".to_string());
+        let name = identity(role, "name", config).unwrap_or_else(|| login.clone());
+        let email = identity(role, "email", config).unwrap_or_else(|| format!("{login}@localhost"));
+        Signature::with_date(role, name, email)
+    }
+
+    fn with_date(role: &str, name: String, email: String) -> Result<Signature> {
+        let (time, offset) = match env::var(format!("GIT_{role}_DATE")) {
+            Ok(date) => {
+                parse_date_spec(&date).ok_or_else(|| RitError::InvalidArgument(format!("invalid date: {date}")))?
+            }
+            Err(_) => {
+                let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+                (secs, local_offset(secs))
+            }
+        };
+
+        Ok(Signature { name, email, time, offset })
+    }
+
+    pub fn parse(line: &str) -> Result<Signature> {
+        let bad = || malformed(format!("malformed signature: {line}"));
+        let open = line.find('<').ok_or_else(bad)?;
+        let close = line.rfind('>').ok_or_else(bad)?;
+        if close < open {
+            return Err(bad());
+        }
+
+        let name = line[..open].trim_end().to_string();
+        let email = line[open + 1..close].to_string();
+        let (time, offset) = parse_date(line[close + 1..].trim())?;
+
+        Ok(Signature { name, email, time, offset })
+    }
+
+    /// Timezone in git's "+hhmm" form.
+    pub fn format_offset(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.abs();
+        format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <{}> {} {}", self.name, self.email, self.time, self.format_offset())
+    }
+}
+
+// GIT_<ROLE>_<FIELD>, then <role>.<field> and user.<field> from the config;
+// the EMAIL variable is git's last resort for the address
+fn identity(role: &str, field: &str, config: &Config) -> Option<String> {
+    let configured = |key: String| config.get(&key).map(str::to_string);
+    env::var(format!("GIT_{role}_{}", field.to_ascii_uppercase()))
+        .ok()
+        .or_else(|| configured(format!("{}.{field}", role.to_ascii_lowercase())))
+        .or_else(|| configured(format!("user.{field}")))
+        .or_else(|| if field == "email" { env::var("EMAIL").ok() } else { None })
+        .filter(|value| !value.trim().is_empty())
+}
+
+// "<seconds> <+hhmm>"
+fn parse_date(date: &str) -> Result<(i64, i32)> {
+    let mut parts = date.split_whitespace();
+    let time = parts
+        .next()
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| malformed(format!("invalid date: {date}")))?;
+
+    let offset = match parts.next() {
+        Some(tz) => parse_offset(tz).ok_or_else(|| malformed(format!("invalid timezone: {tz}")))?,
+        None => 0,
+    };
+
+    Ok((time, offset))
+}
+
+fn parse_offset(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+// A date as git accepts it in GIT_AUTHOR_DATE and GIT_COMMITTER_DATE: its
+// internal "<seconds> <+hhmm>" (optionally "@<seconds>"), RFC 2822 such as
+// "Thu, 07 Apr 2005 22:13:13 +0200", or ISO 8601 such as
+// "2005-04-07T22:13:13+02:00". Without a timezone the local one is used.
+fn parse_date_spec(date: &str) -> Option<(i64, i32)> {
+    let raw = date.trim().strip_prefix('@').unwrap_or(date.trim());
+    if raw.split_whitespace().count() <= 2 && raw.split_whitespace().next()?.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(parsed) = parse_date(raw) {
+            return Some(parsed);
+        }
+    }
+
+    let (mut year, mut month, mut day) = (None, None, None);
+    let (mut clock, mut offset) = (None, None);
+    let tokens = date.split(|c: char| c.is_whitespace() || c == ',').filter(|t| !t.is_empty());
+    for token in tokens.flat_map(split_iso_datetime) {
+        let digits = token.bytes().all(|b| b.is_ascii_digit());
+        let lower = token.to_ascii_lowercase();
+        let name = |names: &[&str]| names.iter().position(|n| lower.len() >= 3 && n.starts_with(&lower));
+        if let Some(ymd) = parse_iso_day(token) {
+            (year, month, day) = (Some(ymd.0), Some(ymd.1), Some(ymd.2));
+        } else if token.contains(':') && token.as_bytes()[0].is_ascii_digit() {
+            let (time, tz) = parse_clock(token)?;
+            clock = Some(time);
+            offset = tz.or(offset);
+        } else if let Some(tz) = parse_zone(token) {
+            offset = Some(tz);
+        } else if let Some(index) = name(&MONTHS) {
+            month = Some(index as i64 + 1);
+        } else if name(&WEEKDAYS).is_some() {
+            continue;
+        } else if digits && token.len() <= 2 {
+            day = Some(token.parse().ok()?);
+        } else if digits && token.len() == 4 {
+            year = Some(token.parse().ok()?);
+        } else {
+            return None;
+        }
+    }
+
+    let (year, month, day) = (year?, month?, day?);
+    let (hour, minute, second) = clock.unwrap_or((0, 0, 0));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let wall = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    let offset = offset.unwrap_or_else(|| local_offset(wall - local_offset(wall) as i64 * 60));
+    Some((wall - offset as i64 * 60, offset))
+}
+
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+const WEEKDAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+// "2005-04-07T22:13:13" is a date and a time
+fn split_iso_datetime(token: &str) -> Vec<&str> {
+    match token.split_once('T') {
+        Some((day, time)) if parse_iso_day(day).is_some() => vec![day, time],
+        _ => vec![token],
+    }
+}
+
+// "YYYY-MM-DD"
+fn parse_iso_day(token: &str) -> Option<(i64, i64, i64)> {
+    let mut parts = token.split('-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let number = |s: &str| s.bytes().all(|b| b.is_ascii_digit()).then(|| s.parse().ok()).flatten();
+    Some((number(year)?, number(month)?, number(day)?))
+}
+
+// "HH:MM[:SS[.fraction]]" with an optional attached zone, e.g. "Z" or "+02:00"
+fn parse_clock(token: &str) -> Option<((i64, i64, i64), Option<i32>)> {
+    let end = token.find(|c: char| !c.is_ascii_digit() && c != ':' && c != '.').unwrap_or(token.len());
+    let (time, zone) = token.split_at(end);
+    let time = time.split('.').next()?;
+
+    let field = |f: &str| (f.len() == 2).then(|| f.parse().ok()).flatten();
+    let fields: Vec<i64> = time.split(':').map(field).collect::<Option<_>>()?;
+    let clock = match fields[..] {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return None,
+    };
+    let zone = if zone.is_empty() { None } else { Some(parse_zone(zone)?) };
+    Some((clock, zone))
+}
+
+// "+hhmm", "+hh:mm", "Z", "UTC" or "GMT" in minutes east of UTC
+fn parse_zone(token: &str) -> Option<i32> {
+    match token {
+        "Z" | "UTC" | "GMT" => Some(0),
+        _ if token.len() == 5 || token.as_bytes().get(3) == Some(&b':') => parse_offset(&token.replacen(':', "", 1)),
+        _ => None,
+    }
+}
+
+// Days since the Unix epoch of a proleptic Gregorian date (Howard Hinnant's
+// days-from-civil algorithm, the inverse of the one `pretty` uses)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Offset of the local timezone from UTC in minutes at the given instant
+#[cfg(unix)]
+fn local_offset(secs: i64) -> i32 {
+    let t = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return 0;
+    }
+    (tm.tm_gmtoff / 60) as i32
+}
+
+// Without the C library's timezone database, times are recorded in UTC
+#[cfg(not(unix))]
+fn local_offset(_secs: i64) -> i32 {
+    0
+}
+
+/// A header of a commit or tag: its name and raw value.
+pub type Header = (String, Vec<u8>);
+
+// Split "<headers>\n\n<message>" into header pairs and the message.
+// Continuation lines (starting with a space) are folded into the previous
+// header's value, which is how multi-line headers like gpgsig are stored.
+// Values and the message are bytes: they are in the object's `encoding`.
+fn parse_headers(data: &[u8]) -> Result<(Vec<Header>, Vec<u8>)> {
+    let (head, message) = match data.windows(2).position(|pair| pair == b"\n\n") {
+        Some(pos) => (&data[..pos], &data[pos + 2..]),
+        None => (data.strip_suffix(b"\n").unwrap_or(data), &b""[..]),
+    };
+
+    let mut headers: Vec<Header> = Vec::new();
+    for line in head.split(|&b| b == b'\n') {
+        if let Some(rest) = line.strip_prefix(b" ") {
+            let last = headers.last_mut().ok_or_else(|| malformed("continuation line without header"))?;
+            last.1.push(b'\n');
+            last.1.extend_from_slice(rest);
+        } else {
+            let space = line.iter().position(|&b| b == b' ').unwrap_or(line.len());
+            let key = std::str::from_utf8(&line[..space]).map_err(|_| malformed("header name is not valid UTF-8"))?;
+            headers.push((key.to_string(), line.get(space + 1..).unwrap_or_default().to_vec()));
+        }
+    }
+
+    Ok((headers, message.to_vec()))
+}
+
+fn write_header(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    out.extend_from_slice(key.as_bytes());
+    out.push(b' ');
+    for &b in value {
+        out.push(b);
+        if b == b'\n' {
+            out.push(b' ');
+        }
+    }
+    out.push(b'\n');
+}
+
+// A header value that must be text, such as an object ID or type
+fn header_text<'a>(key: &str, value: &'a [u8]) -> Result<&'a str> {
+    std::str::from_utf8(value).map_err(|_| malformed(format!("{key} header is not valid UTF-8")))
+}
+
+// The "encoding" header, if any
+fn find_encoding(headers: &[Header]) -> Option<&[u8]> {
+    headers.iter().find(|(key, _)| key == "encoding").map(|(_, value)| &value[..])
+}
+
+/// Text of a commit or tag in its declared `encoding`, as UTF-8: Latin-1
+/// bytes map straight to characters, and anything else is read as UTF-8
+/// with invalid sequences replaced.
+pub fn decode(data: &[u8], encoding: Option<&[u8]>) -> String {
+    let latin1 = encoding.is_some_and(|name| {
+        ["ISO-8859-1", "ISO8859-1", "latin1", "latin-1"].iter().any(|alias| name.eq_ignore_ascii_case(alias.as_bytes()))
+    });
+    if latin1 {
+        data.iter().map(|&b| char::from(b)).collect()
+    } else {
+        String::from_utf8_lossy(data).into_owned()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    /// Author and committer, decoded from the commit's encoding.
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers other than the ones above (encoding, gpgsig, ...), in order.
+    pub extra_headers: Vec<Header>,
+    /// The message as stored, in the commit's encoding.
+    pub message: Vec<u8>,
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> Result<Commit> {
+        let (headers, message) = parse_headers(data)?;
+        let encoding = find_encoding(&headers).map(<[u8]>::to_vec);
+        let signature = |value: &[u8]| Signature::parse(&decode(value, encoding.as_deref()));
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" => tree = Some(Oid::from_hex_bytes(&value)?),
+                "parent" => parents.push(Oid::from_hex_bytes(&value)?),
+                "author" => author = Some(signature(&value)?),
+                "committer" => committer = Some(signature(&value)?),
+                _ => extra_headers.push((key, value)),
+            }
+        }
+
+        Ok(Commit {
+            tree: tree.ok_or_else(|| malformed("commit is missing a tree"))?,
+            parents,
+            author: author.ok_or_else(|| malformed("commit is missing an author"))?,
+            committer: committer.ok_or_else(|| malformed("commit is missing a committer"))?,
+            extra_headers,
+            message,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, "tree", self.tree.to_hex().as_bytes());
+        for parent in &self.parents {
+            write_header(&mut out, "parent", parent.to_hex().as_bytes());
+        }
+        write_header(&mut out, "author", self.author.to_string().as_bytes());
+        write_header(&mut out, "committer", self.committer.to_string().as_bytes());
+        for (key, value) in &self.extra_headers {
+            write_header(&mut out, key, value);
+        }
+        out.push(b'\n');
+        out.extend_from_slice(&self.message);
+        out
+    }
+
+    /// The message decoded from the commit's encoding.
+    pub fn message_text(&self) -> String {
+        decode(&self.message, find_encoding(&self.extra_headers))
+    }
+
+    /// First line of the message.
+    pub fn summary(&self) -> String {
+        self.message_text().lines().next().unwrap_or("").to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub object: Oid,
+    pub kind: ObjectKind,
+    pub tag: String,
+    pub tagger: Option<Signature>,
+    /// Headers other than the ones above, in order.
+    pub extra_headers: Vec<Header>,
+    /// The message as stored, in the tag's encoding.
+    pub message: Vec<u8>,
+}
+
+impl Tag {
+    pub fn parse(data: &[u8]) -> Result<Tag> {
+        let (headers, message) = parse_headers(data)?;
+        let encoding = find_encoding(&headers).map(<[u8]>::to_vec);
+
+        let mut object = None;
+        let mut kind = None;
+        let mut tag = None;
+        let mut tagger = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" => object = Some(Oid::from_hex_bytes(&value)?),
+                "type" => kind = Some(header_text(&key, &value)?.parse()?),
+                "tag" => tag = Some(decode(&value, encoding.as_deref())),
+                "tagger" => tagger = Some(Signature::parse(&decode(&value, encoding.as_deref()))?),
+                _ => extra_headers.push((key, value)),
+            }
+        }
+
+        Ok(Tag {
+            object: object.ok_or_else(|| malformed("tag is missing an object"))?,
+            kind: kind.ok_or_else(|| malformed("tag is missing a type"))?,
+            tag: tag.ok_or_else(|| malformed("tag is missing a name"))?,
+            tagger,
+            extra_headers,
+            message,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, "object", self.object.to_hex().as_bytes());
+        write_header(&mut out, "type", self.kind.as_str().as_bytes());
+        write_header(&mut out, "tag", self.tag.as_bytes());
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut out, "tagger", tagger.to_string().as_bytes());
+        }
+        for (key, value) in &self.extra_headers {
+            write_header(&mut out, key, value);
+        }
+        out.push(b'\n');
+        out.extend_from_slice(&self.message);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trees_roundtrip() {
+        let entries = vec![
+            TreeEntry { mode: MODE_FILE, name: b"a.txt".to_vec(), oid: Oid::hash(ObjectKind::Blob, b"a") },
+            TreeEntry { mode: MODE_TREE, name: b"dir".to_vec(), oid: Oid::hash(ObjectKind::Tree, b"") },
+            TreeEntry { mode: MODE_GITLINK, name: b"sub".to_vec(), oid: Oid::hash(ObjectKind::Commit, b"") },
+        ];
+        let tree = Tree { entries };
+        let data = tree.serialize();
+        assert!(data.starts_with(b"100644 a.txt\0"));
+        assert_eq!(Tree::parse(&data).unwrap(), tree);
+
+        let kinds: Vec<ObjectKind> = tree.entries.iter().map(TreeEntry::kind).collect();
+        assert_eq!(kinds, [ObjectKind::Blob, ObjectKind::Tree, ObjectKind::Commit]);
+        assert_eq!(Oid::hash(ObjectKind::Tree, b"").to_hex(), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+        assert!(Tree::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn tree_builder_sorts_like_git() {
+        let oid = Oid::hash(ObjectKind::Blob, b"");
+        let entry = |mode, name: &str| TreeEntry { mode, name: name.as_bytes().to_vec(), oid };
+
+        // A directory sorts as if its name ended in '/'
+        let mut builder = TreeBuilder::new();
+        let inserted = [(MODE_FILE, "foo0"), (MODE_TREE, "foo"), (MODE_FILE, "foo.txt"), (MODE_FILE, "foo-bar")];
+        for (mode, name) in inserted {
+            builder.insert(entry(mode, name)).unwrap();
+        }
+        let tree = builder.clone().build();
+        let names: Vec<&[u8]> = tree.entries.iter().map(|e| e.name.as_slice()).collect();
+        assert_eq!(names, [&b"foo-bar"[..], b"foo.txt", b"foo", b"foo0"]);
+
+        for name in ["", ".", "..", ".git", ".GIT", "a/b", "a\0b", "foo"] {
+            assert!(builder.insert(entry(MODE_FILE, name)).is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn commits_roundtrip() {
+        let data = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                    author A U Thor <author@example.com> 1112911993 -0700\n\
+                    committer C O Mitter <committer@example.com> 1112912053 +0545\n\
+                    gpgsig -----BEGIN PGP SIGNATURE-----\n \n -----END PGP SIGNATURE-----\n\
+                    \n\
+                    Subject\n\nBody\n";
+        let commit = Commit::parse(data.as_bytes()).unwrap();
+        assert_eq!(commit.author.name, "A U Thor");
+        assert_eq!((commit.author.time, commit.author.offset), (1112911993, -420));
+        assert_eq!(commit.committer.format_offset(), "+0545");
+        assert_eq!(commit.extra_headers[0].1, b"-----BEGIN PGP SIGNATURE-----\n\n-----END PGP SIGNATURE-----");
+        assert_eq!(commit.summary(), "Subject");
+        assert_eq!(commit.serialize(), data.as_bytes());
+    }
+
+    #[test]
+    fn signature_comes_from_the_environment() {
+        // A role no real environment sets
+        env::set_var("GIT_RITTEST_NAME", "A U Thor");
+        env::set_var("GIT_RITTEST_EMAIL", "author@example.com");
+        env::set_var("GIT_RITTEST_DATE", "1112911993 -0700");
+        let config = Config::default();
+        let sig = Signature::from_env("RITTEST", &config).unwrap();
+        assert_eq!(sig.to_string(), "A U Thor <author@example.com> 1112911993 -0700");
+
+        env::remove_var("GIT_RITTEST_DATE");
+        let sig = Signature::from_env("RITTEST", &config).unwrap();
+        assert_eq!(sig.offset, local_offset(sig.time));
+
+        env::remove_var("GIT_RITTEST_NAME");
+        assert!(matches!(Signature::from_env("RITTEST", &config), Err(RitError::UnknownIdentity(_))));
+    }
+
+    #[test]
+    fn parses_git_date_formats() {
+        let expected = Some((1112904793, 120));
+        assert_eq!(parse_date_spec("1112904793 +0200"), expected);
+        assert_eq!(parse_date_spec("@1112904793 +0200"), expected);
+        assert_eq!(parse_date_spec("Thu, 07 Apr 2005 22:13:13 +0200"), expected);
+        assert_eq!(parse_date_spec("7 Apr 2005 22:13:13 +0200"), expected);
+        assert_eq!(parse_date_spec("2005-04-07 22:13:13 +0200"), expected);
+        assert_eq!(parse_date_spec("2005-04-07T22:13:13+02:00"), expected);
+        assert_eq!(parse_date_spec("2005-04-07T20:13:13Z"), Some((1112904793, 0)));
+        assert_eq!(parse_date_spec("2005-13-07 22:13:13 +0200"), None);
+        assert_eq!(parse_date_spec("yesterday"), None);
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::error::{Result, RitError};
+use crate::object::{Commit, Object, ObjectKind, Tree};
+use crate::oid::Oid;
+use crate::pack::Pack;
+
+/// Shortest abbreviated object ID accepted anywhere.
+pub const MIN_ABBREV: usize = 4;
+/// Abbreviation length used for display unless asked otherwise.
+pub const DEFAULT_ABBREV: usize = 7;
+
+// Distinguishes temporary object files written by this process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Object storage: loose objects under `objects/xx/yyyy...` and packs
+/// under `objects/pack`, which every lookup searches transparently.
+pub struct ObjectDatabase {
+    dir: PathBuf,
+    // Opened on first use
+    packs: OnceLock<Vec<Pack>>,
+}
+
+impl ObjectDatabase {
+    /// Open the object database rooted at `dir` (usually `.git/objects`).
+    pub fn new(dir: impl Into<PathBuf>) -> ObjectDatabase {
+        ObjectDatabase { dir: dir.into(), packs: OnceLock::new() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where `oid` is stored as a loose object.
+    pub fn object_path(&self, oid: &Oid) -> PathBuf {
+        let hex = oid.to_hex();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// The packs in `objects/pack`, most recently modified first.
+    pub fn packs(&self) -> Result<&[Pack]> {
+        if let Some(packs) = self.packs.get() {
+            return Ok(packs);
+        }
+
+        let mut found = Vec::new();
+        for path in self.pack_indexes()? {
+            let modified = fs::metadata(path.with_extension("pack"))?.modified()?;
+            found.push((modified, Pack::open(&path)?));
+        }
+
+        found.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        Ok(self.packs.get_or_init(|| found.into_iter().map(|(_, pack)| pack).collect()))
+    }
+
+    /// The `.idx` files in `objects/pack` that have a pack beside them.
+    pub fn pack_indexes(&self) -> Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(self.dir.join("pack")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // An index without its pack is left over from an interrupted write
+            if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn exists(&self, oid: &Oid) -> bool {
+        self.object_path(oid).is_file() || self.packs().is_ok_and(|packs| packs.iter().any(|p| p.contains(oid)))
+    }
+
+    /// Every object whose hex ID starts with `prefix` (at least two hex
+    /// characters, lowercase).
+    pub fn find_by_prefix(&self, prefix: &str) -> Result<Vec<Oid>> {
+        let mut found = Vec::new();
+        for pack in self.packs()? {
+            found.extend(pack.index().find_prefix(prefix));
+        }
+
+        let fanout = match fs::read_dir(self.dir.join(&prefix[..2])) {
+            Ok(entries) => Some(entries),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in fanout.into_iter().flatten() {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else { continue };
+            if name.starts_with(&prefix[2..]) {
+                if let Ok(oid) = Oid::from_hex(&format!("{}{}", &prefix[..2], name)) {
+                    found.push(oid);
+                }
+            }
+        }
+
+        // The same object may be both loose and packed
+        found.sort();
+        found.dedup();
+        Ok(found)
+    }
+
+    /// Every loose object with its file, in no particular order.
+    pub fn loose_objects(&self) -> Result<Vec<(Oid, PathBuf)>> {
+        let mut found = Vec::new();
+        for dir in fs::read_dir(&self.dir)? {
+            let dir = dir?;
+            let prefix = dir.file_name();
+            let Some(prefix) = prefix.to_str().filter(|p| p.len() == 2) else { continue };
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(dir.path())? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let Some(name) = name.to_str() else { continue };
+                // Skips temporary files left behind by interrupted writes
+                if let Ok(oid) = Oid::from_hex(&format!("{prefix}{name}")) {
+                    found.push((oid, entry.path()));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Expand an abbreviated object ID of at least `MIN_ABBREV` hex
+    /// characters, failing if no object or more than one object matches.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<Oid> {
+        if prefix.len() < MIN_ABBREV || prefix.len() > Oid::HEX_LEN || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(RitError::InvalidOid(prefix.to_string()));
+        }
+
+        let prefix = prefix.to_ascii_lowercase();
+        let mut candidates = self.find_by_prefix(&prefix)?;
+        match candidates.len() {
+            0 => Err(RitError::ObjectNotFound(prefix)),
+            1 => Ok(candidates.remove(0)),
+            _ => {
+                let candidates = candidates
+                    .iter()
+                    .map(|oid| {
+                        let kind = self.read_raw(oid).map(|(kind, _)| kind.to_string());
+                        (*oid, kind.unwrap_or_else(|_| "bad object".to_string()))
+                    })
+                    .collect();
+                Err(RitError::AmbiguousOid { prefix, candidates })
+            }
+        }
+    }
+
+    /// The shortest prefix of `oid`, at least `min_len` characters long,
+    /// that no other object shares.
+    pub fn abbreviate(&self, oid: &Oid, min_len: usize) -> Result<String> {
+        let hex = oid.to_hex();
+        let min_len = min_len.clamp(MIN_ABBREV, Oid::HEX_LEN);
+
+        // One character past the longest prefix shared with any other object
+        let mut len = min_len;
+        for other in self.find_by_prefix(&hex[..2])? {
+            if other != *oid {
+                let other = other.to_hex();
+                let common = hex.bytes().zip(other.bytes()).take_while(|(a, b)| a == b).count();
+                len = len.max(common + 1);
+            }
+        }
+
+        Ok(hex[..len.min(Oid::HEX_LEN)].to_string())
+    }
+
+    /// Read an object's type and body without parsing it, from a loose
+    /// file or any pack.
+    pub fn read_raw(&self, oid: &Oid) -> Result<(ObjectKind, Vec<u8>)> {
+        let compressed = match fs::read(self.object_path(oid)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.read_packed(oid),
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = |reason: &str| RitError::CorruptObject { oid: *oid, reason: reason.to_string() };
+
+        let mut decoder = ZlibDecoder::new(&compressed[..]);
+        let mut decompressed = Vec::new();
+        decoder
+            .read_to_end(&mut decompressed)
+            .map_err(|e| corrupt(&format!("zlib: {e}")))?;
+
+        // Header is "<kind> <size>\0"
+        let null_pos = decompressed.iter().position(|&b| b == 0).ok_or_else(|| corrupt("missing header"))?;
+        let (kind, size) = std::str::from_utf8(&decompressed[..null_pos])
+            .ok()
+            .and_then(|header| header.split_once(' '))
+            .ok_or_else(|| corrupt("malformed header"))?;
+        let kind: ObjectKind = kind.parse().map_err(|_| corrupt("unknown object type"))?;
+        let size: usize = size.parse().map_err(|_| corrupt("malformed header"))?;
+
+        let body = decompressed.split_off(null_pos + 1);
+        if body.len() != size {
+            return Err(corrupt(&format!("length {} does not match header size {size}", body.len())));
+        }
+
+        Ok((kind, body))
+    }
+
+    fn read_packed(&self, oid: &Oid) -> Result<(ObjectKind, Vec<u8>)> {
+        for pack in self.packs()? {
+            // A REF_DELTA base may live in another pack or loose
+            if let Some(object) = pack.read(oid, &|base| self.read_raw(base))? {
+                return Ok(object);
+            }
+        }
+        Err(RitError::ObjectNotFound(oid.to_hex()))
+    }
+
+    pub fn read_object(&self, oid: &Oid) -> Result<Object> {
+        let (kind, data) = self.read_raw(oid)?;
+        Object::parse(kind, &data).map_err(|e| match e {
+            RitError::MalformedObject(reason) => RitError::CorruptObject { oid: *oid, reason },
+            other => other,
+        })
+    }
+
+    /// Read an object that must be a tree.
+    pub fn read_tree(&self, oid: &Oid) -> Result<Tree> {
+        match self.read_object(oid)? {
+            Object::Tree(tree) => Ok(tree),
+            other => Err(wrong_type(oid, ObjectKind::Tree, &other)),
+        }
+    }
+
+    /// Read an object that must be a commit.
+    pub fn read_commit(&self, oid: &Oid) -> Result<Commit> {
+        match self.read_object(oid)? {
+            Object::Commit(commit) => Ok(commit),
+            other => Err(wrong_type(oid, ObjectKind::Commit, &other)),
+        }
+    }
+
+    /// Store an already serialized object body, returning its ID.
+    pub fn write_raw(&self, kind: ObjectKind, data: &[u8]) -> Result<Oid> {
+        let oid = Oid::hash(kind, data);
+        if !self.exists(&oid) {
+            self.write_loose(kind, data)?;
+        }
+        Ok(oid)
+    }
+
+    /// Store an object as a loose file even if a pack already has it.
+    pub fn write_loose(&self, kind: ObjectKind, data: &[u8]) -> Result<Oid> {
+        let oid = Oid::hash(kind, data);
+        let path = self.object_path(&oid);
+
+        if !path.is_file() {
+            // Write a temporary file in the same directory and rename it into
+            // place, so that no reader sees a partly written object
+            let dir = path.parent().unwrap();
+            fs::create_dir_all(dir)?;
+            let serial = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let temp = dir.join(format!("tmp_obj_{}_{serial}", std::process::id()));
+
+            let written = fs::File::options().write(true).create_new(true).open(&temp).and_then(|f| {
+                let mut encoder = ZlibEncoder::new(f, Compression::default());
+                encoder.write_all(format!("{} {}\0", kind, data.len()).as_bytes())?;
+                encoder.write_all(data)?;
+                encoder.finish()?;
+                fs::rename(&temp, &path)
+            });
+            if let Err(e) = written {
+                let _ = fs::remove_file(&temp);
+                return Err(e.into());
+            }
+        }
+
+        Ok(oid)
+    }
+
+    pub fn write_object(&self, object: &Object) -> Result<Oid> {
+        self.write_raw(object.kind(), &object.serialize())
+    }
+}
+
+fn wrong_type(oid: &Oid, expected: ObjectKind, actual: &Object) -> RitError {
+    RitError::WrongObjectType { oid: *oid, expected, actual: actual.kind() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rit-odb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let odb = ObjectDatabase::new(&dir);
+
+        let oid = odb.write_raw(ObjectKind::Blob, b"hello world\n").unwrap();
+        assert_eq!(oid.to_hex(), "3b18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        assert!(dir.join("3b").join("18e512dba79e4c8300dd08aeb37f8e728b8dad").is_file());
+        assert!(odb.exists(&oid));
+        assert_eq!(odb.read_raw(&oid).unwrap(), (ObjectKind::Blob, b"hello world\n".to_vec()));
+        assert_eq!(odb.read_object(&oid).unwrap(), Object::Blob(b"hello world\n".to_vec()));
+        assert!(!odb.exists(&Oid::hash(ObjectKind::Blob, b"missing")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failures_map_to_exit_codes() {
+        let dir = std::env::temp_dir().join(format!("rit-odb-errors-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let odb = ObjectDatabase::new(&dir);
+
+        let missing = odb.read_raw(&Oid::hash(ObjectKind::Blob, b"missing")).unwrap_err();
+        assert!(matches!(missing, RitError::ObjectNotFound(_)));
+        assert_eq!(missing.exit_code(), 128);
+
+        let blob = odb.write_raw(ObjectKind::Blob, b"data").unwrap();
+        let wrong = odb.read_tree(&blob).unwrap_err();
+        assert!(matches!(wrong, RitError::WrongObjectType { actual: ObjectKind::Blob, .. }));
+        assert_eq!(wrong.exit_code(), 129);
+
+        let tree = odb.write_raw(ObjectKind::Tree, b"100644 truncated").unwrap();
+        assert!(matches!(odb.read_tree(&tree), Err(RitError::CorruptObject { .. })));
+        fs::write(dir.join(&blob.to_hex()[..2]).join(&blob.to_hex()[2..]), b"not zlib").unwrap();
+        let corrupt = odb.read_raw(&blob).unwrap_err();
+        assert!(matches!(corrupt, RitError::CorruptObject { .. }));
+        assert_eq!(corrupt.exit_code(), 65);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn abbreviated_ids() {
+        let dir = std::env::temp_dir().join(format!("rit-odb-abbrev-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let odb = ObjectDatabase::new(&dir);
+
+        // Two blobs whose IDs share their first five hex digits
+        let mut seen = std::collections::HashMap::new();
+        let (a, b) = (0..)
+            .find_map(|n: u32| {
+                let data = n.to_string().into_bytes();
+                let hex = Oid::hash(ObjectKind::Blob, &data).to_hex();
+                seen.insert(hex[..5].to_string(), data.clone()).map(|other| (other, data))
+            })
+            .unwrap();
+        let a = odb.write_raw(ObjectKind::Blob, &a).unwrap();
+        let b = odb.write_raw(ObjectKind::Blob, &b).unwrap();
+        let (a_hex, b_hex) = (a.to_hex(), b.to_hex());
+        let common = a_hex.bytes().zip(b_hex.bytes()).take_while(|(x, y)| x == y).count();
+
+        assert!(matches!(odb.resolve_prefix(&a_hex[..5]), Err(RitError::AmbiguousOid { candidates, .. })
+            if candidates.len() == 2));
+        assert_eq!(odb.resolve_prefix(&a_hex[..common + 1]).unwrap(), a);
+        assert_eq!(odb.resolve_prefix(&b_hex[..common + 1].to_uppercase()).unwrap(), b);
+        assert_eq!(odb.abbreviate(&a, DEFAULT_ABBREV).unwrap(), a_hex[..DEFAULT_ABBREV.max(common + 1)]);
+        assert_eq!(odb.abbreviate(&a, 40).unwrap(), a_hex);
+
+        assert!(matches!(odb.resolve_prefix(&a_hex[..3]), Err(RitError::InvalidOid(_))));
+        assert!(matches!(odb.resolve_prefix("zzzz"), Err(RitError::InvalidOid(_))));
+        let missing = if a_hex.starts_with("0000") { "ffff" } else { "0000" };
+        assert!(matches!(odb.resolve_prefix(missing), Err(RitError::ObjectNotFound(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::str::FromStr;
+
+use sha1::{Digest, Sha1};
+
+use crate::error::{Result, RitError};
+use crate::object::ObjectKind;
+
+/// A SHA-1 object ID.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Oid([u8; 20]);
+
+impl Oid {
+    /// Length of a raw object ID in bytes.
+    pub const LEN: usize = 20;
+    /// Length of a hex-encoded object ID.
+    pub const HEX_LEN: usize = 40;
+    /// The all-zero ID git uses for "no object", e.g. a ref that does not exist.
+    pub const ZERO: Oid = Oid([0; 20]);
+
+    /// Build an ID from 20 raw bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Oid> {
+        match <[u8; 20]>::try_from(bytes) {
+            Ok(raw) => Ok(Oid(raw)),
+            Err(_) => Err(RitError::InvalidOid(hex::encode(bytes))),
+        }
+    }
+
+    /// Parse a full 40 character hex ID.
+    pub fn from_hex(hex: &str) -> Result<Oid> {
+        Oid::from_hex_bytes(hex.as_bytes())
+    }
+
+    /// Parse a full 40 character hex ID that is not known to be text, such
+    /// as one taken from an object body or a reflog line.
+    pub fn from_hex_bytes(hex: &[u8]) -> Result<Oid> {
+        let mut raw = [0u8; 20];
+        if hex.len() != Self::HEX_LEN || hex::decode_to_slice(hex, &mut raw).is_err() {
+            return Err(RitError::InvalidOid(String::from_utf8_lossy(hex).into_owned()));
+        }
+        Ok(Oid(raw))
+    }
+
+    /// Hash an object the way git does: over "<kind> <size>\0<data>".
+    pub fn hash(kind: ObjectKind, data: &[u8]) -> Oid {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{} {}\0", kind, data.len()).as_bytes());
+        hasher.update(data);
+        Oid(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Oid({})", self.to_hex())
+    }
+}
+
+impl FromStr for Oid {
+    type Err = RitError;
+
+    fn from_str(s: &str) -> Result<Oid> {
+        Oid::from_hex(s)
+    }
+}
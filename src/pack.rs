@@ -0,0 +1,881 @@
+//! Packfiles: `objects/pack/pack-<hash>.pack` holding many compressed
+//! objects, most stored as deltas against another object, and the matching
+//! `.idx` (version 2) mapping object IDs to offsets in the pack.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+
+use crate::delta::{apply_delta, create_delta, MAX_RESERVE};
+use crate::error::{Result, RitError};
+use crate::lockfile::Lockfile;
+use crate::object::ObjectKind;
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+
+/// First four bytes of a version 2 (or later) pack index.
+pub const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+/// First four bytes of a packfile.
+pub const PACK_SIGNATURE: [u8; 4] = *b"PACK";
+
+// Type numbers in pack entry headers
+pub(crate) const OBJ_COMMIT: u8 = 1;
+pub(crate) const OBJ_TREE: u8 = 2;
+pub(crate) const OBJ_BLOB: u8 = 3;
+pub(crate) const OBJ_TAG: u8 = 4;
+pub(crate) const OBJ_OFS_DELTA: u8 = 6;
+pub(crate) const OBJ_REF_DELTA: u8 = 7;
+
+/// Reads a REF_DELTA base that is not in the same pack.
+pub type BaseLookup<'a> = &'a dyn Fn(&Oid) -> Result<(ObjectKind, Vec<u8>)>;
+
+// Guards against delta cycles in a corrupt pack
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+// Objects smaller than this are never stored as deltas
+const MIN_DELTA_SIZE: usize = 32;
+
+// Size of the fixed part of an idx file: header and fan-out table
+const IDX_HEADER_LEN: usize = 8 + 256 * 4;
+
+/// A parsed `.idx` file, kept in memory and searched in place.
+pub struct PackIndex {
+    path: PathBuf,
+    data: Vec<u8>,
+    count: usize,
+}
+
+impl PackIndex {
+    pub fn open(path: &Path) -> Result<PackIndex> {
+        let data = fs::read(path)?;
+        let corrupt = |reason: &str| RitError::CorruptPack { path: path.to_path_buf(), reason: reason.to_string() };
+
+        if data.len() < IDX_HEADER_LEN || data[..4] != IDX_MAGIC {
+            return Err(corrupt("not a version 2 pack index"));
+        }
+        if read_u32(&data, 4) != 2 {
+            return Err(corrupt(&format!("unsupported index version {}", read_u32(&data, 4))));
+        }
+
+        let mut previous = 0;
+        for i in 0..256 {
+            let n = read_u32(&data, 8 + i * 4);
+            if n < previous {
+                return Err(corrupt("fan-out table is not sorted"));
+            }
+            previous = n;
+        }
+
+        // IDs, CRCs, 4-byte offsets, 8-byte offsets, pack and idx checksums
+        let count = previous as usize;
+        let min_len = IDX_HEADER_LEN + count * (Oid::LEN + 4 + 4) + 2 * Oid::LEN;
+        if data.len() < min_len || (data.len() - min_len) % 8 != 0 {
+            return Err(corrupt("wrong index size"));
+        }
+
+        let index = PackIndex { path: path.to_path_buf(), data, count };
+        let large = (0..count).filter(|&i| index.raw_offset(i) & 0x8000_0000 != 0).count();
+        if index.data.len() != min_len + large * 8 {
+            return Err(corrupt("wrong index size"));
+        }
+        Ok(index)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of objects in the pack.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The `i`-th object ID, in sorted order.
+    pub fn oid(&self, i: usize) -> Oid {
+        let start = IDX_HEADER_LEN + i * Oid::LEN;
+        Oid::from_bytes(&self.data[start..start + Oid::LEN]).expect("20 bytes")
+    }
+
+    /// All object IDs in the pack, sorted.
+    pub fn oids(&self) -> impl Iterator<Item = Oid> + '_ {
+        (0..self.count).map(|i| self.oid(i))
+    }
+
+    /// CRC-32 of the `i`-th object's packed entry.
+    pub fn crc32(&self, i: usize) -> u32 {
+        read_u32(&self.data, IDX_HEADER_LEN + self.count * Oid::LEN + i * 4)
+    }
+
+    fn raw_offset(&self, i: usize) -> u32 {
+        read_u32(&self.data, IDX_HEADER_LEN + self.count * (Oid::LEN + 4) + i * 4)
+    }
+
+    /// Where the `i`-th object's entry starts in the pack.
+    pub fn offset(&self, i: usize) -> Result<u64> {
+        let offset = self.raw_offset(i);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(offset as u64);
+        }
+
+        // The high bit marks an index into the table of 8-byte offsets,
+        // which ends before the two checksums
+        let start = IDX_HEADER_LEN + self.count * (Oid::LEN + 8) + (offset & 0x7fff_ffff) as usize * 8;
+        let table = &self.data[..self.data.len() - 2 * Oid::LEN];
+        match table.get(start..start + 8) {
+            Some(bytes) => Ok(u64::from_be_bytes(bytes.try_into().unwrap())),
+            None => Err(RitError::CorruptPack {
+                path: self.path.clone(),
+                reason: format!("large offset {} of object {} is out of range", offset & 0x7fff_ffff, self.oid(i)),
+            }),
+        }
+    }
+
+    /// Checksum of the pack this index describes.
+    pub fn pack_checksum(&self) -> Oid {
+        let start = self.data.len() - 2 * Oid::LEN;
+        Oid::from_bytes(&self.data[start..start + Oid::LEN]).expect("20 bytes")
+    }
+
+    // Range of positions whose IDs start with `first_byte`
+    fn fanout_range(&self, first_byte: u8) -> (usize, usize) {
+        let end = read_u32(&self.data, 8 + first_byte as usize * 4) as usize;
+        let start = match first_byte {
+            0 => 0,
+            b => read_u32(&self.data, 8 + (b as usize - 1) * 4) as usize,
+        };
+        (start, end)
+    }
+
+    /// Position of `oid` in the index, if the pack has it.
+    pub fn find(&self, oid: &Oid) -> Option<usize> {
+        let (mut lo, mut hi) = self.fanout_range(oid.as_bytes()[0]);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.oid(mid).cmp(oid) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Every object whose hex ID starts with `prefix` (at least two
+    /// lowercase hex characters).
+    pub fn find_prefix(&self, prefix: &str) -> Vec<Oid> {
+        let Ok(first_byte) = u8::from_str_radix(&prefix[..2], 16) else {
+            return Vec::new();
+        };
+        let (start, end) = self.fanout_range(first_byte);
+        (start..end).map(|i| self.oid(i)).filter(|oid| oid.to_hex().starts_with(prefix)).collect()
+    }
+}
+
+/// What a pack entry holds, from its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Object(ObjectKind),
+    /// A delta against the entry starting at this offset.
+    OfsDelta(u64),
+    /// A delta against the object with this ID.
+    RefDelta(Oid),
+}
+
+/// The header of one entry in a pack.
+#[derive(Clone, Copy, Debug)]
+pub struct PackEntry {
+    pub kind: EntryKind,
+    /// Size of the object, or of the delta, once inflated.
+    pub size: u64,
+    /// Where the entry starts.
+    pub offset: u64,
+    /// Where the compressed data starts, after the header.
+    pub data_offset: u64,
+}
+
+/// One object of a verified pack, as listed by `verify-pack -v`.
+#[derive(Clone, Debug)]
+pub struct VerifiedEntry {
+    pub oid: Oid,
+    pub kind: ObjectKind,
+    /// Size of the object, or of its delta.
+    pub size: u64,
+    /// Bytes the entry takes up in the pack.
+    pub packed_size: u64,
+    pub offset: u64,
+    /// Chain depth and base object, for deltas.
+    pub delta: Option<(usize, Oid)>,
+}
+
+/// A packfile opened through its index.
+pub struct Pack {
+    path: PathBuf,
+    file: File,
+    index: PackIndex,
+}
+
+impl Pack {
+    /// Open the pack described by the index at `idx_path`; the pack itself
+    /// is the `.pack` file next to it.
+    pub fn open(idx_path: &Path) -> Result<Pack> {
+        let index = PackIndex::open(idx_path)?;
+        let path = idx_path.with_extension("pack");
+        let file = File::open(&path)?;
+
+        let pack = Pack { path, file, index };
+        let mut header = [0u8; 12];
+        pack.file.read_exact_at(&mut header, 0).map_err(|_| pack.corrupt("truncated header"))?;
+        if header[..4] != PACK_SIGNATURE || !matches!(read_u32(&header, 4), 2 | 3) {
+            return Err(pack.corrupt("not a version 2 packfile"));
+        }
+        if read_u32(&header, 8) as usize != pack.index.len() {
+            return Err(pack.corrupt("object count does not match its index"));
+        }
+        Ok(pack)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    pub fn contains(&self, oid: &Oid) -> bool {
+        self.index.find(oid).is_some()
+    }
+
+    fn corrupt(&self, reason: &str) -> RitError {
+        RitError::CorruptPack { path: self.path.clone(), reason: reason.to_string() }
+    }
+
+    /// Read `oid` if this pack has it. Bases of REF_DELTA entries missing
+    /// from this pack are read through `external`.
+    pub fn read(&self, oid: &Oid, external: BaseLookup) -> Result<Option<(ObjectKind, Vec<u8>)>> {
+        match self.index.find(oid) {
+            Some(i) => self.read_at(self.index.offset(i)?, external).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the object whose entry starts at `offset`, applying deltas.
+    pub fn read_at(&self, offset: u64, external: BaseLookup) -> Result<(ObjectKind, Vec<u8>)> {
+        // Follow the chain down to a whole object, then apply the deltas
+        // from the innermost out
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+        let (kind, mut data) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(self.corrupt(&format!("delta chain at offset {offset} is too deep")));
+            }
+
+            let entry = self.entry_at(offset)?;
+            match entry.kind {
+                EntryKind::Object(kind) => break (kind, self.inflate(&entry)?),
+                EntryKind::OfsDelta(base) => {
+                    deltas.push(self.inflate(&entry)?);
+                    offset = base;
+                }
+                EntryKind::RefDelta(base) => {
+                    deltas.push(self.inflate(&entry)?);
+                    match self.index.find(&base) {
+                        Some(i) => offset = self.index.offset(i)?,
+                        None => break external(&base)?,
+                    }
+                }
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            data = apply_delta(&data, delta).map_err(|reason| self.corrupt(&reason))?;
+        }
+        Ok((kind, data))
+    }
+
+    /// Check the pack and index checksums and every entry's CRC, and that
+    /// every object inflates and hashes to its ID. Returns the entries in
+    /// pack order.
+    pub fn verify(&self, external: BaseLookup) -> Result<Vec<VerifiedEntry>> {
+        let data = fs::read(&self.path)?;
+        if data.len() < 12 + Oid::LEN {
+            return Err(self.corrupt("truncated pack"));
+        }
+        let trailer = data.len() - Oid::LEN;
+        if Sha1::digest(&data[..trailer])[..] != data[trailer..] {
+            return Err(self.corrupt("pack checksum mismatch"));
+        }
+        if data[trailer..] != self.index.pack_checksum().as_bytes()[..] {
+            return Err(self.corrupt("pack does not match its index"));
+        }
+        let idx = &self.index.data;
+        if Sha1::digest(&idx[..idx.len() - Oid::LEN])[..] != idx[idx.len() - Oid::LEN..] {
+            return Err(RitError::CorruptPack { path: self.index.path.clone(), reason: "index checksum mismatch".into() });
+        }
+
+        let mut by_offset: Vec<(u64, usize)> =
+            (0..self.index.len()).map(|i| Ok((self.index.offset(i)?, i))).collect::<Result<_>>()?;
+        by_offset.sort();
+        let oid_at: HashMap<u64, Oid> =
+            by_offset.iter().map(|&(offset, i)| (offset, self.index.oid(i))).collect();
+
+        let mut entries: Vec<VerifiedEntry> = Vec::with_capacity(by_offset.len());
+        let mut depths: HashMap<u64, usize> = HashMap::new();
+        for (n, &(offset, i)) in by_offset.iter().enumerate() {
+            let oid = self.index.oid(i);
+            let end = by_offset.get(n + 1).map_or(trailer as u64, |&(next, _)| next);
+            let raw = data.get(offset as usize..end as usize).ok_or_else(|| self.corrupt("offset out of range"))?;
+            if crc32fast::hash(raw) != self.index.crc32(i) {
+                return Err(self.corrupt(&format!("CRC mismatch for object {oid}")));
+            }
+
+            let (kind, body) = self.read_at(offset, external)?;
+            if Oid::hash(kind, &body) != oid {
+                return Err(self.corrupt(&format!("object {oid} hashes to {}", Oid::hash(kind, &body))));
+            }
+
+            // Bases come earlier in the pack, except REF_DELTA bases
+            let entry = self.entry_at(offset)?;
+            let delta = match entry.kind {
+                EntryKind::Object(_) => None,
+                EntryKind::OfsDelta(base) => {
+                    let base_oid = *oid_at.get(&base).ok_or_else(|| self.corrupt("delta base is not an entry"))?;
+                    Some((depths.get(&base).copied().unwrap_or(0) + 1, base_oid))
+                }
+                EntryKind::RefDelta(base) => {
+                    let base_depth = match self.index.find(&base) {
+                        Some(b) => depths.get(&self.index.offset(b)?).copied(),
+                        None => None,
+                    };
+                    Some((base_depth.unwrap_or(0) + 1, base))
+                }
+            };
+            depths.insert(offset, delta.map_or(0, |(depth, _)| depth));
+
+            entries.push(VerifiedEntry { oid, kind, size: entry.size, packed_size: end - offset, offset, delta });
+        }
+
+        Ok(entries)
+    }
+
+    /// Parse the header of the entry at `offset`.
+    pub fn entry_at(&self, offset: u64) -> Result<PackEntry> {
+        let truncated = || self.corrupt(&format!("truncated entry at offset {offset}"));
+
+        // Type and size, then for deltas the base: at most 10 + 20 bytes
+        let mut buf = [0u8; 32];
+        let n = self.file.read_at(&mut buf, offset)?;
+        let buf = &buf[..n];
+
+        let mut pos = 0;
+        let byte = *buf.first().ok_or_else(truncated)?;
+        let type_num = (byte >> 4) & 0x7;
+        let mut size = (byte & 0x0f) as u64;
+        let mut shift = 4;
+        let mut more = byte & 0x80 != 0;
+        while more {
+            if shift >= 64 {
+                return Err(self.corrupt(&format!("entry size at offset {offset} does not fit in 64 bits")));
+            }
+            pos += 1;
+            let byte = *buf.get(pos).ok_or_else(truncated)?;
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            more = byte & 0x80 != 0;
+        }
+        pos += 1;
+
+        let kind = match type_num {
+            OBJ_COMMIT => EntryKind::Object(ObjectKind::Commit),
+            OBJ_TREE => EntryKind::Object(ObjectKind::Tree),
+            OBJ_BLOB => EntryKind::Object(ObjectKind::Blob),
+            OBJ_TAG => EntryKind::Object(ObjectKind::Tag),
+            OBJ_OFS_DELTA => {
+                // Big-endian base-128, adding one for each continuation byte
+                let bad_base = || self.corrupt(&format!("bad delta base offset at offset {offset}"));
+                let mut byte = *buf.get(pos).ok_or_else(truncated)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    pos += 1;
+                    byte = *buf.get(pos).ok_or_else(truncated)?;
+                    distance = distance.checked_add(1).and_then(|d| d.checked_mul(0x80)).ok_or_else(bad_base)?;
+                    distance |= (byte & 0x7f) as u64;
+                }
+                pos += 1;
+                let base = offset.checked_sub(distance).filter(|_| distance > 0).ok_or_else(bad_base)?;
+                EntryKind::OfsDelta(base)
+            }
+            OBJ_REF_DELTA => {
+                let base = buf.get(pos..pos + Oid::LEN).ok_or_else(truncated)?;
+                pos += Oid::LEN;
+                EntryKind::RefDelta(Oid::from_bytes(base)?)
+            }
+            other => return Err(self.corrupt(&format!("unknown object type {other} at offset {offset}"))),
+        };
+
+        Ok(PackEntry { kind, size, offset, data_offset: offset + pos as u64 })
+    }
+
+    /// Inflate an entry's data: the object itself, or its delta.
+    pub fn inflate(&self, entry: &PackEntry) -> Result<Vec<u8>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.data_offset))?;
+
+        let mut data = Vec::with_capacity(entry.size.min(MAX_RESERVE as u64) as usize);
+        ZlibDecoder::new(BufReader::new(file))
+            .take(entry.size + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| self.corrupt(&format!("zlib at offset {}: {e}", entry.offset)))?;
+
+        if data.len() as u64 != entry.size {
+            return Err(self.corrupt(&format!("entry at offset {} has the wrong size", entry.offset)));
+        }
+        Ok(data)
+    }
+}
+
+/// Delta search settings for `build_pack`.
+#[derive(Clone, Copy, Debug)]
+pub struct PackOptions {
+    /// How many preceding similar objects are tried as delta bases.
+    pub window: usize,
+    /// Longest delta chain allowed.
+    pub depth: usize,
+}
+
+impl Default for PackOptions {
+    fn default() -> PackOptions {
+        PackOptions { window: 10, depth: 50 }
+    }
+}
+
+/// A pack built in memory with its index, named by `checksum`.
+pub struct PackData {
+    pub pack: Vec<u8>,
+    pub index: Vec<u8>,
+    pub checksum: Oid,
+}
+
+// An object on its way into a pack
+struct ToPack {
+    oid: Oid,
+    kind: ObjectKind,
+    data: Vec<u8>,
+    name_hash: u32,
+    // Chosen delta base, as a position in the list, and the delta itself
+    delta: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+/// Build a pack holding `objects`, each given with the path it was reached
+/// by (empty if none), which helps pair up similar objects as deltas.
+/// Objects are written in the order given, except that a delta's base
+/// always comes first so it can be referred to by offset.
+pub fn build_pack(odb: &ObjectDatabase, objects: &[(Oid, Vec<u8>)], options: PackOptions) -> Result<PackData> {
+    let mut seen = HashSet::new();
+    let mut entries = Vec::with_capacity(objects.len());
+    for (oid, name) in objects {
+        if seen.insert(*oid) {
+            let (kind, data) = odb.read_raw(oid)?;
+            entries.push(ToPack { oid: *oid, kind, data, name_hash: name_hash(name), delta: None, depth: 0 });
+        }
+    }
+
+    find_deltas(&mut entries, options);
+
+    let mut pack = Vec::new();
+    pack.extend_from_slice(&PACK_SIGNATURE);
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    let mut written: Vec<Option<(u64, u32)>> = vec![None; entries.len()];
+    for i in 0..entries.len() {
+        write_entry(&entries, i, &mut pack, &mut written)?;
+    }
+
+    let checksum = Oid::from_bytes(&Sha1::digest(&pack))?;
+    pack.extend_from_slice(checksum.as_bytes());
+
+    let mut index: Vec<(Oid, u64, u32)> = entries
+        .iter()
+        .zip(written)
+        .map(|(entry, written)| {
+            let (offset, crc) = written.expect("every entry is written");
+            (entry.oid, offset, crc)
+        })
+        .collect();
+    index.sort();
+
+    Ok(PackData { pack, index: build_index(&index, &checksum), checksum })
+}
+
+/// Write `data` as `<base_name>-<checksum>.pack` and `.idx`, returning the
+/// path of the pack.
+pub fn write_pack_files(base_name: &Path, data: &PackData) -> Result<PathBuf> {
+    let base = base_name.to_string_lossy();
+    let pack_path = PathBuf::from(format!("{base}-{}.pack", data.checksum));
+
+    // The index goes last: a pack is only used once its index exists
+    for (path, contents) in [(pack_path.clone(), &data.pack), (pack_path.with_extension("idx"), &data.index)] {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut lock = Lockfile::acquire(&path)?;
+        lock.write_all(contents)?;
+        lock.commit()?;
+    }
+
+    Ok(pack_path)
+}
+
+// Git's path hash: the last characters weigh most, so files with the same
+// name or extension sort next to each other
+fn name_hash(name: &[u8]) -> u32 {
+    name.iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .fold(0u32, |hash, &b| (hash >> 2).wrapping_add((b as u32) << 24))
+}
+
+// Sort similar objects next to each other, largest first, and try each of
+// the previous `window` objects of the same type as a delta base
+fn find_deltas(entries: &mut [ToPack], options: PackOptions) {
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| {
+        let entry = &entries[i];
+        (type_number(entry.kind), entry.name_hash, std::cmp::Reverse(entry.data.len()))
+    });
+
+    let mut window: VecDeque<usize> = VecDeque::new();
+    for i in order {
+        if window.front().is_some_and(|&j| entries[j].kind != entries[i].kind) {
+            window.clear();
+        }
+
+        let target = &entries[i].data;
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        if target.len() >= MIN_DELTA_SIZE && options.depth > 0 {
+            for &j in window.iter().rev() {
+                let base = &entries[j];
+                if base.depth >= options.depth || base.data.len() < target.len() / 32 {
+                    continue;
+                }
+
+                // Deep chains are slow to read, so they must save more
+                let limit = (target.len() / 2).saturating_sub(Oid::LEN) * (options.depth - base.depth) / options.depth;
+                let limit = best.as_ref().map_or(limit, |(_, delta)| limit.min(delta.len().saturating_sub(1)));
+                if let Some(delta) = create_delta(&base.data, target, limit) {
+                    best = Some((j, delta));
+                }
+            }
+        }
+
+        if let Some((base, delta)) = best {
+            entries[i].depth = entries[base].depth + 1;
+            entries[i].delta = Some((base, delta));
+        }
+
+        window.push_back(i);
+        if window.len() > options.window {
+            window.pop_front();
+        }
+    }
+}
+
+// Append entry `i`, after its delta base, recording its offset and CRC
+fn write_entry(entries: &[ToPack], i: usize, pack: &mut Vec<u8>, written: &mut [Option<(u64, u32)>]) -> Result<()> {
+    if written[i].is_some() {
+        return Ok(());
+    }
+
+    let entry = &entries[i];
+    let (type_num, data) = match &entry.delta {
+        Some((base, delta)) => {
+            write_entry(entries, *base, pack, written)?;
+            (OBJ_OFS_DELTA, delta)
+        }
+        None => (type_number(entry.kind), &entry.data),
+    };
+    let offset = pack.len() as u64;
+
+    // Type and size: 4 bits of size in the first byte, then 7 per byte
+    let mut size = data.len() as u64;
+    let mut header = vec![(type_num << 4) | (size & 0x0f) as u8];
+    size >>= 4;
+    while size > 0 {
+        *header.last_mut().unwrap() |= 0x80;
+        header.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+
+    if let Some((base, _)) = &entry.delta {
+        // Distance back to the base, big-endian, less one per extra byte
+        let mut distance = offset - written[*base].expect("bases are written first").0;
+        let mut encoded = vec![(distance & 0x7f) as u8];
+        distance >>= 7;
+        while distance > 0 {
+            distance -= 1;
+            encoded.push(0x80 | (distance & 0x7f) as u8);
+            distance >>= 7;
+        }
+        encoded.reverse();
+        header.extend(encoded);
+    }
+
+    let mut encoder = ZlibEncoder::new(header, Compression::default());
+    encoder.write_all(data)?;
+    let raw = encoder.finish()?;
+
+    written[i] = Some((offset, crc32fast::hash(&raw)));
+    pack.extend_from_slice(&raw);
+    Ok(())
+}
+
+// Index version 2 for `objects`, which must be sorted by ID
+fn build_index(objects: &[(Oid, u64, u32)], pack_checksum: &Oid) -> Vec<u8> {
+    let mut index = Vec::new();
+    index.extend_from_slice(&IDX_MAGIC);
+    index.extend_from_slice(&2u32.to_be_bytes());
+
+    let mut count = 0u32;
+    for byte in 0..=255u8 {
+        count += objects[count as usize..].iter().take_while(|(oid, _, _)| oid.as_bytes()[0] == byte).count() as u32;
+        index.extend_from_slice(&count.to_be_bytes());
+    }
+
+    for (oid, _, _) in objects {
+        index.extend_from_slice(oid.as_bytes());
+    }
+    for (_, _, crc) in objects {
+        index.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    // Offsets past 2 GiB go in a table of 8-byte offsets
+    let mut large = Vec::new();
+    for (_, offset, _) in objects {
+        let small = match u32::try_from(*offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => offset,
+            _ => {
+                large.push(*offset);
+                0x8000_0000 | (large.len() as u32 - 1)
+            }
+        };
+        index.extend_from_slice(&small.to_be_bytes());
+    }
+    for offset in large {
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    index.extend_from_slice(pack_checksum.as_bytes());
+    let checksum = Sha1::digest(&index);
+    index.extend_from_slice(&checksum);
+    index
+}
+
+fn type_number(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => OBJ_COMMIT,
+        ObjectKind::Tree => OBJ_TREE,
+        ObjectKind::Blob => OBJ_BLOB,
+        ObjectKind::Tag => OBJ_TAG,
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rit-pack-{}-{name}", std::process::id()))
+    }
+
+    // Versions of a file that differ a little, so they pack as deltas
+    fn versions() -> Vec<Vec<u8>> {
+        let base: Vec<u8> = (0..200).flat_map(|n| format!("line {n} of a file\n").into_bytes()).collect();
+        (0..6)
+            .map(|v| {
+                let mut data = base.clone();
+                data.splice(v * 500..v * 500, format!("version {v}\n").into_bytes());
+                data
+            })
+            .collect()
+    }
+
+    // A pack entry: the type and size header, `extra` (a delta's base), and
+    // the zlib stream
+    fn raw_entry(type_num: u8, extra: &[u8], data: &[u8]) -> Vec<u8> {
+        raw_entry_sized(type_num, data.len() as u64, extra, data)
+    }
+
+    // Like `raw_entry`, but with any size in the header
+    fn raw_entry_sized(type_num: u8, mut size: u64, extra: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut header = vec![(type_num << 4) | (size & 0x0f) as u8];
+        size >>= 4;
+        while size > 0 {
+            *header.last_mut().unwrap() |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        header.extend_from_slice(extra);
+        let mut encoder = ZlibEncoder::new(header, Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn packs_read_back() {
+        let dir = temp_path("roundtrip");
+        let _ = fs::remove_dir_all(&dir);
+        let odb = ObjectDatabase::new(dir.join("objects"));
+        let mut objects = Vec::new();
+        for (n, data) in versions().iter().enumerate() {
+            objects.push((odb.write_raw(ObjectKind::Blob, data).unwrap(), b"file.txt".to_vec()));
+            objects.push((odb.write_raw(ObjectKind::Blob, format!("small {n}").as_bytes()).unwrap(), Vec::new()));
+        }
+
+        let data = build_pack(&odb, &objects, PackOptions::default()).unwrap();
+        let path = write_pack_files(&dir.join("pack"), &data).unwrap();
+        let pack = Pack::open(&path.with_extension("idx")).unwrap();
+        let no_external: BaseLookup = &|oid| Err(RitError::ObjectNotFound(oid.to_hex()));
+
+        assert_eq!(pack.index().len(), objects.len());
+        for (oid, _) in &objects {
+            assert_eq!(pack.read(oid, no_external).unwrap().unwrap(), odb.read_raw(oid).unwrap());
+        }
+        let entries = pack.verify(no_external).unwrap();
+        assert!(entries.iter().filter(|entry| entry.delta.is_some()).count() >= 4);
+        assert!(entries.iter().all(|entry| entry.kind == ObjectKind::Blob));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_ofs_and_ref_deltas() {
+        let versions = versions();
+        let oids: Vec<Oid> = versions.iter().map(|data| Oid::hash(ObjectKind::Blob, data)).collect();
+        let delta = |from: usize, to: usize| create_delta(&versions[from], &versions[to], usize::MAX).unwrap();
+
+        // A whole object, an OFS_DELTA and a REF_DELTA on it, and a REF_DELTA
+        // on an object outside the pack, as in a thin pack
+        let mut pack = Vec::new();
+        pack.extend_from_slice(&PACK_SIGNATURE);
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&4u32.to_be_bytes());
+        let mut index = Vec::new();
+        let mut add = |pack: &mut Vec<u8>, oid: Oid, raw: Vec<u8>| {
+            index.push((oid, pack.len() as u64, crc32fast::hash(&raw)));
+            pack.extend_from_slice(&raw);
+        };
+        add(&mut pack, oids[0], raw_entry(OBJ_BLOB, &[], &versions[0]));
+        // Distance back to the base, big-endian, less one per extra byte
+        let mut distance = pack.len() as u64 - 12;
+        let mut encoded = vec![(distance & 0x7f) as u8];
+        while distance >> 7 > 0 {
+            distance = (distance >> 7) - 1;
+            encoded.push(0x80 | (distance & 0x7f) as u8);
+        }
+        encoded.reverse();
+        add(&mut pack, oids[1], raw_entry(OBJ_OFS_DELTA, &encoded, &delta(0, 1)));
+        add(&mut pack, oids[2], raw_entry(OBJ_REF_DELTA, oids[1].as_bytes(), &delta(1, 2)));
+        add(&mut pack, oids[3], raw_entry(OBJ_REF_DELTA, oids[5].as_bytes(), &delta(5, 3)));
+        let checksum = Oid::from_bytes(&Sha1::digest(&pack)).unwrap();
+        pack.extend_from_slice(checksum.as_bytes());
+        index.sort();
+
+        let path = temp_path("deltas.pack");
+        fs::write(&path, &pack).unwrap();
+        fs::write(path.with_extension("idx"), build_index(&index, &checksum)).unwrap();
+        let pack = Pack::open(&path.with_extension("idx")).unwrap();
+
+        let external: BaseLookup = &|oid| match *oid == oids[5] {
+            true => Ok((ObjectKind::Blob, versions[5].clone())),
+            false => Err(RitError::ObjectNotFound(oid.to_hex())),
+        };
+        for (n, oid) in oids[..4].iter().enumerate() {
+            assert_eq!(pack.read(oid, external).unwrap(), Some((ObjectKind::Blob, versions[n].clone())));
+        }
+        assert!(pack.read(&oids[3], &|oid| Err(RitError::ObjectNotFound(oid.to_hex()))).is_err());
+
+        let depths: Vec<Option<(usize, Oid)>> = pack.verify(external).unwrap().iter().map(|e| e.delta).collect();
+        assert_eq!(depths, [None, Some((1, oids[0])), Some((2, oids[1])), Some((1, oids[5]))]);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("idx")).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupt_entry_headers() {
+        let oids: Vec<Oid> = (0..3u8).map(|n| Oid::hash(ObjectKind::Blob, &[n])).collect();
+        let mut pack = Vec::new();
+        pack.extend_from_slice(&PACK_SIGNATURE);
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&3u32.to_be_bytes());
+        let mut index = Vec::new();
+
+        // A size running past 64 bits, a base distance that overflows, and
+        // a size far larger than the data
+        index.push((oids[0], pack.len() as u64, 0));
+        pack.push(0x80 | (OBJ_BLOB << 4));
+        pack.extend_from_slice(&[0xff; 10]);
+        pack.push(0);
+        index.push((oids[1], pack.len() as u64, 0));
+        pack.push(OBJ_OFS_DELTA << 4 | 1);
+        pack.extend_from_slice(&[0xff; 10]);
+        pack.push(0x7f);
+        index.push((oids[2], pack.len() as u64, 0));
+        pack.extend_from_slice(&raw_entry_sized(OBJ_BLOB, 1 << 62, &[], b"x"));
+        let checksum = Oid::from_bytes(&Sha1::digest(&pack)).unwrap();
+        pack.extend_from_slice(checksum.as_bytes());
+        index.sort();
+
+        let path = temp_path("corrupt.pack");
+        fs::write(&path, &pack).unwrap();
+        fs::write(path.with_extension("idx"), build_index(&index, &checksum)).unwrap();
+        let pack = Pack::open(&path.with_extension("idx")).unwrap();
+        for oid in &oids {
+            let result = pack.read(oid, &|oid| Err(RitError::ObjectNotFound(oid.to_hex())));
+            assert!(matches!(result, Err(RitError::CorruptPack { .. })), "{result:?}");
+        }
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("idx")).unwrap();
+    }
+
+    #[test]
+    fn large_offsets_are_bounds_checked() {
+        let (low, high) = (Oid::hash(ObjectKind::Blob, b"a"), Oid::hash(ObjectKind::Blob, b"b"));
+        let mut objects = vec![(low, 1 << 32, 0), (high, 12, 0)];
+        objects.sort();
+        let mut data = build_index(&objects, &Oid::ZERO);
+
+        let path = temp_path("large.idx");
+        fs::write(&path, &data).unwrap();
+        let index = PackIndex::open(&path).unwrap();
+        let large = index.find(&low).unwrap();
+        assert_eq!(index.offset(large).unwrap(), 1 << 32);
+        assert_eq!(index.offset(index.find(&high).unwrap()).unwrap(), 12);
+
+        // Point the entry past the end of the one-entry table
+        let pos = IDX_HEADER_LEN + 2 * (Oid::LEN + 4) + large * 4;
+        data[pos..pos + 4].copy_from_slice(&0x8000_0001u32.to_be_bytes());
+        fs::write(&path, &data).unwrap();
+        let index = PackIndex::open(&path).unwrap();
+        assert!(matches!(index.offset(large), Err(RitError::CorruptPack { .. })));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
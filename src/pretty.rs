@@ -0,0 +1,339 @@
+//! Commit formatting for `log`: the built-in formats and `--format`
+//! placeholders.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Result, RitError};
+use crate::object::{Commit, Signature};
+use crate::odb::{ObjectDatabase, DEFAULT_ABBREV};
+use crate::oid::Oid;
+use crate::refs::{self, RefValue};
+use crate::repository::Repository;
+use crate::revision;
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// A `--format` value: one of git's named formats or a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    /// A template with `%` placeholders. `tformat:` templates (and bare ones)
+    /// end each commit with a newline; `format:` ones put it between commits.
+    Template { template: String, terminated: bool },
+}
+
+impl Format {
+    /// Parse a `--format`/`--pretty` argument: a named format, a template
+    /// prefixed with `format:` or `tformat:`, or a bare template with at
+    /// least one `%` placeholder. An empty one prints nothing.
+    pub fn parse(spec: &str) -> Result<Format> {
+        let template = |template: &str, terminated| Format::Template { template: template.to_string(), terminated };
+        Ok(match spec {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            _ => match (spec.strip_prefix("format:"), spec.strip_prefix("tformat:")) {
+                (Some(format), _) => template(format, false),
+                (_, Some(tformat)) => template(tformat, true),
+                _ if spec.is_empty() || spec.contains('%') => template(spec, true),
+                _ => return Err(RitError::Fatal(format!("invalid --pretty format: {spec}"))),
+            },
+        })
+    }
+
+    /// Whether each commit ends with a newline, rather than commits being
+    /// separated by one.
+    pub fn is_terminated(&self) -> bool {
+        matches!(self, Format::Oneline | Format::Template { terminated: true, .. })
+    }
+}
+
+/// Ref names to show next to commits, as `%d` and `%D` do.
+#[derive(Debug, Default)]
+pub struct Decorations {
+    names: HashMap<Oid, Vec<String>>,
+}
+
+impl Decorations {
+    /// Decorate every commit a ref or HEAD points at, in git's order: HEAD
+    /// first (as "HEAD -> main" when on a branch), then the other refs in
+    /// reverse name order, symbolic ones included. Tags decorate the commit
+    /// they peel to.
+    pub fn load(repo: &Repository) -> Result<Decorations> {
+        let mut refs = repo.refs().list()?;
+        for (name, target) in repo.refs().list_symbolic()? {
+            refs.extend(repo.refs().resolve(&target)?.map(|oid| (name, oid)));
+        }
+        refs.sort();
+
+        let mut names: HashMap<Oid, Vec<String>> = HashMap::new();
+        for (name, oid) in refs.into_iter().rev() {
+            let short = if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag: {tag}")
+            } else {
+                let short = name.strip_prefix("refs/heads/").or_else(|| name.strip_prefix("refs/remotes/"));
+                short.unwrap_or(&name).to_string()
+            };
+            let oid = revision::peel(repo, &oid, None)?;
+            names.entry(oid).or_default().push(short);
+        }
+
+        if let Some(head) = repo.refs().resolve(refs::HEAD)? {
+            let names = names.entry(head).or_default();
+            match repo.refs().read(refs::HEAD)? {
+                Some(RefValue::Symbolic(branch)) if branch.starts_with("refs/heads/") => {
+                    let branch = &branch["refs/heads/".len()..];
+                    names.retain(|name| name != branch);
+                    names.insert(0, format!("HEAD -> {branch}"));
+                }
+                _ => names.insert(0, refs::HEAD.to_string()),
+            }
+        }
+        Ok(Decorations { names })
+    }
+
+    // "tag: v1, main", or empty if nothing points at `oid`
+    fn get(&self, oid: &Oid) -> String {
+        self.names.get(oid).map(|names| names.join(", ")).unwrap_or_default()
+    }
+}
+
+/// Render `commit` in `format`, without the newline that ends or separates
+/// commits; the built-in multi-line formats end with a newline of their own.
+pub fn format_commit(
+    odb: &ObjectDatabase,
+    oid: &Oid,
+    commit: &Commit,
+    format: &Format,
+    decorations: &Decorations,
+) -> Result<String> {
+    let abbrev = |oid: &Oid| odb.abbreviate(oid, DEFAULT_ABBREV);
+
+    let mut out = String::new();
+    match format {
+        Format::Oneline => out.push_str(&format!("{} {}", abbrev(oid)?, commit.summary())),
+        Format::Template { template, .. } => out.push_str(&expand(template, odb, oid, commit, decorations)?),
+        Format::Short | Format::Medium | Format::Full => {
+            out.push_str(&format!("commit {oid}\n"));
+            if commit.parents.len() > 1 {
+                let parents: Vec<String> = commit.parents.iter().map(abbrev).collect::<Result<_>>()?;
+                out.push_str(&format!("Merge: {}\n", parents.join(" ")));
+            }
+            out.push_str(&format!("Author: {} <{}>\n", commit.author.name, commit.author.email));
+            match format {
+                Format::Medium => out.push_str(&format!("Date:   {}\n", format_date(&commit.author))),
+                Format::Full => out.push_str(&format!("Commit: {} <{}>\n", commit.committer.name, commit.committer.email)),
+                _ => {}
+            }
+            out.push('\n');
+
+            let message = if *format == Format::Short { commit.summary() } else { commit.message_text() };
+            for line in message.trim_end().lines() {
+                if line.is_empty() {
+                    out.push('\n');
+                } else {
+                    out.push_str(&format!("    {line}\n"));
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Expand %-placeholders; unknown ones are kept as written
+fn expand(
+    template: &str,
+    odb: &ObjectDatabase,
+    oid: &Oid,
+    commit: &Commit,
+    decorations: &Decorations,
+) -> Result<String> {
+    let abbrev = |oid: &Oid| odb.abbreviate(oid, DEFAULT_ABBREV);
+    let join = |oids: Vec<String>| oids.join(" ");
+
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+
+        let (text, len) = match rest.as_bytes() {
+            [b'%', ..] => ("%".to_string(), 1),
+            [b'n', ..] => ("\n".to_string(), 1),
+            [b'H', ..] => (oid.to_hex(), 1),
+            [b'h', ..] => (abbrev(oid)?, 1),
+            [b'T', ..] => (commit.tree.to_hex(), 1),
+            [b't', ..] => (abbrev(&commit.tree)?, 1),
+            [b'P', ..] => (join(commit.parents.iter().map(Oid::to_hex).collect()), 1),
+            [b'p', ..] => (join(commit.parents.iter().map(abbrev).collect::<Result<_>>()?), 1),
+            [b's', ..] => (commit.summary(), 1),
+            [b'b', ..] => (body(&commit.message_text()).to_string(), 1),
+            [b'B', ..] => (commit.message_text(), 1),
+            [b'd', ..] => match decorations.get(oid) {
+                names if names.is_empty() => (names, 1),
+                names => (format!(" ({names})"), 1),
+            },
+            [b'D', ..] => (decorations.get(oid), 1),
+            [b'x', hi, lo, ..] if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+                let byte = u8::from_str_radix(&rest[1..3], 16).unwrap_or_default();
+                (char::from(byte).to_string(), 3)
+            }
+            [b'a', c, ..] => match signature_field(&commit.author, *c) {
+                Some(text) => (text, 2),
+                None => ("%a".to_string(), 1),
+            },
+            [b'c', c, ..] => match signature_field(&commit.committer, *c) {
+                Some(text) => (text, 2),
+                None => ("%c".to_string(), 1),
+            },
+            _ => ("%".to_string(), 0),
+        };
+
+        out.push_str(&text);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+// %an, %ae, %ad, %at, %ar, %aD, %ai, %aI, %as and their committer equivalents
+fn signature_field(sig: &Signature, field: u8) -> Option<String> {
+    Some(match field {
+        b'n' => sig.name.clone(),
+        b'e' => sig.email.clone(),
+        b'd' => format_date(sig),
+        b't' => sig.time.to_string(),
+        b'r' => format_relative(sig.time),
+        b'D' => format_rfc2822(sig),
+        b'i' => format_iso(sig, false),
+        b'I' => format_iso(sig, true),
+        b's' => format_iso(sig, false)[..10].to_string(),
+        _ => return None,
+    })
+}
+
+// Everything after the subject paragraph
+fn body(message: &str) -> &str {
+    match message.find("\n\n") {
+        Some(pos) => message[pos + 2..].trim_start_matches('\n'),
+        None => "",
+    }
+}
+
+/// Git's default date format, e.g. "Sun Oct 18 04:46:37 2026 +0200", in the
+/// signature's own timezone.
+pub fn format_date(sig: &Signature) -> String {
+    let (year, month, day, weekday, hour, minute, second) = civil(sig.time + sig.offset as i64 * 60);
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        WEEKDAYS[weekday],
+        MONTHS[month - 1],
+        day,
+        hour,
+        minute,
+        second,
+        year,
+        sig.format_offset()
+    )
+}
+
+/// RFC 2822 date, e.g. "Sun, 18 Oct 2026 04:46:37 +0200".
+pub fn format_rfc2822(sig: &Signature) -> String {
+    let (year, month, day, weekday, hour, minute, second) = civil(sig.time + sig.offset as i64 * 60);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[weekday],
+        day,
+        MONTHS[month - 1],
+        year,
+        hour,
+        minute,
+        second,
+        sig.format_offset()
+    )
+}
+
+/// ISO 8601 date: git's "2026-10-18 04:46:37 +0200", or with `strict` the
+/// standard "2026-10-18T04:46:37+02:00".
+pub fn format_iso(sig: &Signature, strict: bool) -> String {
+    let (year, month, day, _, hour, minute, second) = civil(sig.time + sig.offset as i64 * 60);
+    let date = format!("{year:04}-{month:02}-{day:02}");
+    let time = format!("{hour:02}:{minute:02}:{second:02}");
+    let tz = sig.format_offset();
+    if strict {
+        format!("{date}T{time}{}:{}", &tz[..3], &tz[3..])
+    } else {
+        format!("{date} {time} {tz}")
+    }
+}
+
+/// How long ago `time` was, e.g. "3 days ago", rounded the way git does.
+pub fn format_relative(time: i64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+
+    let mut diff = now - time;
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        // Years and months up to five years
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+// Calendar fields of a Unix timestamp (already shifted to local time):
+// (year, month 1-12, day, weekday index into WEEKDAYS, hour, minute, second)
+fn civil(secs: i64) -> (i64, usize, i64, usize, i64, i64, i64) {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    // 1970-01-01 was a Thursday
+    let weekday = days.rem_euclid(7) as usize;
+    (year, month as usize, day, weekday, rem / 3600, rem % 3600 / 60, rem % 60)
+}
@@ -0,0 +1,48 @@
+//! Quoting paths for display the way git does, so that names with control
+//! characters, quotes or (by default) non-ASCII bytes stay on one line and
+//! can be told apart.
+
+/// `path` as git prints it, as raw bytes: unchanged if it is plain, otherwise in double
+/// quotes with C-style escapes. `quote_high` escapes bytes of 0x80 and up
+/// as octal, like `core.quotePath` (on by default).
+pub fn quote_path(path: &[u8], quote_high: bool) -> Vec<u8> {
+    let needs_quoting = |b: u8| b < 0x20 || b == b'"' || b == b'\\' || b == 0x7f || (quote_high && b >= 0x80);
+    if !path.iter().any(|&b| needs_quoting(b)) {
+        return path.to_vec();
+    }
+
+    let mut out = Vec::with_capacity(path.len() + 2);
+    out.push(b'"');
+    for &b in path {
+        match b {
+            b'\x07' => out.extend_from_slice(b"\\a"),
+            b'\x08' => out.extend_from_slice(b"\\b"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\x0b' => out.extend_from_slice(b"\\v"),
+            b'\x0c' => out.extend_from_slice(b"\\f"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'"' | b'\\' => out.extend_from_slice(&[b'\\', b]),
+            _ if needs_quoting(b) => out.extend_from_slice(format!("\\{b:03o}").as_bytes()),
+            _ => out.push(b),
+        }
+    }
+    out.push(b'"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_like_git() {
+        assert_eq!(quote_path(b"plain name.txt", true), b"plain name.txt");
+        assert_eq!(quote_path(b"tab\there", true), br#""tab\there""#);
+        assert_eq!(quote_path(b"say \"hi\"\\", true), br#""say \"hi\"\\""#);
+        assert_eq!(quote_path(b"\x01\x7f", true), br#""\001\177""#);
+        assert_eq!(quote_path("caf\u{e9}".as_bytes(), true), br#""caf\303\251""#);
+        assert_eq!(quote_path("caf\u{e9}".as_bytes(), false), "caf\u{e9}".as_bytes());
+        assert_eq!(quote_path(b"\xff", false), b"\xff");
+    }
+}
@@ -0,0 +1,512 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::error::{Result, RitError};
+use crate::lockfile::Lockfile;
+use crate::object::Signature;
+use crate::oid::Oid;
+
+pub const HEAD: &str = "HEAD";
+
+// Symbolic refs pointing at symbolic refs are followed at most this deep
+const MAX_SYMREF_DEPTH: usize = 5;
+
+const PACKED_REFS: &str = "packed-refs";
+
+/// Where a short name like `main` is looked for, in order.
+const DWIM_RULES: [&str; 6] = ["{}", "refs/{}", "refs/tags/{}", "refs/heads/{}", "refs/remotes/{}", "refs/remotes/{}/HEAD"];
+
+/// Contents of a single ref.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefValue {
+    Direct(Oid),
+    /// "ref: <target>"
+    Symbolic(String),
+}
+
+/// A line of `packed-refs`, with the object an annotated tag peels to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackedRef {
+    pub name: String,
+    pub oid: Oid,
+    pub peeled: Option<Oid>,
+}
+
+/// One line of a reflog.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub old: Oid,
+    pub new: Oid,
+    pub committer: Signature,
+    pub message: String,
+}
+
+/// Loose refs stored as files under the git directory, backed by
+/// `packed-refs` for refs that have no loose file.
+pub struct Refs {
+    git_dir: PathBuf,
+}
+
+impl Refs {
+    pub fn new(git_dir: impl Into<PathBuf>) -> Refs {
+        Refs { git_dir: git_dir.into() }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.git_dir.join(name)
+    }
+
+    fn read_loose(&self, name: &str) -> Result<Option<RefValue>> {
+        let contents = match fs::read_to_string(self.path(name)) {
+            Ok(contents) => contents,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::IsADirectory) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let contents = contents.trim_end();
+        match contents.strip_prefix("ref:") {
+            Some(target) => Ok(Some(RefValue::Symbolic(target.trim().to_string()))),
+            None => Ok(Some(RefValue::Direct(Oid::from_hex(contents)?))),
+        }
+    }
+
+    /// Read one ref without following symbolic refs.
+    pub fn read(&self, name: &str) -> Result<Option<RefValue>> {
+        if let Some(value) = self.read_loose(name)? {
+            return Ok(Some(value));
+        }
+        Ok(self.packed_refs()?.into_iter().find(|r| r.name == name).map(|r| RefValue::Direct(r.oid)))
+    }
+
+    /// Every entry of `packed-refs`, in file order.
+    pub fn packed_refs(&self) -> Result<Vec<PackedRef>> {
+        let contents = match fs::read_to_string(self.path(PACKED_REFS)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut refs: Vec<PackedRef> = Vec::new();
+        for line in contents.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            if let Some(peeled) = line.strip_prefix('^') {
+                if let Some(last) = refs.last_mut() {
+                    last.peeled = Some(Oid::from_hex(peeled)?);
+                }
+                continue;
+            }
+
+            let (oid, name) = line
+                .split_once(' ')
+                .ok_or_else(|| RitError::InvalidArgument(format!("unexpected line in packed-refs: {line}")))?;
+            refs.push(PackedRef { name: name.to_string(), oid: Oid::from_hex(oid)?, peeled: None });
+        }
+
+        Ok(refs)
+    }
+
+    /// Every ref under `refs/` that holds an object ID, loose refs taking
+    /// precedence over packed ones, sorted by name.
+    pub fn list(&self) -> Result<Vec<(String, Oid)>> {
+        let mut refs: Vec<(String, Oid)> = Vec::new();
+        for name in self.loose_names()? {
+            if let Some(RefValue::Direct(oid)) = self.read_loose(&name)? {
+                refs.push((name, oid));
+            }
+        }
+
+        for packed in self.packed_refs()? {
+            if !refs.iter().any(|(name, _)| *name == packed.name) {
+                refs.push((packed.name, packed.oid));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    /// Every symbolic ref under `refs/` (such as `refs/remotes/origin/HEAD`)
+    /// with its target, sorted by name.
+    pub fn list_symbolic(&self) -> Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        for name in self.loose_names()? {
+            if let Some(RefValue::Symbolic(target)) = self.read_loose(&name)? {
+                refs.push((name, target));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    // Names of the well-formed loose ref files under refs/
+    fn loose_names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut dirs = vec![self.path("refs")];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+
+                let Ok(name) = path.strip_prefix(&self.git_dir) else { continue };
+                let Some(name) = name.to_str() else { continue };
+                if check_ref_format(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Follow symbolic refs from `name` to the ref that holds an object ID,
+    /// which may not exist yet (an unborn branch).
+    pub fn resolve_name(&self, name: &str) -> Result<String> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.read(&name)? {
+                Some(RefValue::Symbolic(target)) => name = target,
+                _ => return Ok(name),
+            }
+        }
+        Err(RitError::InvalidArgument(format!("symbolic ref loop at {name}")))
+    }
+
+    /// The object ID `name` ultimately points at, if any.
+    pub fn resolve(&self, name: &str) -> Result<Option<Oid>> {
+        match self.read(&self.resolve_name(name)?)? {
+            Some(RefValue::Direct(oid)) => Ok(Some(oid)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Expand a short name like `main` or `tags/v1` to the first existing
+    /// full ref name, using git's lookup order.
+    pub fn dwim(&self, short: &str) -> Result<Option<String>> {
+        if !check_ref_format(short) {
+            return Ok(None);
+        }
+        for rule in DWIM_RULES {
+            let name = rule.replace("{}", short);
+            if self.resolve(&name)?.is_some() {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Point `name` (after following symbolic refs) at `new`.
+    ///
+    /// With `expected`, the update only happens if the ref currently points
+    /// there; `Oid::ZERO` means the ref must not exist yet. The check and the
+    /// write happen while holding the ref's lockfile.
+    pub fn update(&self, name: &str, new: &Oid, expected: Option<&Oid>, message: &str) -> Result<()> {
+        let target = self.resolve_name(name)?;
+        check_name(&target)?;
+
+        let mut lock = Lockfile::acquire(&self.path(&target))?;
+        let old = self.check_current(&target, expected)?;
+
+        lock.write_all(format!("{new}\n").as_bytes())?;
+        lock.commit()?;
+
+        self.log_update(&target, &old, new, message)?;
+        if target != HEAD && self.resolve_name(HEAD)? == target {
+            // Moving the checked out branch moves HEAD too
+            self.log_update(HEAD, &old, new, message)?;
+        }
+        Ok(())
+    }
+
+    /// Delete `name` (after following symbolic refs) from both the loose
+    /// files and `packed-refs`, with the same `expected` check as `update`.
+    pub fn delete(&self, name: &str, expected: Option<&Oid>) -> Result<()> {
+        check_name(name)?;
+        let target = self.resolve_name(name)?;
+        check_name(&target)?;
+        let lock = Lockfile::acquire(&self.path(&target))?;
+        let old = self.check_current(&target, expected)?;
+        if old == Oid::ZERO {
+            return Err(RitError::InvalidArgument(format!("ref '{target}' does not exist")));
+        }
+
+        let packed = self.packed_refs()?;
+        if packed.iter().any(|r| r.name == target) {
+            let remaining: Vec<PackedRef> = packed.into_iter().filter(|r| r.name != target).collect();
+            self.write_packed_refs(&remaining)?;
+        }
+
+        match fs::remove_file(self.path(&target)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let _ = fs::remove_file(self.log_path(&target));
+        drop(lock);
+        Ok(())
+    }
+
+    /// Make `name` a symbolic ref pointing at `target`.
+    pub fn set_symbolic(&self, name: &str, target: &str) -> Result<()> {
+        check_name(name)?;
+        if !target.starts_with("refs/") || !check_ref_format(target) {
+            return Err(RitError::InvalidArgument(format!("refusing to point {name} outside of refs/: {target}")));
+        }
+
+        let mut lock = Lockfile::acquire(&self.path(name))?;
+        lock.write_all(format!("ref: {target}\n").as_bytes())?;
+        lock.commit()
+    }
+
+    /// Replace `packed-refs` with `refs`, which must be sorted by name.
+    pub fn write_packed_refs(&self, refs: &[PackedRef]) -> Result<()> {
+        let mut contents = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+        for r in refs {
+            contents.push_str(&format!("{} {}\n", r.oid, r.name));
+            if let Some(peeled) = r.peeled {
+                contents.push_str(&format!("^{peeled}\n"));
+            }
+        }
+
+        let mut lock = Lockfile::acquire(&self.path(PACKED_REFS))?;
+        lock.write_all(contents.as_bytes())?;
+        lock.commit()
+    }
+
+    /// Move refs into `packed-refs`: `refs` becomes its new contents (sorted
+    /// by name), and each loose file that still holds the packed value is
+    /// deleted under its lock.
+    pub fn pack(&self, refs: &[PackedRef]) -> Result<()> {
+        self.write_packed_refs(refs)?;
+
+        for r in refs {
+            let path = self.path(&r.name);
+            let lock = match Lockfile::acquire(&path) {
+                Ok(lock) => lock,
+                // Busy: leave the loose copy, which still takes precedence
+                Err(RitError::Locked(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            if self.read_loose(&r.name)? == Some(RefValue::Direct(r.oid)) {
+                fs::remove_file(&path)?;
+            }
+            drop(lock);
+
+            // Drop directories left empty, but keep refs/heads and the like
+            let top = self.path("refs");
+            let mut dir = path.parent();
+            while let Some(d) = dir.filter(|d| d.parent() != Some(top.as_path()) && *d != top) {
+                if fs::remove_dir(d).is_err() {
+                    break;
+                }
+                dir = d.parent();
+            }
+        }
+        Ok(())
+    }
+
+    // Current value of `name`, or ZERO if it does not exist, checked against
+    // `expected`
+    fn check_current(&self, name: &str, expected: Option<&Oid>) -> Result<Oid> {
+        let current = match self.read(name)? {
+            Some(RefValue::Direct(oid)) => oid,
+            // Someone made it symbolic after `resolve_name` looked
+            Some(RefValue::Symbolic(target)) => {
+                return Err(RitError::RefUpdateRejected(format!(
+                    "cannot lock ref '{name}': is now a symbolic ref to '{target}'"
+                )))
+            }
+            None => Oid::ZERO,
+        };
+
+        if let Some(expected) = expected {
+            if *expected != current {
+                return Err(RitError::RefUpdateRejected(format!(
+                    "cannot lock ref '{name}': is at {current} but expected {expected}"
+                )));
+            }
+        }
+        Ok(current)
+    }
+
+    /// The reflog of `name`, oldest entry first; empty if there is none.
+    /// Messages are stored as given, so they are decoded lossily.
+    pub fn read_reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        let contents = match fs::read(self.log_path(name)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in contents.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            // "<old> <new> <committer>\t<message>"
+            let (head, message) = match line.iter().position(|&b| b == b'\t') {
+                Some(tab) => (&line[..tab], &line[tab + 1..]),
+                None => (line, &b""[..]),
+            };
+            let bad = || RitError::CorruptReflog {
+                name: name.to_string(),
+                reason: format!("malformed line '{}'", String::from_utf8_lossy(line)),
+            };
+            let new_end = 2 * Oid::HEX_LEN + 1;
+            if head.len() <= new_end + 1 || head[Oid::HEX_LEN] != b' ' || head[new_end] != b' ' {
+                return Err(bad());
+            }
+
+            entries.push(ReflogEntry {
+                old: Oid::from_hex_bytes(&head[..Oid::HEX_LEN]).map_err(|_| bad())?,
+                new: Oid::from_hex_bytes(&head[Oid::HEX_LEN + 1..new_end]).map_err(|_| bad())?,
+                committer: Signature::parse(&String::from_utf8_lossy(&head[new_end + 1..])).map_err(|_| bad())?,
+                message: String::from_utf8_lossy(message).into_owned(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn log_path(&self, name: &str) -> PathBuf {
+        self.git_dir.join("logs").join(name)
+    }
+
+    // Reflogs are kept for HEAD and branches, and for any ref that already
+    // has one
+    fn log_update(&self, name: &str, old: &Oid, new: &Oid, message: &str) -> Result<()> {
+        let path = self.log_path(name);
+        if name != HEAD && !name.starts_with("refs/heads/") && !path.exists() {
+            return Ok(());
+        }
+
+        // A ref update does not need a configured identity; git then records
+        // the login name instead
+        let committer = Signature::from_env_or_login("COMMITTER", &Config::for_git_dir(&self.git_dir)?)?;
+        let line = format!("{old} {new} {committer}\t{}\n", message.lines().next().unwrap_or(""));
+
+        fs::create_dir_all(path.parent().unwrap())?;
+        OpenOptions::new().create(true).append(true).open(&path)?.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name == HEAD || (name.starts_with("refs/") && check_ref_format(name)) {
+        Ok(())
+    } else {
+        Err(RitError::InvalidArgument(format!("invalid ref name: '{name}'")))
+    }
+}
+
+/// Whether `name` is a valid ref name by git's rules: no component starts
+/// with `.` or ends with `.lock`, no `..`, `@{`, control characters, spaces
+/// or any of `~^:?*[\`, and no leading, trailing or doubled `/`.
+pub fn check_ref_format(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.ends_with('.') || name.contains("..") || name.contains("@{") {
+        return false;
+    }
+    if name.bytes().any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b)) {
+        return false;
+    }
+    name.split('/').all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::ObjectKind;
+
+    #[test]
+    fn head_follows_the_current_branch() {
+        let git_dir = std::env::temp_dir().join(format!("rit-refs-head-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&git_dir);
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::write(git_dir.join(HEAD), "ref: refs/heads/main\n").unwrap();
+        let refs = Refs::new(&git_dir);
+
+        // An unborn branch resolves to its name but not to a commit
+        assert_eq!(refs.resolve_name(HEAD).unwrap(), "refs/heads/main");
+        assert_eq!(refs.resolve(HEAD).unwrap(), None);
+
+        let oid = Oid::hash(ObjectKind::Commit, b"first");
+        refs.update(HEAD, &oid, None, "commit (initial): first").unwrap();
+        assert_eq!(refs.resolve(HEAD).unwrap(), Some(oid));
+        assert_eq!(fs::read_to_string(git_dir.join("refs/heads/main")).unwrap(), format!("{oid}\n"));
+        assert_eq!(refs.read(HEAD).unwrap(), Some(RefValue::Symbolic("refs/heads/main".to_string())));
+
+        fs::write(git_dir.join("refs/heads/loop"), "ref: refs/heads/loop\n").unwrap();
+        assert!(refs.resolve("refs/heads/loop").is_err());
+
+        fs::remove_dir_all(&git_dir).unwrap();
+    }
+
+    #[test]
+    fn packed_refs_and_expected_values() {
+        let git_dir = std::env::temp_dir().join(format!("rit-refs-packed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&git_dir);
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::write(git_dir.join(HEAD), "ref: refs/heads/main\n").unwrap();
+        let refs = Refs::new(&git_dir);
+        let (one, two) = (Oid::hash(ObjectKind::Commit, b"one"), Oid::hash(ObjectKind::Commit, b"two"));
+
+        let tag = PackedRef { name: "refs/tags/v1".to_string(), oid: two, peeled: Some(one) };
+        let main = PackedRef { name: "refs/heads/main".to_string(), oid: one, peeled: None };
+        refs.write_packed_refs(&[main.clone(), tag.clone()]).unwrap();
+        assert_eq!(refs.packed_refs().unwrap(), [main, tag]);
+        assert_eq!(refs.resolve(HEAD).unwrap(), Some(one));
+        assert_eq!(refs.dwim("v1").unwrap().as_deref(), Some("refs/tags/v1"));
+        assert_eq!(refs.dwim("main").unwrap().as_deref(), Some("refs/heads/main"));
+        assert_eq!(refs.dwim("missing").unwrap(), None);
+
+        // Updates take full names and check the old value under the lock
+        assert!(matches!(refs.update("main", &two, None, "m"), Err(RitError::InvalidArgument(_))));
+        assert!(matches!(refs.update(HEAD, &two, Some(&two), "m"), Err(RitError::RefUpdateRejected(_))));
+        assert!(refs.update("refs/heads/main", &two, Some(&Oid::ZERO), "m").is_err());
+        refs.update(HEAD, &two, Some(&one), "m").unwrap();
+        assert_eq!(refs.resolve("refs/heads/main").unwrap(), Some(two));
+        assert_eq!(fs::read_to_string(git_dir.join("logs/HEAD")).unwrap().lines().count(), 1);
+
+        refs.delete("refs/tags/v1", Some(&two)).unwrap();
+        assert_eq!(refs.packed_refs().unwrap().len(), 1);
+        assert!(refs.delete("refs/tags/v1", None).is_err());
+
+        refs.set_symbolic("refs/heads/alias", "refs/heads/main").unwrap();
+        assert_eq!(refs.resolve("refs/heads/alias").unwrap(), Some(two));
+        assert!(refs.set_symbolic(HEAD, "main").is_err());
+
+        fs::remove_dir_all(&git_dir).unwrap();
+    }
+
+    #[test]
+    fn ref_names_follow_git_rules() {
+        for name in ["refs/heads/main", "refs/heads/feature/x", "refs/tags/v1.0", "HEAD", "a-b_c"] {
+            assert!(check_ref_format(name), "{name}");
+        }
+        for name in ["", "@", "refs/heads/", "refs//x", "refs/.hidden", "a..b", "x.lock/y", "a.lock", "tip.",
+                     "a@{1}", "with space", "a~1", "a^", "a:b", "a?", "a*", "a[", "a\\b", "tab\t"] {
+            assert!(!check_ref_format(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn delete_stays_inside_the_git_dir() {
+        let dir = std::env::temp_dir().join(format!("rit-refs-delete-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let git_dir = dir.join("repo/.git");
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        let victim = dir.join("victim");
+        fs::write(&victim, "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n").unwrap();
+
+        let refs = Refs::new(&git_dir);
+        assert!(matches!(refs.delete("../../victim", None), Err(RitError::InvalidArgument(_))));
+        assert!(victim.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+//! Rename and copy detection: pairing files added by a change with removed
+//! (or, for copies, surviving) files whose contents are identical or alike.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::diff::{self, FileChange, Rename};
+use crate::error::{Result, RitError};
+use crate::object::{MODE_EXECUTABLE, MODE_FILE, MODE_TREE};
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+use crate::status::Change;
+
+/// Similarity of identical files.
+pub const MAX_SCORE: u32 = 60000;
+
+/// Similarity needed when no threshold is given: half.
+pub const DEFAULT_SCORE: u32 = MAX_SCORE / 2;
+
+/// Inexact candidates kept for each added file.
+const CANDIDATES_PER_FILE: usize = 4;
+
+/// Content is compared in chunks of at most this many bytes, or up to the
+/// end of a line.
+const CHUNK_LEN: usize = 64;
+
+/// Chunks are told apart by a hash modulo this prime.
+const HASH_BASE: u32 = 107927;
+
+/// How [`detect_renames`] pairs files up.
+#[derive(Clone, Copy, Debug)]
+pub struct RenameOptions {
+    /// Similarity needed for files that are not identical, out of [`MAX_SCORE`].
+    pub min_score: u32,
+    /// Also take files that were modified, or are passed in as unchanged,
+    /// as sources; their copies keep the source in place.
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> RenameOptions {
+        RenameOptions { min_score: DEFAULT_SCORE, copies: false }
+    }
+}
+
+/// Parse a similarity threshold as git does: digits that are a fraction
+/// after an implied decimal point ("5" is 50%, "05" is 5%), or a percentage
+/// with `%` ("5%"). An empty or zero threshold means the default.
+pub fn parse_score(text: &str) -> Result<u32> {
+    let (mut number, mut scale, mut dot) = (0u64, 1u64, false);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            // A percentage always ends the threshold
+            '%' if chars.peek().is_none() => {
+                scale = if dot { scale * 100 } else { 100 };
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    number = number * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => return Err(RitError::InvalidArgument(format!("invalid similarity threshold '{text}'"))),
+        }
+    }
+
+    let score = if number >= scale { MAX_SCORE } else { (u64::from(MAX_SCORE) * number / scale) as u32 };
+    Ok(if score == 0 { DEFAULT_SCORE } else { score })
+}
+
+// A file that added files can be paired with
+struct Source {
+    path: Vec<u8>,
+    mode: u32,
+    oid: Oid,
+    // Whether it is gone on the new side, and which change removed it
+    deleted: Option<usize>,
+    // Pairs taking it so far; a source that stays in place uses itself
+    used: usize,
+}
+
+/// Turn additions in `changes` (sorted by path, as from
+/// [`diff::diff_trees`]) into renames and copies where a source is found.
+/// Identical files pair up first, preferring sources with the same file
+/// name, then those at least `min_score` alike, best matches first. A
+/// deleted source used several times is renamed by the last of them and
+/// copied by the others; its deletion is dropped. `unchanged` lists further
+/// copy sources (mode, ID and path) for finding copies of files the change
+/// did not touch.
+pub fn detect_renames(
+    odb: &ObjectDatabase,
+    changes: Vec<FileChange>,
+    options: &RenameOptions,
+    unchanged: &[(Vec<u8>, u32, Oid)],
+) -> Result<Vec<FileChange>> {
+    let is_file = |mode: u32| mode != MODE_TREE && mode & 0o170000 != 0o160000;
+
+    let mut sources = Vec::new();
+    for (n, change) in changes.iter().enumerate() {
+        let Some((mode, oid)) = change.old.filter(|&(mode, _)| is_file(mode)) else { continue };
+        let deleted = change.new.is_none();
+        if deleted || options.copies {
+            let (deleted, used) = (deleted.then_some(n), usize::from(!deleted));
+            sources.push(Source { path: change.path.clone(), mode, oid, deleted, used });
+        }
+    }
+    if options.copies {
+        for (path, mode, oid) in unchanged.iter().filter(|(_, mode, _)| is_file(*mode)) {
+            sources.push(Source { path: path.clone(), mode: *mode, oid: *oid, deleted: None, used: 1 });
+        }
+    }
+    sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let targets: Vec<usize> = (0..changes.len())
+        .filter(|&n| changes[n].old.is_none() && changes[n].new.is_some_and(|(mode, _)| is_file(mode)))
+        .collect();
+    if sources.is_empty() || targets.is_empty() {
+        return Ok(changes);
+    }
+
+    // Target change index to source index and score
+    let mut pairs: HashMap<usize, (usize, u32)> = HashMap::new();
+
+    // Identical contents first
+    for &target in &targets {
+        let (mode, oid) = changes[target].new.unwrap();
+        let best = sources
+            .iter()
+            .enumerate()
+            // Anything but regular files must keep their mode
+            .filter(|(_, source)| source.oid == oid && (source.mode == mode || (regular(source.mode) && regular(mode))))
+            .filter(|(_, source)| options.copies || source.used == 0)
+            .max_by_key(|(n, source)| {
+                let same_name = same_basename(&source.path, &changes[target].path);
+                let score = usize::from(source.used == 0) + usize::from(same_name);
+                // Among equals, the first source
+                (score, Reverse(*n))
+            })
+            .map(|(n, _)| n);
+        if let Some(source) = best {
+            sources[source].used += 1;
+            pairs.insert(target, (source, MAX_SCORE));
+        }
+    }
+
+    // Then similar regular files, sized up by their chunks of content
+    let unpaired: Vec<usize> = targets
+        .iter()
+        .copied()
+        .filter(|target| !pairs.contains_key(target) && changes[*target].new.is_some_and(|(mode, _)| regular(mode)))
+        .collect();
+    let mut contents: HashMap<Oid, (usize, HashMap<u32, usize>)> = HashMap::new();
+    if !unpaired.is_empty() {
+        let oids = sources.iter().filter(|source| regular(source.mode)).map(|source| source.oid);
+        for oid in oids.chain(unpaired.iter().map(|&target| changes[target].new.unwrap().1)) {
+            if let Entry::Vacant(entry) = contents.entry(oid) {
+                let data = odb.read_raw(&oid)?.1;
+                entry.insert((data.len(), chunk_counts(&data)));
+            }
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for &target in &unpaired {
+        let (target_size, target_chunks) = &contents[&changes[target].new.unwrap().1];
+        let mut found = Vec::new();
+        for (n, source) in sources.iter().enumerate() {
+            if !regular(source.mode) || (source.used > 0 && !options.copies) {
+                continue;
+            }
+            let (source_size, source_chunks) = &contents[&source.oid];
+            let score = similarity(*source_size, source_chunks, *target_size, target_chunks, options.min_score);
+            if score >= options.min_score {
+                let same_name = same_basename(&source.path, &changes[target].path);
+                found.push((score, same_name, target, n));
+            }
+        }
+        found.sort_by_key(|&(score, same_name, _, _)| Reverse((score, same_name)));
+        found.truncate(CANDIDATES_PER_FILE);
+        candidates.extend(found);
+    }
+    candidates.sort_by_key(|&(score, same_name, _, _)| Reverse((score, same_name)));
+
+    // Each source is renamed once; only copies may reuse one
+    for copies in [false, true] {
+        if copies && !options.copies {
+            break;
+        }
+        for &(score, _, target, source) in &candidates {
+            if pairs.contains_key(&target) || (!copies && sources[source].used > 0) {
+                continue;
+            }
+            sources[source].used += 1;
+            pairs.insert(target, (source, score));
+        }
+    }
+
+    // The last pair, in path order, to take a deleted source is its rename
+    let mut remaining: Vec<usize> = sources.iter().map(|source| source.used).collect();
+    let mut dropped = vec![false; changes.len()];
+    let mut changes = changes;
+    for (n, change) in changes.iter_mut().enumerate() {
+        let Some(&(source, score)) = pairs.get(&n) else { continue };
+        let from = &sources[source];
+        remaining[source] -= 1;
+        let copy = from.deleted.is_none() || remaining[source] > 0;
+        if let Some(deleted) = from.deleted {
+            dropped[deleted] = true;
+        }
+        change.old = Some((from.mode, from.oid));
+        change.change = Change::Modified;
+        change.rename = Some(Rename { from: from.path.clone(), score, copy });
+    }
+
+    Ok(changes.into_iter().enumerate().filter(|(n, _)| !dropped[*n]).map(|(_, change)| change).collect())
+}
+
+fn regular(mode: u32) -> bool {
+    mode == MODE_FILE || mode == MODE_EXECUTABLE
+}
+
+fn same_basename(a: &[u8], b: &[u8]) -> bool {
+    let name = |path: &[u8]| path.rsplit(|&c| c == b'/').next().unwrap_or(path).to_vec();
+    name(a) == name(b)
+}
+
+// Bytes of content in each chunk hash: lines, split further every CHUNK_LEN
+// bytes, with the CR of CRLF line ends ignored in text
+fn chunk_counts(data: &[u8]) -> HashMap<u32, usize> {
+    let text = !diff::is_binary(data);
+    let mut counts = HashMap::new();
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0usize);
+    for (i, &c) in data.iter().enumerate() {
+        if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        len += 1;
+        if len < CHUNK_LEN && c != b'\n' {
+            continue;
+        }
+        *counts.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE).or_insert(0) += len;
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    if len > 0 {
+        *counts.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE).or_insert(0) += len;
+    }
+    counts
+}
+
+// How much of the larger file is content the source already had, out of
+// MAX_SCORE; 0 when the sizes alone rule out reaching `min_score`
+fn similarity(
+    source_size: usize,
+    source: &HashMap<u32, usize>,
+    target_size: usize,
+    target: &HashMap<u32, usize>,
+    min_score: u32,
+) -> u32 {
+    let max_size = source_size.max(target_size) as u64;
+    let delta = source_size.abs_diff(target_size) as u64;
+    if target_size == 0 || max_size * u64::from(MAX_SCORE - min_score) < delta * u64::from(MAX_SCORE) {
+        return 0;
+    }
+
+    let copied: usize = target.iter().map(|(hash, &count)| source.get(hash).map_or(0, |&have| have.min(count))).sum();
+    (copied as u64 * u64::from(MAX_SCORE) / max_size) as u32
+}
+
+/// A rename as a diffstat shows it: the part the two paths share written
+/// once, e.g. `src/{old.rs => new.rs}`.
+pub fn stat_name(from: &[u8], to: &[u8]) -> Vec<u8> {
+    // Common leading directories
+    let mut prefix = 0;
+    for (i, (a, b)) in from.iter().zip(to).enumerate() {
+        if a != b {
+            break;
+        }
+        if *a == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // Common trailing part from a '/', allowed to reach back into the
+    // prefix as far as its slash; the end of both paths compares equal
+    let mut suffix = 0;
+    let floor = prefix.saturating_sub(1) as isize;
+    let at = |path: &[u8], i: isize| path.get(i as usize).copied();
+    let (mut i, mut j) = (from.len() as isize, to.len() as isize);
+    while i >= floor && j >= floor && at(from, i) == at(to, j) {
+        if at(from, i) == Some(b'/') {
+            suffix = from.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let from_mid = &from[prefix..from.len().saturating_sub(suffix).max(prefix)];
+    let to_mid = &to[prefix..to.len().saturating_sub(suffix).max(prefix)];
+    let mut name = Vec::new();
+    if prefix + suffix > 0 {
+        name.extend_from_slice(&from[..prefix]);
+        name.push(b'{');
+    }
+    name.extend_from_slice(from_mid);
+    name.extend_from_slice(b" => ");
+    name.extend_from_slice(to_mid);
+    if prefix + suffix > 0 {
+        name.push(b'}');
+        name.extend_from_slice(&from[from.len() - suffix..]);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_thresholds_like_git() {
+        let percent = |n: u32| MAX_SCORE * n / 100;
+        assert_eq!(parse_score("5").unwrap(), percent(50));
+        assert_eq!(parse_score("75").unwrap(), percent(75));
+        assert_eq!(parse_score("05").unwrap(), percent(5));
+        assert_eq!(parse_score("5%").unwrap(), percent(5));
+        assert_eq!(parse_score("1.5%").unwrap(), MAX_SCORE * 15 / 1000);
+        assert_eq!(parse_score("100%").unwrap(), MAX_SCORE);
+        assert_eq!(parse_score("").unwrap(), DEFAULT_SCORE);
+        assert_eq!(parse_score("0").unwrap(), DEFAULT_SCORE);
+        assert!(parse_score("5x").is_err());
+        assert!(parse_score("5%%").is_err());
+    }
+
+    #[test]
+    fn scores_shared_content() {
+        let score = |old: &[u8], new: &[u8], min_score| {
+            similarity(old.len(), &chunk_counts(old), new.len(), &chunk_counts(new), min_score)
+        };
+        let old = b"one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+        assert_eq!(score(old, old, DEFAULT_SCORE), MAX_SCORE);
+
+        // The CRs of CRLF line ends are skipped, so only the size differs;
+        // git rates this 83%
+        let crlf: Vec<u8> = old.iter().flat_map(|&c| if c == b'\n' { vec![b'\r', c] } else { vec![c] }).collect();
+        assert_eq!(score(old, &crlf, DEFAULT_SCORE) * 100 / MAX_SCORE, 83);
+
+        // git diff -M calls this a 66% similar rename
+        let new = b"one\ntwo\nTHREE\nfour\nfive\nsix\nseven\nEIGHT\nnine\nten\neleven\n";
+        assert_eq!(score(old, new, DEFAULT_SCORE) * 100 / MAX_SCORE, 66);
+
+        // Too different in size to reach the threshold at all
+        assert_eq!(score(b"one\n", old, DEFAULT_SCORE), 0);
+        assert_eq!(score(old, b"", DEFAULT_SCORE), 0);
+    }
+}
@@ -0,0 +1,353 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::error::{Result, RitError};
+use crate::odb::ObjectDatabase;
+use crate::refs::{self, Refs};
+
+/// Name of the repository directory inside a work tree. The contents are
+/// the same either way; `.rit` lets rit run next to real git in one tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Git-compatible `.git` directory.
+    #[default]
+    Git,
+    /// Native `.rit` directory.
+    Rit,
+}
+
+impl Layout {
+    /// Layouts in discovery order: rit's own directory wins over `.git`.
+    pub const ALL: [Layout; 2] = [Layout::Rit, Layout::Git];
+
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            Layout::Git => ".git",
+            Layout::Rit => ".rit",
+        }
+    }
+
+    /// Layout implied by a git directory's name; anything but `.rit` is git.
+    fn of(git_dir: &Path) -> Layout {
+        if git_dir.file_name() == Some(Layout::Rit.dir_name().as_ref()) {
+            Layout::Rit
+        } else {
+            Layout::Git
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.dir_name()[1..])
+    }
+}
+
+impl FromStr for Layout {
+    type Err = RitError;
+
+    fn from_str(s: &str) -> Result<Layout> {
+        match s {
+            "git" => Ok(Layout::Git),
+            "rit" => Ok(Layout::Rit),
+            _ => Err(RitError::InvalidArgument(format!("unknown layout '{s}' (expected 'git' or 'rit')"))),
+        }
+    }
+}
+
+/// Options for [`Repository::init`].
+#[derive(Clone, Debug, Default)]
+pub struct InitOptions {
+    /// Make the target directory itself the git directory, with no work tree.
+    pub bare: bool,
+    pub layout: Layout,
+    /// Branch `HEAD` points at; defaults to `main`.
+    pub initial_branch: Option<String>,
+}
+
+const DEFAULT_BRANCH: &str = "main";
+
+const DESCRIPTION: &str = "Unnamed repository; edit this file 'description' to name the repository.\n";
+
+const EXCLUDE: &str = "\
+# rit ls-files --others --exclude-from=.git/info/exclude
+# Lines that start with '#' are comments.
+# For a project mostly in C, the following would be a good set of
+# exclude patterns (uncomment them if you want to use them):
+# *.[oa]
+# *~
+";
+
+/// A git directory, its object database and (unless bare) its work tree.
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    layout: Layout,
+    odb: ObjectDatabase,
+    refs: Refs,
+}
+
+impl Repository {
+    /// Open a repository whose git directory is already known.
+    pub fn open(git_dir: impl Into<PathBuf>, work_tree: Option<PathBuf>) -> Result<Repository> {
+        let git_dir = git_dir.into();
+        if !is_git_dir(&git_dir) {
+            return Err(RitError::NotARepository(git_dir));
+        }
+
+        let objects = match env::var_os("GIT_OBJECT_DIRECTORY") {
+            Some(dir) => PathBuf::from(dir),
+            None => git_dir.join("objects"),
+        };
+
+        Ok(Repository {
+            odb: ObjectDatabase::new(objects),
+            refs: Refs::new(&git_dir),
+            layout: Layout::of(&git_dir),
+            git_dir,
+            work_tree,
+        })
+    }
+
+    /// Find the repository containing `start`.
+    ///
+    /// `GIT_DIR` names the git directory outright, with the current directory
+    /// as the work tree. Otherwise each directory from `start` upwards is
+    /// checked for a `.rit` or `.git` entry, or for being a bare repository
+    /// itself. `GIT_WORK_TREE` overrides the work tree in both cases.
+    pub fn discover(start: &Path) -> Result<Repository> {
+        let start = absolute(start)?;
+        let work_tree_override = env::var_os("GIT_WORK_TREE").map(|dir| start.join(dir));
+
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let work_tree = work_tree_override.or_else(|| Some(start.clone()));
+            return Repository::open(start.join(git_dir), work_tree);
+        }
+
+        for dir in start.ancestors() {
+            for layout in Layout::ALL {
+                if let Some(git_dir) = resolve_dot_git(&dir.join(layout.dir_name()))? {
+                    let work_tree = work_tree_override.or_else(|| Some(dir.to_path_buf()));
+                    return Repository::open(git_dir, work_tree);
+                }
+            }
+            if is_git_dir(dir) {
+                return Repository::open(dir, work_tree_override);
+            }
+        }
+
+        Err(RitError::NotARepository(start))
+    }
+
+    /// Create an empty repository at `path`, or reinitialize the one already
+    /// there without touching existing files. Returns the repository and
+    /// whether it already existed.
+    ///
+    /// The git directory is `<path>/.git` (or `.rit`), `path` itself for a
+    /// bare repository, or `GIT_DIR` when that is set.
+    pub fn init(path: &Path, options: &InitOptions) -> Result<(Repository, bool)> {
+        let path = absolute(path)?;
+        let git_dir = match env::var_os("GIT_DIR") {
+            Some(dir) => path.join(dir),
+            None if options.bare => path.clone(),
+            None => path.join(options.layout.dir_name()),
+        };
+        let existed = is_git_dir(&git_dir);
+
+        let branch = options.initial_branch.as_deref().unwrap_or(DEFAULT_BRANCH);
+        if !refs::check_ref_format(&format!("refs/heads/{branch}")) {
+            return Err(RitError::Fatal(format!("invalid initial branch name: '{branch}'")));
+        }
+
+        fs::create_dir_all(&path)?;
+        for dir in ["hooks", "info", "objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+            fs::create_dir_all(git_dir.join(dir))?;
+        }
+
+        write_if_missing(&git_dir.join("HEAD"), &format!("ref: refs/heads/{branch}\n"))?;
+        write_if_missing(&git_dir.join("description"), DESCRIPTION)?;
+        write_if_missing(&git_dir.join("info/exclude"), EXCLUDE)?;
+
+        let mut config = String::from("[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n");
+        if options.bare {
+            config.push_str("\tbare = true\n");
+        } else {
+            config.push_str("\tbare = false\n\tlogallrefupdates = true\n");
+        }
+        write_if_missing(&git_dir.join("config"), &config)?;
+
+        let work_tree = if options.bare { None } else { Some(path) };
+        Ok((Repository::open(git_dir, work_tree)?, existed))
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        match env::var_os("GIT_INDEX_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => self.git_dir.join("index"),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The work tree, or `None` for a bare repository.
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    /// The work tree, failing for a bare repository.
+    pub fn require_work_tree(&self) -> Result<&Path> {
+        self.work_tree().ok_or_else(|| RitError::Fatal("this operation must be run in a work tree".to_string()))
+    }
+
+    pub fn odb(&self) -> &ObjectDatabase {
+        &self.odb
+    }
+
+    pub fn refs(&self) -> &Refs {
+        &self.refs
+    }
+
+    /// The user's global configuration overlaid with the repository's.
+    pub fn config(&self) -> Result<Config> {
+        Config::for_git_dir(&self.git_dir)
+    }
+}
+
+// A git directory has HEAD and objects/; refs/ is optional so that
+// minimal `.rit` directories are still recognised
+fn is_git_dir(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir()
+}
+
+// `.git`/`.rit` is either the git directory or a "gitdir: <path>" file pointing at it
+fn resolve_dot_git(dot_git: &Path) -> Result<Option<PathBuf>> {
+    if dot_git.is_dir() {
+        return Ok(is_git_dir(dot_git).then(|| dot_git.to_path_buf()));
+    }
+    if !dot_git.is_file() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(dot_git)?;
+    let target = contents
+        .strip_prefix("gitdir:")
+        .map(str::trim)
+        .ok_or_else(|| RitError::InvalidArgument(format!("invalid gitfile format: {}", dot_git.display())))?;
+    Ok(Some(dot_git.parent().unwrap_or(Path::new(".")).join(target)))
+}
+
+// Never clobber files a previous init (or the user) already wrote
+fn write_if_missing(path: &Path, contents: &str) -> Result<()> {
+    if !path.exists() {
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    Ok(if path.is_absolute() { path.to_path_buf() } else { env::current_dir()?.join(path) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovers_from_subdirectories_and_gitfiles() {
+        let root = env::temp_dir().join(format!("rit-discover-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let git_dir = root.join("work/.git");
+        for dir in ["objects", "refs"] {
+            fs::create_dir_all(git_dir.join(dir)).unwrap();
+        }
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::create_dir_all(root.join("work/a/b")).unwrap();
+
+        let repo = Repository::discover(&root.join("work/a/b")).unwrap();
+        assert_eq!(repo.git_dir(), git_dir);
+        assert_eq!(repo.work_tree(), Some(root.join("work").as_path()));
+
+        // A linked checkout points at the git directory through a gitfile
+        fs::create_dir_all(root.join("linked/sub")).unwrap();
+        fs::write(root.join("linked/.git"), "gitdir: ../work/.git\n").unwrap();
+        let repo = Repository::discover(&root.join("linked/sub")).unwrap();
+        assert_eq!(repo.git_dir(), root.join("linked/../work/.git"));
+        assert_eq!(repo.work_tree(), Some(root.join("linked").as_path()));
+
+        // Inside the git directory itself there is no work tree
+        let repo = Repository::discover(&git_dir.join("objects")).unwrap();
+        assert_eq!(repo.git_dir(), git_dir);
+        assert!(repo.require_work_tree().is_err());
+
+        fs::write(root.join("linked/.git"), "not a gitfile\n").unwrap();
+        assert!(Repository::discover(&root.join("linked")).is_err());
+        assert!(matches!(Repository::discover(&root), Err(RitError::NotARepository(_))));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rit_directories_win_over_git() {
+        let root = env::temp_dir().join(format!("rit-layout-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+
+        let options = |layout| InitOptions { layout, ..InitOptions::default() };
+        let (repo, _) = Repository::init(&root, &options(Layout::Git)).unwrap();
+        assert_eq!(repo.layout(), Layout::Git);
+        assert_eq!(Repository::discover(&root.join("sub")).unwrap().git_dir(), root.join(".git"));
+
+        Repository::init(&root, &options(Layout::Rit)).unwrap();
+        let repo = Repository::discover(&root.join("sub")).unwrap();
+        assert_eq!(repo.git_dir(), root.join(".rit"));
+        assert_eq!(repo.layout(), Layout::Rit);
+
+        assert_eq!("rit".parse::<Layout>().unwrap(), Layout::Rit);
+        assert_eq!(Layout::Git.to_string(), "git");
+        assert!("svn".parse::<Layout>().is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn init_is_idempotent() {
+        let root = env::temp_dir().join(format!("rit-init-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        let options = InitOptions { initial_branch: Some("trunk".to_string()), ..InitOptions::default() };
+        let (repo, existed) = Repository::init(&root.join("work"), &options).unwrap();
+        assert!(!existed);
+        assert_eq!(fs::read_to_string(repo.git_dir().join("HEAD")).unwrap(), "ref: refs/heads/trunk\n");
+        assert!(repo.git_dir().join("refs/tags").is_dir());
+
+        // Reinitializing keeps what is already there
+        fs::write(repo.git_dir().join("description"), "mine\n").unwrap();
+        let (repo, existed) = Repository::init(&root.join("work"), &InitOptions::default()).unwrap();
+        assert!(existed);
+        assert_eq!(fs::read_to_string(repo.git_dir().join("HEAD")).unwrap(), "ref: refs/heads/trunk\n");
+        assert_eq!(fs::read_to_string(repo.git_dir().join("description")).unwrap(), "mine\n");
+
+        let bare = InitOptions { bare: true, ..InitOptions::default() };
+        let (repo, _) = Repository::init(&root.join("bare.git"), &bare).unwrap();
+        assert_eq!(repo.git_dir(), root.join("bare.git"));
+        assert_eq!(repo.work_tree(), None);
+        assert!(fs::read_to_string(root.join("bare.git/config")).unwrap().contains("bare = true"));
+
+        for branch in ["", "a..b", "-x/", "with space"] {
+            let options = InitOptions { initial_branch: Some(branch.to_string()), ..InitOptions::default() };
+            assert!(Repository::init(&root.join("bad"), &options).is_err(), "{branch:?}");
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
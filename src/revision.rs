@@ -0,0 +1,324 @@
+//! Revision expressions as accepted by `git rev-parse`:
+//!
+//! - `<oid>`, `<refname>`, `@` (HEAD), a unique prefix of 4+ hex digits
+//! - `<refname>@{<n>}` and `@{<n>}`: the n-th prior value from the reflog
+//! - `@{-<n>}`: the n-th branch checked out before the current one
+//! - `<rev>~<n>`, `<rev>^<n>`: n-th first-parent ancestor, n-th parent
+//! - `<rev>^{<type>}`, `<rev>^{}`: peel tags (and commits) to a type
+//! - `<rev>:<path>`: the object at `path` in the rev's tree
+//! - `:<path>`, `:<stage>:<path>`: an object in the index
+
+use crate::error::{Result, RitError};
+use crate::index::Index;
+use crate::object::{Object, ObjectKind};
+use crate::odb::MIN_ABBREV;
+use crate::oid::Oid;
+use crate::refs::HEAD;
+use crate::repository::Repository;
+
+/// Resolve a revision expression to an object ID.
+pub fn resolve(repo: &Repository, spec: &str) -> Result<Oid> {
+    let bad = || RitError::BadRevision(spec.to_string());
+
+    if let Some(rest) = spec.strip_prefix(':') {
+        return resolve_index_path(repo, rest).map_err(|e| not_found_as(e, spec));
+    }
+
+    let (rev, path) = split_path(spec);
+    let (base, ops) = match rev.find(['~', '^']) {
+        Some(pos) => rev.split_at(pos),
+        None => (rev, ""),
+    };
+
+    let mut oid = resolve_base(repo, base)?.ok_or_else(bad)?;
+    apply_ops(repo, &mut oid, ops).map_err(|e| not_found_as(e, spec))?;
+
+    if let Some(path) = path {
+        oid = lookup_path(repo, &oid, path).map_err(|e| not_found_as(e, spec))?;
+    }
+    Ok(oid)
+}
+
+/// Resolve a revision and peel it to an object of `kind`, so that e.g. a
+/// commit can be given where a tree is expected.
+pub fn resolve_as(repo: &Repository, spec: &str, kind: ObjectKind) -> Result<Oid> {
+    let oid = resolve(repo, spec)?;
+    peel(repo, &oid, Some(kind))
+}
+
+// A missing object or malformed operator partway through an expression
+// means the expression names nothing
+fn not_found_as(e: RitError, spec: &str) -> RitError {
+    match e {
+        RitError::ObjectNotFound(_) | RitError::BadRevision(_) => RitError::BadRevision(spec.to_string()),
+        other => other,
+    }
+}
+
+// Split "<rev>:<path>" at the first colon outside of braces
+fn split_path(spec: &str) -> (&str, Option<&str>) {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => return (&spec[..i], Some(&spec[i + 1..])),
+            _ => {}
+        }
+    }
+    (spec, None)
+}
+
+fn resolve_base(repo: &Repository, base: &str) -> Result<Option<Oid>> {
+    if let Some(at) = base.find("@{") {
+        let selector = base[at + 2..].strip_suffix('}').ok_or_else(|| RitError::BadRevision(base.to_string()))?;
+        return resolve_reflog(repo, &base[..at], selector);
+    }
+
+    if base == "@" {
+        return repo.refs().resolve(HEAD);
+    }
+    if base.len() == Oid::HEX_LEN {
+        if let Ok(oid) = Oid::from_hex(base) {
+            return Ok(Some(oid));
+        }
+    }
+
+    if let Some(name) = repo.refs().dwim(base)? {
+        return repo.refs().resolve(&name);
+    }
+
+    // Anything else that looks like hex is an abbreviated object ID
+    if base.len() >= MIN_ABBREV && base.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return match repo.odb().resolve_prefix(base) {
+            Ok(oid) => Ok(Some(oid)),
+            Err(RitError::ObjectNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+    Ok(None)
+}
+
+// "<name>@{<n>}" or "@{-<n>}"
+fn resolve_reflog(repo: &Repository, name: &str, selector: &str) -> Result<Option<Oid>> {
+    let bad = || RitError::BadRevision(format!("{name}@{{{selector}}}"));
+
+    if let Some(n) = selector.strip_prefix('-') {
+        if !name.is_empty() {
+            return Err(bad());
+        }
+        let n: usize = n.parse().map_err(|_| bad())?;
+        let branch = previous_branch(repo, n)?.ok_or_else(bad)?;
+        return match repo.refs().dwim(&branch)? {
+            Some(full) => repo.refs().resolve(&full),
+            None => Ok(None),
+        };
+    }
+
+    let n: usize = selector.parse().map_err(|_| bad())?;
+    let full = match name {
+        "" | "@" => HEAD.to_string(),
+        _ => repo.refs().dwim(name)?.ok_or_else(bad)?,
+    };
+
+    // Entry 0 is the most recent update
+    let log = repo.refs().read_reflog(&full)?;
+    Ok(log.iter().rev().nth(n).map(|entry| entry.new))
+}
+
+// The n-th branch switched away from, according to HEAD's reflog
+fn previous_branch(repo: &Repository, n: usize) -> Result<Option<String>> {
+    let log = repo.refs().read_reflog(HEAD)?;
+    Ok(log
+        .iter()
+        .rev()
+        .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+        .filter_map(|moved| moved.split_once(" to ").map(|(from, _)| from.to_string()))
+        .nth(n.saturating_sub(1)))
+}
+
+// Apply a chain of "~<n>", "^<n>" and "^{<type>}" operators
+fn apply_ops(repo: &Repository, oid: &mut Oid, mut ops: &str) -> Result<()> {
+    while let Some(op) = ops.chars().next() {
+        ops = &ops[op.len_utf8()..];
+        if op != '~' && op != '^' {
+            return Err(RitError::BadRevision(format!("{op}{ops}")));
+        }
+
+        if op == '^' && ops.starts_with('{') {
+            let end = ops.find('}').ok_or_else(|| RitError::BadRevision(ops.to_string()))?;
+            let kind = match &ops[1..end] {
+                "" => None,
+                name => Some(name.parse().map_err(|_| RitError::BadRevision(format!("^{{{name}}}")))?),
+            };
+            *oid = peel(repo, oid, kind)?;
+            ops = &ops[end + 1..];
+            continue;
+        }
+
+        let digits = ops.bytes().take_while(u8::is_ascii_digit).count();
+        let n: usize = match digits {
+            0 => 1,
+            _ => ops[..digits].parse().map_err(|_| RitError::BadRevision(ops.to_string()))?,
+        };
+        ops = &ops[digits..];
+
+        let mut commit = peel(repo, oid, Some(ObjectKind::Commit))?;
+        if op == '~' {
+            for _ in 0..n {
+                commit = *repo.odb().read_commit(&commit)?.parents.first().ok_or_else(no_such_parent)?;
+            }
+        } else if n > 0 {
+            commit = *repo.odb().read_commit(&commit)?.parents.get(n - 1).ok_or_else(no_such_parent)?;
+        }
+        *oid = commit;
+    }
+    Ok(())
+}
+
+fn no_such_parent() -> RitError {
+    RitError::ObjectNotFound("parent".to_string())
+}
+
+/// Dereference tags until reaching an object of `kind`, also going from a
+/// commit to its tree. With `kind` of `None`, peel tags to whatever they
+/// point at.
+pub fn peel(repo: &Repository, oid: &Oid, kind: Option<ObjectKind>) -> Result<Oid> {
+    let mut oid = *oid;
+    loop {
+        let object = repo.odb().read_object(&oid)?;
+        if Some(object.kind()) == kind {
+            return Ok(oid);
+        }
+        match object {
+            Object::Tag(tag) => oid = tag.object,
+            Object::Commit(commit) if kind == Some(ObjectKind::Tree) => oid = commit.tree,
+            _ if kind.is_none() => return Ok(oid),
+            other => {
+                return Err(RitError::WrongObjectType {
+                    oid,
+                    expected: kind.unwrap(),
+                    actual: other.kind(),
+                })
+            }
+        }
+    }
+}
+
+// Walk "dir/sub/file" down from a tree-ish
+fn lookup_path(repo: &Repository, oid: &Oid, path: &str) -> Result<Oid> {
+    let mut current = peel(repo, oid, Some(ObjectKind::Tree))?;
+
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        let tree = repo.odb().read_tree(&current)?;
+        current = tree
+            .entries
+            .iter()
+            .find(|e| e.name == part.as_bytes())
+            .map(|e| e.oid)
+            .ok_or_else(|| RitError::InvalidArgument(format!("path '{path}' does not exist")))?;
+    }
+    Ok(current)
+}
+
+// ":<path>" or ":<stage>:<path>"
+fn resolve_index_path(repo: &Repository, rest: &str) -> Result<Oid> {
+    let (stage, path) = match rest.as_bytes() {
+        [s @ b'0'..=b'3', b':', ..] => ((s - b'0') as u16, &rest[2..]),
+        _ => (0, rest),
+    };
+
+    let index = Index::load(&repo.index_path())?;
+    index
+        .entries()
+        .iter()
+        .find(|e| e.path == path.as_bytes() && e.stage() == stage)
+        .map(|e| e.oid)
+        .ok_or_else(|| RitError::InvalidArgument(format!("path '{path}' is not in the index")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use super::*;
+    use crate::repository::InitOptions;
+
+    const SIGNATURE: &str = "A U Thor <author@example.com> 1112911993 +0000";
+
+    fn commit(repo: &Repository, tree: &Oid, parents: &[Oid], message: &str) -> Oid {
+        let mut text = format!("tree {tree}\n");
+        for parent in parents {
+            text.push_str(&format!("parent {parent}\n"));
+        }
+        text.push_str(&format!("author {SIGNATURE}\ncommitter {SIGNATURE}\n\n{message}\n"));
+        repo.odb().write_raw(ObjectKind::Commit, text.as_bytes()).unwrap()
+    }
+
+    fn tree(repo: &Repository, mode: &str, name: &str, oid: &Oid) -> Oid {
+        let mut data = format!("{mode} {name}\0").into_bytes();
+        data.extend_from_slice(oid.as_bytes());
+        repo.odb().write_raw(ObjectKind::Tree, &data).unwrap()
+    }
+
+    #[test]
+    fn resolves_revision_expressions() {
+        let dir = std::env::temp_dir().join(format!("rit-revision-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        // one - two - merge on main, with side branching off one
+        let blob = repo.odb().write_raw(ObjectKind::Blob, b"content\n").unwrap();
+        let sub = tree(&repo, "100644", "file", &blob);
+        let root = tree(&repo, "40000", "dir", &sub);
+        let one = commit(&repo, &root, &[], "one");
+        let two = commit(&repo, &root, &[one], "two");
+        let side = commit(&repo, &root, &[one], "side");
+        let merge = commit(&repo, &root, &[two, side], "merge");
+        for oid in [one, two, merge] {
+            repo.refs().update(HEAD, &oid, None, "commit").unwrap();
+        }
+        repo.refs().update("refs/heads/side", &side, None, "branch").unwrap();
+        let tag_text = format!("object {merge}\ntype commit\ntag v1\ntagger {SIGNATURE}\n\nrelease\n");
+        let tag = repo.odb().write_raw(ObjectKind::Tag, tag_text.as_bytes()).unwrap();
+        repo.refs().update("refs/tags/v1", &tag, None, "tag").unwrap();
+        let checkout = format!("{merge} {merge} {SIGNATURE}\tcheckout: moving from side to main\n");
+        let mut log = OpenOptions::new().append(true).open(dir.join(".git/logs/HEAD")).unwrap();
+        log.write_all(checkout.as_bytes()).unwrap();
+
+        let cases = [
+            ("HEAD", merge),
+            ("@", merge),
+            ("main", merge),
+            (&merge.to_hex(), merge),
+            ("HEAD~", two),
+            ("HEAD~2", one),
+            ("HEAD^2", side),
+            ("HEAD^2~1", one),
+            ("HEAD^0", merge),
+            ("v1", tag),
+            ("tags/v1", tag),
+            ("v1^{}", merge),
+            ("v1^{commit}~1", two),
+            ("v1^{tree}", root),
+            ("HEAD:dir/file", blob),
+            ("HEAD^{tree}:dir", sub),
+            ("main@{1}", two),
+            ("main@{2}", one),
+            ("@{-1}", side),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(resolve(&repo, spec).unwrap(), expected, "{spec}");
+        }
+        assert_eq!(resolve_as(&repo, "v1", ObjectKind::Tree).unwrap(), root);
+
+        for spec in ["nope", "HEAD~3", "HEAD^3", "main@{9}", "@{-2}"] {
+            assert!(matches!(resolve(&repo, spec), Err(RitError::BadRevision(_))), "{spec}");
+        }
+        assert!(resolve(&repo, "HEAD:missing").is_err());
+        assert!(matches!(resolve(&repo, "v1^{blob}"), Err(RitError::WrongObjectType { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
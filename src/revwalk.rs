@@ -0,0 +1,482 @@
+//! Commit graph traversal for `log`.
+
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::error::Result;
+use crate::object::{Commit, Object, TreeEntry, MODE_GITLINK};
+use crate::oid::Oid;
+use crate::repository::Repository;
+
+/// Order commits are produced in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Newest committer date first, as commits are discovered.
+    #[default]
+    Date,
+    /// No parent before all of its children, otherwise newest first.
+    DateTopo,
+    /// No parent before all of its children, keeping lines of history
+    /// together.
+    Topo,
+}
+
+/// A commit selected by the walk. `parents` are the parents as shown: with
+/// path limiting, hidden commits are skipped over to their nearest shown
+/// ancestors.
+pub struct LogEntry {
+    pub oid: Oid,
+    pub commit: Commit,
+    pub parents: Vec<Oid>,
+}
+
+pub struct RevWalk<'r> {
+    repo: &'r Repository,
+    order: Order,
+    paths: Vec<Vec<u8>>,
+    limit: Option<usize>,
+}
+
+// What the walk knows about one commit
+struct Node {
+    commit: Commit,
+    /// Parents still followed after history simplification.
+    parents: Vec<Oid>,
+    shown: bool,
+}
+
+impl<'r> RevWalk<'r> {
+    pub fn new(repo: &'r Repository) -> RevWalk<'r> {
+        RevWalk { repo, order: Order::Date, paths: Vec::new(), limit: None }
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only show commits that change one of `paths` (repository paths,
+    /// `/`-separated). Merges that leave the paths as one parent had them
+    /// are followed down that parent only, as git does by default.
+    pub fn paths(mut self, paths: Vec<Vec<u8>>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Stop after this many commits.
+    pub fn limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Walk history from `tips`.
+    pub fn run(&self, tips: &[Oid]) -> Result<Vec<LogEntry>> {
+        match self.order {
+            Order::Date => self.walk_by_date(tips),
+            Order::DateTopo | Order::Topo => self.walk_sorted(tips),
+        }
+    }
+
+    // Pop the newest commit until enough have been shown; no need to read
+    // further back than that
+    fn walk_by_date(&self, tips: &[Oid]) -> Result<Vec<LogEntry>> {
+        let mut queue = BinaryHeap::new();
+        let mut commits = HashMap::new();
+        let mut tree_cache = HashMap::new();
+        let mut entries = Vec::new();
+        // Ties go to the commit discovered first
+        let mut seq = u64::MAX;
+
+        for tip in tips {
+            self.enqueue(tip, &mut queue, &mut commits, &mut seq)?;
+        }
+
+        while let Some((_, _, oid)) = queue.pop() {
+            if self.limit.is_some_and(|limit| entries.len() >= limit) {
+                break;
+            }
+
+            let commit = commits.get_mut(&oid).and_then(Option::take).expect("queued commits are read");
+            let node = self.simplify(commit, &mut tree_cache)?;
+            for parent in &node.parents {
+                self.enqueue(parent, &mut queue, &mut commits, &mut seq)?;
+            }
+
+            if node.shown {
+                entries.push(LogEntry { oid, parents: node.parents, commit: node.commit });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn enqueue(
+        &self,
+        oid: &Oid,
+        queue: &mut BinaryHeap<(i64, u64, Oid)>,
+        commits: &mut HashMap<Oid, Option<Commit>>,
+        seq: &mut u64,
+    ) -> Result<()> {
+        if !commits.contains_key(oid) {
+            let commit = self.repo.odb().read_commit(oid)?;
+            queue.push((commit.committer.time, *seq, *oid));
+            commits.insert(*oid, Some(commit));
+            *seq -= 1;
+        }
+        Ok(())
+    }
+
+    // Read everything reachable, then sort so that children come first
+    fn walk_sorted(&self, tips: &[Oid]) -> Result<Vec<LogEntry>> {
+        let mut nodes: HashMap<Oid, Node> = HashMap::new();
+        let mut tree_cache = HashMap::new();
+        let mut pending: Vec<Oid> = tips.to_vec();
+
+        while let Some(oid) = pending.pop() {
+            if nodes.contains_key(&oid) {
+                continue;
+            }
+            let node = self.simplify(self.repo.odb().read_commit(&oid)?, &mut tree_cache)?;
+            pending.extend(node.parents.iter().filter(|p| !nodes.contains_key(p)));
+            nodes.insert(oid, node);
+        }
+
+        let mut tips: Vec<Oid> = tips.to_vec();
+        let mut seen = HashSet::new();
+        tips.retain(|tip| seen.insert(*tip));
+
+        // Parents as shown: hidden commits are replaced by their nearest
+        // shown ancestors
+        let all_parents: HashMap<Oid, Vec<Oid>> = nodes.iter().map(|(oid, node)| (*oid, node.parents.clone())).collect();
+        let full_order = topo_sort(&tips, &all_parents, |oid| nodes[oid].commit.committer.time, Order::DateTopo);
+
+        let mut effective: HashMap<Oid, Vec<Oid>> = HashMap::new();
+        let mut shown_parents: HashMap<Oid, Vec<Oid>> = HashMap::new();
+        for oid in full_order.iter().rev() {
+            let node = &nodes[oid];
+            let mut rewritten = Vec::new();
+            for parent in &node.parents {
+                for ancestor in &effective[parent] {
+                    if !rewritten.contains(ancestor) {
+                        rewritten.push(*ancestor);
+                    }
+                }
+            }
+            if node.shown {
+                effective.insert(*oid, vec![*oid]);
+                shown_parents.insert(*oid, rewritten);
+            } else {
+                effective.insert(*oid, rewritten);
+            }
+        }
+
+        let mut shown_tips = Vec::new();
+        for tip in &tips {
+            for oid in &effective[tip] {
+                if !shown_tips.contains(oid) {
+                    shown_tips.push(*oid);
+                }
+            }
+        }
+
+        let mut order = topo_sort(&shown_tips, &shown_parents, |oid| nodes[oid].commit.committer.time, self.order);
+        if let Some(limit) = self.limit {
+            order.truncate(limit);
+        }
+
+        Ok(order
+            .into_iter()
+            .map(|oid| {
+                let parents = shown_parents.remove(&oid).unwrap_or_default();
+                let commit = nodes.remove(&oid).unwrap().commit;
+                LogEntry { oid, commit, parents }
+            })
+            .collect())
+    }
+
+    // Decide whether a commit is shown and which parents to follow
+    fn simplify(&self, commit: Commit, cache: &mut HashMap<Oid, Vec<Option<(u32, Oid)>>>) -> Result<Node> {
+        if self.paths.is_empty() {
+            return Ok(Node { parents: commit.parents.clone(), commit, shown: true });
+        }
+
+        let ours = self.path_entries(&commit.tree, cache)?;
+        if commit.parents.is_empty() {
+            let shown = ours.iter().any(Option::is_some);
+            return Ok(Node { parents: Vec::new(), commit, shown });
+        }
+
+        for parent in &commit.parents {
+            let parent_tree = self.repo.odb().read_commit(parent)?.tree;
+            if self.path_entries(&parent_tree, cache)? == ours {
+                // Same as this parent: the change came from its side alone
+                return Ok(Node { parents: vec![*parent], commit, shown: false });
+            }
+        }
+
+        Ok(Node { parents: commit.parents.clone(), commit, shown: true })
+    }
+
+    // Mode and ID of each limiting path in `tree`, compared to decide
+    // whether two trees agree on the paths
+    fn path_entries(&self, tree: &Oid, cache: &mut HashMap<Oid, Vec<Option<(u32, Oid)>>>) -> Result<Vec<Option<(u32, Oid)>>> {
+        if let Some(entries) = cache.get(tree) {
+            return Ok(entries.clone());
+        }
+
+        let mut entries = Vec::with_capacity(self.paths.len());
+        for path in &self.paths {
+            entries.push(lookup(self.repo, tree, path)?.map(|e| (e.mode, e.oid)));
+        }
+        cache.insert(*tree, entries.clone());
+        Ok(entries)
+    }
+}
+
+/// Every object reachable from `include` but not from `exclude`, each with
+/// the path it was reached by: tags and commits first, newest first, then
+/// the trees and blobs of each commit in turn. Submodule commits are not
+/// followed.
+pub fn list_objects(repo: &Repository, include: &[Oid], exclude: &[Oid]) -> Result<Vec<(Oid, Vec<u8>)>> {
+    let mut excluded = HashSet::new();
+    let mut ignored = Vec::new();
+    collect(repo, exclude, &mut excluded, &mut ignored)?;
+
+    let mut objects = Vec::new();
+    collect(repo, include, &mut excluded, &mut objects)?;
+    Ok(objects)
+}
+
+// Add objects reachable from `tips` that are not yet in `seen`
+fn collect(repo: &Repository, tips: &[Oid], seen: &mut HashSet<Oid>, out: &mut Vec<(Oid, Vec<u8>)>) -> Result<()> {
+    let odb = repo.odb();
+    let mut commits = Vec::new();
+    let mut trees = Vec::new();
+
+    for tip in tips {
+        // Peel tags, keeping the tag objects themselves
+        let mut oid = *tip;
+        loop {
+            if !seen.insert(oid) {
+                break;
+            }
+            match odb.read_object(&oid)? {
+                Object::Tag(tag) => {
+                    out.push((oid, Vec::new()));
+                    oid = tag.object;
+                }
+                Object::Commit(_) => {
+                    seen.remove(&oid);
+                    commits.push(oid);
+                    break;
+                }
+                Object::Tree(_) => {
+                    trees.push((oid, Vec::new()));
+                    break;
+                }
+                Object::Blob(_) => {
+                    out.push((oid, Vec::new()));
+                    break;
+                }
+            }
+        }
+    }
+
+    // Newest commit first, stopping at anything already seen
+    let mut queue = BinaryHeap::new();
+    let mut pending = HashMap::new();
+    let mut walked = Vec::new();
+    for oid in commits {
+        if seen.insert(oid) {
+            let commit = odb.read_commit(&oid)?;
+            queue.push((commit.committer.time, oid));
+            pending.insert(oid, commit);
+        }
+    }
+    while let Some((_, oid)) = queue.pop() {
+        let commit = pending.remove(&oid).expect("queued commits are read");
+        for parent in &commit.parents {
+            if seen.insert(*parent) {
+                let parent_commit = odb.read_commit(parent)?;
+                queue.push((parent_commit.committer.time, *parent));
+                pending.insert(*parent, parent_commit);
+            }
+        }
+        out.push((oid, Vec::new()));
+        walked.push(commit.tree);
+    }
+
+    for tree in walked {
+        if seen.insert(tree) {
+            collect_tree(repo, &tree, Vec::new(), seen, out)?;
+        }
+    }
+    for (tree, path) in trees {
+        collect_tree(repo, &tree, path, seen, out)?;
+    }
+    Ok(())
+}
+
+// Add a tree and everything below it not yet in `seen`
+fn collect_tree(
+    repo: &Repository,
+    tree: &Oid,
+    path: Vec<u8>,
+    seen: &mut HashSet<Oid>,
+    out: &mut Vec<(Oid, Vec<u8>)>,
+) -> Result<()> {
+    let entries = repo.odb().read_tree(tree)?.entries;
+    out.push((*tree, path.clone()));
+
+    for entry in entries {
+        if entry.mode == MODE_GITLINK || !seen.insert(entry.oid) {
+            continue;
+        }
+        let mut child = path.clone();
+        if !child.is_empty() {
+            child.push(b'/');
+        }
+        child.extend_from_slice(&entry.name);
+
+        if entry.is_tree() {
+            collect_tree(repo, &entry.oid, child, seen, out)?;
+        } else {
+            out.push((entry.oid, child));
+        }
+    }
+    Ok(())
+}
+
+/// The tree entry at `path` below `tree`, if it exists. Identical subtrees
+/// are never expanded since only the entries along `path` are read.
+pub fn lookup(repo: &Repository, tree: &Oid, path: &[u8]) -> Result<Option<TreeEntry>> {
+    let mut current = *tree;
+    let mut parts = path.split(|&b| b == b'/').filter(|p| !p.is_empty()).peekable();
+
+    while let Some(part) = parts.next() {
+        let tree = repo.odb().read_tree(&current)?;
+        let Some(entry) = tree.entries.into_iter().find(|e| e.name == part) else {
+            return Ok(None);
+        };
+        if parts.peek().is_none() {
+            return Ok(Some(entry));
+        }
+        if !entry.is_tree() {
+            return Ok(None);
+        }
+        current = entry.oid;
+    }
+
+    // The empty path names the root tree itself
+    Ok(Some(TreeEntry { mode: crate::object::MODE_TREE, name: Vec::new(), oid: *tree }))
+}
+
+// Kahn's algorithm over `parents`, emitting a commit only after all of its
+// children. `DateTopo` picks the newest ready commit; `Topo` continues with
+// the parents of the commit just emitted so branches stay together.
+fn topo_sort(tips: &[Oid], parents: &HashMap<Oid, Vec<Oid>>, time: impl Fn(&Oid) -> i64, order: Order) -> Vec<Oid> {
+    let mut children: HashMap<Oid, usize> = HashMap::new();
+    for list in parents.values() {
+        for parent in list {
+            *children.entry(*parent).or_default() += 1;
+        }
+    }
+
+    let mut result = Vec::with_capacity(parents.len());
+    let ready_tips = tips.iter().filter(|tip| !children.contains_key(tip)).copied();
+
+    if order == Order::Topo {
+        // Stack: a parent is popped right after its last child, the
+        // merged-in side of a merge first, as git does
+        let mut tips: Vec<Oid> = ready_tips.collect();
+        tips.sort_by_key(|oid| time(oid));
+        let mut stack = tips;
+        while let Some(oid) = stack.pop() {
+            result.push(oid);
+            for parent in &parents[&oid] {
+                let count = children.get_mut(parent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    stack.push(*parent);
+                }
+            }
+        }
+    } else {
+        let mut seq = 0u64;
+        let mut heap: BinaryHeap<(i64, u64, Oid)> = BinaryHeap::new();
+        for tip in ready_tips {
+            heap.push((time(&tip), u64::MAX - seq, tip));
+            seq += 1;
+        }
+        while let Some((_, _, oid)) = heap.pop() {
+            result.push(oid);
+            for parent in &parents[&oid] {
+                let count = children.get_mut(parent).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    heap.push((time(parent), u64::MAX - seq, *parent));
+                    seq += 1;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::object::ObjectKind;
+    use crate::repository::InitOptions;
+
+    #[test]
+    fn orders_and_limits_history() {
+        let dir = std::env::temp_dir().join(format!("rit-revwalk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+
+        // x changes in A, C and E; the merge M keeps E's version
+        let tree_of = |content: &str| {
+            let blob = repo.odb().write_raw(ObjectKind::Blob, content.as_bytes()).unwrap();
+            let mut data = b"100644 x\0".to_vec();
+            data.extend_from_slice(blob.as_bytes());
+            repo.odb().write_raw(ObjectKind::Tree, &data).unwrap()
+        };
+        let trees = [tree_of("0"), tree_of("1"), tree_of("2")];
+        let history = [
+            ("A", "", 0, 1000000000),
+            ("B", "A", 0, 1000002000),
+            ("C", "A", 1, 1000003000),
+            ("D", "C", 1, 1000003500),
+            ("E", "B", 2, 1000004000),
+            ("M", "E D", 2, 1000005000),
+            ("F", "M", 2, 1000006000),
+        ];
+        let mut oids = HashMap::new();
+        for (name, parents, tree, time) in history {
+            let mut text = format!("tree {}\n", trees[tree]);
+            for parent in parents.split_whitespace() {
+                text.push_str(&format!("parent {}\n", oids[parent]));
+            }
+            let sig = format!("A U Thor <author@example.com> {time} +0000");
+            text.push_str(&format!("author {sig}\ncommitter {sig}\n\n{name}\n"));
+            oids.insert(name, repo.odb().write_raw(ObjectKind::Commit, text.as_bytes()).unwrap());
+        }
+        let names: HashMap<Oid, &str> = oids.iter().map(|(name, oid)| (*oid, *name)).collect();
+        let walk = |walk: RevWalk| -> String {
+            let entries = walk.run(&[oids["F"]]).unwrap();
+            entries.iter().map(|entry| names[&entry.oid]).collect::<Vec<_>>().join(" ")
+        };
+
+        // As printed by git log, --date-order, --topo-order, -n 3 and -- x
+        assert_eq!(walk(RevWalk::new(&repo)), "F M E D C B A");
+        assert_eq!(walk(RevWalk::new(&repo).order(Order::DateTopo)), "F M E D C B A");
+        assert_eq!(walk(RevWalk::new(&repo).order(Order::Topo)), "F M D C E B A");
+        assert_eq!(walk(RevWalk::new(&repo).limit(Some(3))), "F M E");
+        assert_eq!(walk(RevWalk::new(&repo).paths(vec![b"x".to_vec()])), "E A");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,458 @@
+//! Work tree status: what is staged (HEAD against the index), what is not
+//! (the index against the work tree) and which files are untracked.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, Metadata};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use crate::error::{Result, RitError};
+use crate::ignore::Ignore;
+use crate::index::{Index, IndexEntry};
+use crate::object::{ObjectKind, MODE_GITLINK, MODE_SYMLINK};
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+use crate::refs::HEAD;
+use crate::repository::{Layout, Repository};
+use crate::worktree;
+
+/// How one side of a path changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// A file became a symlink or the other way round.
+    TypeChanged,
+}
+
+impl Change {
+    /// The letter `status --short` shows.
+    pub fn letter(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+        }
+    }
+}
+
+/// A tracked path that differs somewhere between HEAD, the index and the
+/// work tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStatus {
+    pub path: Vec<u8>,
+    /// HEAD against the index.
+    pub staged: Option<Change>,
+    /// The index against the work tree.
+    pub unstaged: Option<Change>,
+    /// Mode and object ID in HEAD, if the path is there.
+    pub head: Option<(u32, Oid)>,
+    /// Mode and object ID in the index, if the path is staged.
+    pub index: Option<(u32, Oid)>,
+    /// Mode in the work tree, if the file exists and is in the index.
+    pub worktree_mode: Option<u32>,
+    /// For a merge conflict, the base, ours and theirs entries (stages 1-3);
+    /// `staged` and `unstaged` are then `None`.
+    pub conflict: Option<[Option<(u32, Oid)>; 3]>,
+}
+
+impl FileStatus {
+    /// Git's two-letter code for a conflict, e.g. `UU` for both modified.
+    pub fn conflict_code(&self) -> Option<&'static str> {
+        let [base, ours, theirs] = self.conflict?;
+        Some(match (base.is_some(), ours.is_some(), theirs.is_some()) {
+            (true, false, false) => "DD",
+            (false, true, false) => "AU",
+            (true, false, true) => "DU",
+            (false, false, true) => "UA",
+            (true, true, false) => "UD",
+            (false, true, true) => "AA",
+            _ => "UU",
+        })
+    }
+}
+
+/// Which untracked files to report.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UntrackedMode {
+    No,
+    /// Untracked directories as a whole, not the files in them.
+    #[default]
+    Normal,
+    All,
+}
+
+impl FromStr for UntrackedMode {
+    type Err = RitError;
+
+    fn from_str(s: &str) -> Result<UntrackedMode> {
+        match s {
+            "no" => Ok(UntrackedMode::No),
+            "normal" => Ok(UntrackedMode::Normal),
+            "all" => Ok(UntrackedMode::All),
+            _ => Err(RitError::InvalidArgument(format!("invalid untracked files mode '{s}'"))),
+        }
+    }
+}
+
+/// The state of a repository's work tree.
+#[derive(Clone, Debug)]
+pub struct Status {
+    /// Branch HEAD points at, as a full ref name; `None` when detached.
+    pub branch: Option<String>,
+    /// Commit HEAD resolves to; `None` on an unborn branch.
+    pub head: Option<Oid>,
+    /// Changed tracked paths, sorted.
+    pub files: Vec<FileStatus>,
+    /// Untracked paths, sorted; directories reported as a whole end in `/`.
+    pub untracked: Vec<Vec<u8>>,
+}
+
+/// Compare HEAD, the index and the work tree. Files whose stat data still
+/// matches the index are taken as unchanged without being read; the index is
+/// refreshed with the stat data of files that turn out to be unchanged, so
+/// later runs can skip them too.
+pub fn status(repo: &Repository, untracked: UntrackedMode) -> Result<Status> {
+    let work_tree = repo.require_work_tree()?;
+    let odb = repo.odb();
+
+    let head_ref = repo.refs().resolve_name(HEAD)?;
+    let branch = head_ref.starts_with("refs/heads/").then_some(head_ref);
+    let head = repo.refs().resolve(HEAD)?;
+    let head_files = match head {
+        Some(commit) => tree_files(odb, &odb.read_commit(&commit)?.tree)?,
+        None => BTreeMap::new(),
+    };
+
+    let index_path = repo.index_path();
+    let mut index = Index::load(&index_path)?;
+    let index_mtime = index_mtime(&index_path)?;
+
+    let mut files: BTreeMap<Vec<u8>, FileStatus> = BTreeMap::new();
+    let entry_for = |path: &[u8]| FileStatus {
+        path: path.to_vec(),
+        staged: None,
+        unstaged: None,
+        head: head_files.get(path).copied(),
+        index: None,
+        worktree_mode: None,
+        conflict: None,
+    };
+
+    let mut refreshed = Vec::new();
+    for entry in index.entries() {
+        let status = files.entry(entry.path.clone()).or_insert_with(|| entry_for(&entry.path));
+        if entry.stage() != 0 {
+            let conflict = status.conflict.get_or_insert([None; 3]);
+            conflict[entry.stage() as usize - 1] = Some((entry.mode, entry.oid));
+            status.worktree_mode = fs::symlink_metadata(worktree::work_path(work_tree, &entry.path))
+                .ok()
+                .filter(|meta| !meta.is_dir())
+                .map(|meta| worktree::file_mode(&meta));
+            continue;
+        }
+
+        let file = worktree::work_path(work_tree, &entry.path);
+        let meta = fs::symlink_metadata(&file).ok().filter(|meta| !meta.is_dir());
+        status.worktree_mode = meta.as_ref().map(worktree::file_mode);
+
+        // A path added with `add -N` has nothing staged; the file itself
+        // is the unstaged addition
+        if entry.is_intent_to_add() {
+            status.unstaged = Some(if meta.is_some() { Change::Added } else { Change::Deleted });
+            continue;
+        }
+
+        status.index = Some((entry.mode, entry.oid));
+        status.staged = compare(status.head, status.index);
+        status.unstaged = match &meta {
+            _ if entry.is_skip_worktree() => None,
+            None => Some(Change::Deleted),
+            // Submodules are not looked into
+            Some(_) if entry.mode == MODE_GITLINK => None,
+            Some(meta) => {
+                if stat_clean(entry, meta, index_mtime) {
+                    None
+                } else {
+                    let oid = Oid::hash(ObjectKind::Blob, &worktree::read_entry(&file, meta)?);
+                    let change = compare(status.index, Some((worktree::file_mode(meta), oid)));
+                    if change.is_none() && !entry.stat_matches(meta) {
+                        let fresh = IndexEntry::from_metadata(entry.path.clone(), oid, entry.mode, meta);
+                        let flags = entry.flags;
+                        refreshed.push(IndexEntry { flags, extended_flags: entry.extended_flags, ..fresh });
+                    }
+                    change
+                }
+            }
+        };
+    }
+
+    // Deleted from the index but still in HEAD
+    for (path, &(mode, oid)) in &head_files {
+        if !files.contains_key(path) {
+            let mut status = entry_for(path);
+            status.staged = Some(Change::Deleted);
+            status.head = Some((mode, oid));
+            files.insert(path.clone(), status);
+        }
+    }
+
+    if !refreshed.is_empty() {
+        for entry in refreshed {
+            index.add(entry);
+        }
+        // Refreshing is only an optimisation; another process may hold the lock
+        match index.write(&index_path) {
+            Err(RitError::Locked(_)) => {}
+            other => other?,
+        }
+    }
+
+    let untracked = match untracked {
+        UntrackedMode::No => Vec::new(),
+        mode => {
+            let tracked = Tracked::new(&index);
+            let mut ignore = Ignore::for_repo(repo)?;
+            let mut found = Vec::new();
+            find_untracked(work_tree, b"", &tracked, &mut ignore, mode, &mut found)?;
+            found.sort();
+            found
+        }
+    };
+
+    let files = files
+        .into_values()
+        .filter(|status| status.staged.is_some() || status.unstaged.is_some() || status.conflict.is_some())
+        .collect();
+    Ok(Status { branch, head, files, untracked })
+}
+
+/// Every blob and gitlink in a tree, by full path, with its mode.
+pub fn tree_files(odb: &ObjectDatabase, tree: &Oid) -> Result<BTreeMap<Vec<u8>, (u32, Oid)>> {
+    let mut files = BTreeMap::new();
+    collect_tree(odb, tree, b"", &mut files)?;
+    Ok(files)
+}
+
+fn collect_tree(odb: &ObjectDatabase, tree: &Oid, prefix: &[u8], files: &mut BTreeMap<Vec<u8>, (u32, Oid)>) -> Result<()> {
+    for entry in odb.read_tree(tree)?.entries {
+        let path = worktree::join_path(prefix, &entry.name);
+        if entry.is_tree() {
+            collect_tree(odb, &entry.oid, &path, files)?;
+        } else {
+            files.insert(path, (entry.mode, entry.oid));
+        }
+    }
+    Ok(())
+}
+
+/// Modification time of the index file in seconds, 0 if there is none.
+pub(crate) fn index_mtime(index_path: &Path) -> Result<u64> {
+    match fs::metadata(index_path) {
+        Ok(meta) => Ok(meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Whether stat data alone shows the work tree file unchanged since `entry`
+/// was staged. Files modified in the same second the index was written may
+/// have changed without their stat data showing it, so those never are.
+pub(crate) fn stat_clean(entry: &IndexEntry, meta: &Metadata, index_mtime: u64) -> bool {
+    let racy = entry.mtime as u64 >= index_mtime;
+    entry.stat_matches(meta) && !racy && worktree::file_mode(meta) == entry.mode
+}
+
+/// How `new` differs from `old`, each a mode and object ID if present.
+pub fn compare(old: Option<(u32, Oid)>, new: Option<(u32, Oid)>) -> Option<Change> {
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Change::Added),
+        (Some(_), None) => Some(Change::Deleted),
+        (Some(old), Some(new)) if old == new => None,
+        (Some((old_mode, _)), Some((new_mode, _))) if (old_mode == MODE_SYMLINK) != (new_mode == MODE_SYMLINK) => {
+            Some(Change::TypeChanged)
+        }
+        _ => Some(Change::Modified),
+    }
+}
+
+// Tracked paths and every directory above one
+struct Tracked<'a> {
+    files: HashSet<&'a [u8]>,
+    dirs: HashSet<&'a [u8]>,
+}
+
+impl<'a> Tracked<'a> {
+    fn new(index: &'a Index) -> Tracked<'a> {
+        let mut tracked = Tracked { files: HashSet::new(), dirs: HashSet::new() };
+        for entry in index.entries() {
+            tracked.files.insert(&entry.path);
+            for (i, _) in entry.path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+                tracked.dirs.insert(&entry.path[..i]);
+            }
+        }
+        tracked
+    }
+}
+
+fn find_untracked(
+    dir: &Path,
+    prefix: &[u8],
+    tracked: &Tracked,
+    ignore: &mut Ignore,
+    mode: UntrackedMode,
+    found: &mut Vec<Vec<u8>>,
+) -> Result<()> {
+    for (path, relative, meta) in read_dir(dir, prefix)? {
+        if tracked.files.contains(&relative[..]) || ignore.is_ignored(&relative, meta.is_dir())? {
+            continue;
+        }
+
+        if !meta.is_dir() {
+            if meta.is_file() || meta.file_type().is_symlink() {
+                found.push(relative);
+            }
+        } else if mode == UntrackedMode::Normal && !tracked.dirs.contains(&relative[..]) {
+            // A directory with nothing tracked in it is reported as a whole,
+            // unless it holds nothing but ignored files
+            if has_files(&path, &relative, ignore)? {
+                found.push([&relative[..], b"/"].concat());
+            }
+        } else {
+            find_untracked(&path, &relative, tracked, ignore, mode, found)?;
+        }
+    }
+    Ok(())
+}
+
+// Whether anything below `dir` is neither ignored nor an empty directory
+fn has_files(dir: &Path, prefix: &[u8], ignore: &mut Ignore) -> Result<bool> {
+    for (path, relative, meta) in read_dir(dir, prefix)? {
+        if ignore.is_ignored(&relative, meta.is_dir())? {
+            continue;
+        }
+        if !meta.is_dir() || has_files(&path, &relative, ignore)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Entries of a work tree directory with their repository paths, except
+// repository directories
+fn read_dir(dir: &Path, prefix: &[u8]) -> Result<Vec<(PathBuf, Vec<u8>, Metadata)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if Layout::ALL.iter().any(|layout| name == layout.dir_name()) {
+            continue;
+        }
+        let meta = fs::symlink_metadata(entry.path())?;
+        entries.push((entry.path(), worktree::join_path(prefix, name.as_bytes()), meta));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{INTENT_TO_ADD, SKIP_WORKTREE};
+    use crate::repository::InitOptions;
+
+    #[test]
+    fn reports_staged_unstaged_and_untracked_files() {
+        let dir = std::env::temp_dir().join(format!("rit-status-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+        for name in ["kept", "edited", "deleted", "unstaged"] {
+            fs::write(dir.join(name), format!("{name}\n")).unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let mut index = Index::default();
+        worktree::add_to_index(&repo, &mut index, None, &dir).unwrap();
+        let tree = index.write_tree(repo.odb()).unwrap();
+        let commit = format!("tree {tree}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nfirst\n");
+        let commit = repo.odb().write_raw(ObjectKind::Commit, commit.as_bytes()).unwrap();
+        repo.refs().update(HEAD, &commit, None, "commit (initial): first").unwrap();
+
+        // Staged: a new file and a removal; unstaged: an edit and a deletion
+        fs::write(dir.join("added"), "added\n").unwrap();
+        worktree::add_to_index(&repo, &mut index, None, &dir.join("added")).unwrap();
+        index.remove(b"unstaged");
+        fs::write(dir.join("edited"), "edited again\n").unwrap();
+        fs::remove_file(dir.join("deleted")).unwrap();
+        index.write(&repo.index_path()).unwrap();
+
+        fs::create_dir_all(dir.join("new/sub")).unwrap();
+        fs::write(dir.join("new/sub/file"), "").unwrap();
+        fs::write(dir.join("build.log"), "").unwrap();
+
+        let normal = status(&repo, UntrackedMode::Normal).unwrap();
+        assert_eq!(normal.branch.as_deref(), Some("refs/heads/main"));
+        assert_eq!(normal.head, Some(commit));
+        let short: Vec<(&[u8], Option<char>, Option<char>)> = normal
+            .files
+            .iter()
+            .map(|file| (file.path.as_slice(), file.staged.map(Change::letter), file.unstaged.map(Change::letter)))
+            .collect();
+        assert_eq!(
+            short,
+            [
+                (&b"added"[..], Some('A'), None),
+                (b"deleted", None, Some('D')),
+                (b"edited", None, Some('M')),
+                (b"unstaged", Some('D'), None),
+            ]
+        );
+        assert_eq!(normal.untracked, [&b"new/"[..], b"unstaged"]);
+
+        let all = status(&repo, UntrackedMode::All).unwrap();
+        assert_eq!(all.untracked, [&b"new/sub/file"[..], b"unstaged"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_intent_to_add_and_skip_worktree_entries() {
+        let dir = std::env::temp_dir().join(format!("rit-status-flags-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (repo, _) = Repository::init(&dir, &InitOptions::default()).unwrap();
+        for name in ["gone", "new", "sparse"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let mut index = Index::default();
+        worktree::add_to_index(&repo, &mut index, None, &dir).unwrap();
+        for entry in index.entries().to_vec() {
+            let extended_flags = if entry.path == b"sparse" { SKIP_WORKTREE } else { INTENT_TO_ADD };
+            index.add(IndexEntry { extended_flags, ..entry });
+        }
+        index.write(&repo.index_path()).unwrap();
+        fs::remove_file(dir.join("gone")).unwrap();
+        fs::remove_file(dir.join("sparse")).unwrap();
+
+        let status = status(&repo, UntrackedMode::Normal).unwrap();
+        let short: Vec<(&[u8], Option<char>, Option<char>)> = status
+            .files
+            .iter()
+            .map(|file| (file.path.as_slice(), file.staged.map(Change::letter), file.unstaged.map(Change::letter)))
+            .collect();
+        assert_eq!(
+            short,
+            [(&b"gone"[..], None, Some('D')), (b"new", None, Some('A')), (b"sparse", Some('A'), None)]
+        );
+        assert!(status.untracked.is_empty());
+
+        // Refreshing the index on the way must not drop the flags
+        let index = Index::load(&repo.index_path()).unwrap();
+        let flags: Vec<u16> = index.entries().iter().map(|entry| entry.extended_flags).collect();
+        assert_eq!(flags, [INTENT_TO_ADD, INTENT_TO_ADD, SKIP_WORKTREE]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
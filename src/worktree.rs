@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::io;
+use std::ffi::OsStr;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Result, RitError};
+use crate::ignore::Ignore;
+use crate::index::{Index, IndexEntry};
+use crate::object::{Object, ObjectKind, Tree, TreeBuilder, TreeEntry, MODE_EXECUTABLE, MODE_FILE, MODE_SYMLINK, MODE_TREE};
+use crate::odb::ObjectDatabase;
+use crate::oid::Oid;
+use crate::repository::{Layout, Repository};
+
+/// Store the contents of `path` as a blob.
+pub fn hash_file(odb: &ObjectDatabase, path: &Path) -> Result<Oid> {
+    let data = read_file(path)?;
+    odb.write_raw(ObjectKind::Blob, &data)
+}
+
+/// Snapshot a directory recursively into tree objects, returning the root tree.
+/// Executable files and symlinks get their own modes; symlinked directories
+/// are stored as links, not followed. Paths `ignore` excludes are left out,
+/// and so are directories with nothing left in them, which git cannot record.
+pub fn write_tree(odb: &ObjectDatabase, dir: &Path, ignore: &mut Ignore) -> Result<Oid> {
+    let prefix = repo_path(ignore.root(), dir)?;
+    let tree = build_tree(odb, dir, &prefix, ignore)?;
+    odb.write_object(&Object::Tree(tree))
+}
+
+// `prefix` is the path of `dir` relative to the root of `ignore`
+fn build_tree(odb: &ObjectDatabase, dir: &Path, prefix: &[u8], ignore: &mut Ignore) -> Result<Tree> {
+    let mut tree = TreeBuilder::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+
+        // Never snapshot a repository directory of either layout
+        if Layout::ALL.iter().any(|layout| name == layout.dir_name()) {
+            continue;
+        }
+
+        // A symlink is recorded as a link even when it points at a directory
+        let meta = fs::symlink_metadata(&path)?;
+        let relative = join_path(prefix, name.as_bytes());
+        if ignore.is_ignored(&relative, meta.is_dir())? {
+            continue;
+        }
+
+        if meta.is_dir() {
+            // recursive write_tree for subdir
+            let subtree = build_tree(odb, &path, &relative, ignore)?;
+            if !subtree.entries.is_empty() {
+                let oid = odb.write_object(&Object::Tree(subtree))?;
+                tree.insert(TreeEntry { mode: MODE_TREE, name: name.as_bytes().to_vec(), oid })?;
+            }
+        } else if meta.is_file() || meta.file_type().is_symlink() {
+            // hash file contents (or the link target) like `git hash-object -w`
+            let oid = hash_entry(odb, &path, &meta)?;
+            tree.insert(TreeEntry { mode: file_mode(&meta), name: name.as_bytes().to_vec(), oid })?;
+        }
+    }
+
+    Ok(tree.build())
+}
+
+/// `name` inside the directory `dir`, both `/`-separated repository paths.
+pub fn join_path(dir: &[u8], name: &[u8]) -> Vec<u8> {
+    if dir.is_empty() {
+        name.to_vec()
+    } else {
+        [dir, b"/", name].concat()
+    }
+}
+
+/// Read a file, naming it in the error on failure.
+pub fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| {
+        io::Error::new(e.kind(), format!("could not read '{}': {}", path.display(), e)).into()
+    })
+}
+
+/// Mode a work tree file is recorded with, from its `symlink_metadata`.
+pub fn file_mode(meta: &Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        MODE_SYMLINK
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+/// Store a work tree entry as a blob: the file contents, or the link target
+/// for a symlink.
+pub fn hash_entry(odb: &ObjectDatabase, path: &Path, meta: &Metadata) -> Result<Oid> {
+    odb.write_raw(ObjectKind::Blob, &read_entry(path, meta)?)
+}
+
+/// What a work tree entry is stored as: the file contents, or the link
+/// target for a symlink.
+pub fn read_entry(path: &Path, meta: &Metadata) -> Result<Vec<u8>> {
+    if meta.file_type().is_symlink() {
+        Ok(fs::read_link(path)?.into_os_string().into_vec())
+    } else {
+        read_file(path)
+    }
+}
+
+/// `path` (absolute or relative to the current directory) as a
+/// `/`-separated path relative to the work tree. The work tree itself is
+/// the empty path.
+pub fn repo_path(work_tree: &Path, path: &Path) -> Result<Vec<u8>> {
+    let absolute = absolute_path(path)?;
+    let relative = absolute.strip_prefix(work_tree).map_err(|_| {
+        RitError::InvalidArgument(format!("'{}' is outside repository at '{}'", path.display(), work_tree.display()))
+    })?;
+
+    let parts: Vec<&[u8]> = relative.iter().map(|part| part.as_bytes()).collect();
+    Ok(parts.join(&b'/'))
+}
+
+/// `path` made absolute against the current directory, with `.` and `..`
+/// resolved lexically.
+pub fn absolute_path(path: &Path) -> Result<PathBuf> {
+    Ok(normalize(&std::env::current_dir()?.join(path)))
+}
+
+/// Inverse of [`repo_path`]: the file system path of a repository path.
+pub fn work_path(work_tree: &Path, path: &[u8]) -> PathBuf {
+    work_tree.join(OsStr::from_bytes(path))
+}
+
+// Resolve `.` and `..` lexically, since the path may not exist
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Stage `path` (a file, symlink or directory) into `index`, skipping
+/// untracked paths that `ignore` excludes (pass `None` to add them anyway).
+/// Tracked files under `path` are refreshed even if ignored, and those that
+/// no longer exist are removed from the index.
+pub fn add_to_index(repo: &Repository, index: &mut Index, mut ignore: Option<&mut Ignore>, path: &Path) -> Result<()> {
+    let work_tree = repo.require_work_tree()?;
+    let prefix = repo_path(work_tree, path)?;
+    let full_path = work_path(work_tree, &prefix);
+    let tracked = index.paths_under(&prefix);
+
+    let mut staged = HashSet::new();
+    let mut found = !tracked.is_empty();
+    if let Ok(meta) = fs::symlink_metadata(&full_path) {
+        found = true;
+        if let Some(ignore) = ignore.as_deref_mut() {
+            // Naming an ignored path outright is a mistake unless it is tracked
+            if tracked.is_empty() && !prefix.is_empty() && ignore.is_ignored(&prefix, meta.is_dir())? {
+                return Err(RitError::InvalidArgument(format!(
+                    "The following paths are ignored by one of your ignore files:\n{}\n\
+                     hint: Use -f if you really want to add them.",
+                    path.display()
+                )));
+            }
+        }
+
+        let files = if meta.is_dir() { walk_files(&full_path, ignore)? } else { vec![(full_path, meta)] };
+        for (file, meta) in files {
+            staged.insert(stage_file(repo.odb(), index, work_tree, &file, &meta)?);
+        }
+    }
+
+    for tracked in tracked.into_iter().filter(|path| !staged.contains(path)) {
+        let file = work_path(work_tree, &tracked);
+        match fs::symlink_metadata(&file) {
+            Ok(meta) if !meta.is_dir() => {
+                stage_file(repo.odb(), index, work_tree, &file, &meta)?;
+            }
+            Ok(_) => {}
+            Err(_) => {
+                index.remove(&tracked);
+            }
+        }
+    }
+
+    if !found {
+        return Err(RitError::InvalidArgument(format!("pathspec '{}' did not match any files", path.display())));
+    }
+    Ok(())
+}
+
+// Returns the repository path the file was staged at
+fn stage_file(odb: &ObjectDatabase, index: &mut Index, work_tree: &Path, file: &Path, meta: &Metadata) -> Result<Vec<u8>> {
+    let oid = hash_entry(odb, file, meta)?;
+    let path = repo_path(work_tree, file)?;
+    index.add(IndexEntry::from_metadata(path.clone(), oid, file_mode(meta), meta));
+    Ok(path)
+}
+
+/// Every file and symlink below `dir`, with its `symlink_metadata`, skipping
+/// repository directories and never following symlinked directories. With
+/// `ignore`, paths it excludes are skipped too; `dir` must then be inside
+/// its root.
+pub fn walk_files(dir: &Path, mut ignore: Option<&mut Ignore>) -> Result<Vec<(PathBuf, Metadata)>> {
+    let prefix = match ignore.as_deref() {
+        Some(ignore) => repo_path(ignore.root(), dir)?,
+        None => Vec::new(),
+    };
+    let mut files = Vec::new();
+    walk(dir, &prefix, &mut ignore, &mut files)?;
+    Ok(files)
+}
+
+fn walk(dir: &Path, prefix: &[u8], ignore: &mut Option<&mut Ignore>, files: &mut Vec<(PathBuf, Metadata)>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if Layout::ALL.iter().any(|layout| name == layout.dir_name()) {
+            continue;
+        }
+
+        let meta = fs::symlink_metadata(entry.path())?;
+        let relative = join_path(prefix, name.as_bytes());
+        if let Some(ignore) = ignore.as_deref_mut() {
+            if ignore.is_ignored(&relative, meta.is_dir())? {
+                continue;
+            }
+        }
+
+        if meta.is_dir() {
+            walk(&entry.path(), &relative, ignore, files)?;
+        } else if meta.is_file() || meta.file_type().is_symlink() {
+            files.push((entry.path(), meta));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn write_tree_records_modes_like_git() {
+        let root = std::env::temp_dir().join(format!("rit-worktree-modes-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let work = root.join("work");
+        fs::create_dir_all(work.join("sub")).unwrap();
+        fs::create_dir_all(work.join(".rit/objects")).unwrap();
+        fs::write(work.join("file.txt"), "plain\n").unwrap();
+        fs::write(work.join("exec.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(work.join("exec.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("file.txt", work.join("link")).unwrap();
+        symlink("sub", work.join("linkdir")).unwrap();
+        fs::write(work.join("sub/inner.txt"), "inner\n").unwrap();
+
+        let odb = ObjectDatabase::new(root.join("objects"));
+        let mut ignore = Ignore::new(&work, None, &Default::default()).unwrap();
+        let oid = write_tree(&odb, &work, &mut ignore).unwrap();
+        // `git write-tree` of the same files
+        assert_eq!(oid.to_hex(), "df472beb34286f85bac00669bd85059dcc9a44bb");
+
+        let tree = odb.read_tree(&oid).unwrap();
+        let modes: Vec<(&[u8], u32)> = tree.entries.iter().map(|e| (e.name.as_slice(), e.mode)).collect();
+        assert_eq!(
+            modes,
+            [
+                (&b"exec.sh"[..], MODE_EXECUTABLE),
+                (b"file.txt", MODE_FILE),
+                (b"link", MODE_SYMLINK),
+                (b"linkdir", MODE_SYMLINK),
+                (b"sub", MODE_TREE),
+            ]
+        );
+        assert_eq!(odb.read_raw(&tree.entries[2].oid).unwrap().1, b"file.txt");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}